@@ -3,7 +3,7 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use clap::ValueEnum;
-use sqllogictest::{AsyncDB, DBOutput, DefaultColumnType};
+use sqllogictest::{connect_with_retry, AsyncDB, DBOutput, DefaultColumnType};
 use sqllogictest_engines::external::ExternalDriver;
 use sqllogictest_engines::mysql::{MySql, MySqlConfig};
 use sqllogictest_engines::postgres::{PostgresConfig, PostgresExtended, PostgresSimple};
@@ -11,7 +11,7 @@ use tokio::process::Command;
 
 use super::{DBConfig, Result};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ValueEnum)]
 pub enum EngineType {
     Mysql,
     Postgres,
@@ -24,14 +24,17 @@ pub enum EngineConfig {
     MySql,
     Postgres,
     PostgresExtended,
-    External(String),
+    External {
+        command_template: String,
+        capture_stderr: bool,
+    },
 }
 
 pub(crate) enum Engines {
     MySql(MySql),
     Postgres(PostgresSimple),
     PostgresExtended(PostgresExtended),
-    External(ExternalDriver),
+    External(Box<ExternalDriver>),
 }
 
 impl From<&DBConfig> for MySqlConfig {
@@ -65,10 +68,34 @@ impl From<&DBConfig> for PostgresConfig {
     }
 }
 
+/// Connects to the engine, retrying transient errors if `config.connect_retry` is set. Then, an
+/// attempt hanging past the deadline of the retries fails as timed out.
 pub(crate) async fn connect(
     engine: &EngineConfig,
     config: &DBConfig,
 ) -> Result<Engines, EnginesError> {
+    match &config.connect_retry {
+        Some(retry) => {
+            let mut attempt = || async {
+                tokio::time::timeout(retry.timeout, connect_once(engine, config))
+                    .await
+                    .map_err(|_| {
+                        EnginesError(
+                            std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                format!("connecting timed out after {:?}", retry.timeout),
+                            )
+                            .into(),
+                        )
+                    })?
+            };
+            connect_with_retry(&mut attempt, retry).await
+        }
+        None => connect_once(engine, config).await,
+    }
+}
+
+async fn connect_once(engine: &EngineConfig, config: &DBConfig) -> Result<Engines, EnginesError> {
     Ok(match engine {
         EngineConfig::MySql => Engines::MySql(
             MySql::connect(config.into())
@@ -85,9 +112,12 @@ pub(crate) async fn connect(
                 .await
                 .map_err(|e| EnginesError(e.into()))?,
         ),
-        EngineConfig::External(cmd_tmpl) => {
+        EngineConfig::External {
+            command_template,
+            capture_stderr,
+        } => {
             let (host, port) = config.random_addr();
-            let cmd_str = cmd_tmpl
+            let cmd_str = command_template
                 .replace("{db}", &config.db)
                 .replace("{host}", host)
                 .replace("{port}", &port.to_string())
@@ -95,11 +125,12 @@ pub(crate) async fn connect(
                 .replace("{pass}", &config.pass);
             let mut cmd = Command::new("bash");
             cmd.args(["-c", &cmd_str]);
-            Engines::External(
-                ExternalDriver::connect(cmd)
-                    .await
-                    .map_err(|e| EnginesError(e.into()))?,
-            )
+            let driver = if *capture_stderr {
+                ExternalDriver::connect_capturing_stderr(cmd).await
+            } else {
+                ExternalDriver::connect(cmd).await
+            };
+            Engines::External(Box::new(driver.map_err(|e| EnginesError(e.into()))?))
         }
     })
 }
@@ -147,6 +178,50 @@ impl AsyncDB for Engines {
         dispatch_engines!(self, e, { e.engine_name() })
     }
 
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        // Errors of both postgres engines are `tokio_postgres::Error`.
+        error
+            .0
+            .downcast_ref::<<PostgresSimple as AsyncDB>::Error>()
+            .and_then(PostgresSimple::error_sqlstate)
+    }
+
+    fn supports_savepoints(&self) -> bool {
+        dispatch_engines!(self, e, { e.supports_savepoints() })
+    }
+
+    async fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+        dispatch_engines!(self, e, {
+            e.savepoint(name)
+                .await
+                .map_err(|e| EnginesError(anyhow::Error::from(e)))
+        })
+    }
+
+    async fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+        dispatch_engines!(self, e, {
+            e.rollback_to(name)
+                .await
+                .map_err(|e| EnginesError(anyhow::Error::from(e)))
+        })
+    }
+
+    async fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+        dispatch_engines!(self, e, {
+            e.release(name)
+                .await
+                .map_err(|e| EnginesError(anyhow::Error::from(e)))
+        })
+    }
+
+    async fn ping(&mut self) -> Result<(), Self::Error> {
+        dispatch_engines!(self, e, {
+            e.ping()
+                .await
+                .map_err(|e| EnginesError(anyhow::Error::from(e)))
+        })
+    }
+
     async fn sleep(dur: Duration) {
         tokio::time::sleep(dur).await
     }
@@ -0,0 +1,121 @@
+//! The `--tap` report in the [Test Anything Protocol](https://testanything.org/tap-version-13-specification.html).
+
+use std::fmt::Write;
+
+use crate::{RunResult, RunStatus};
+
+/// Formats the results of the test files as a TAP version 13 report. The failure message of each
+/// failed file, including the expected failures, is included as a YAML diagnostic block.
+pub(crate) fn report(results: &[RunResult]) -> String {
+    let mut out = String::new();
+    writeln!(out, "TAP version 13").unwrap();
+    writeln!(out, "1..{}", results.len()).unwrap();
+    for (i, result) in results.iter().enumerate() {
+        let n = i + 1;
+        // `#` starts a directive in TAP, and it's hard to escape, so we avoid it in names.
+        let name = result.file.replace('#', "_");
+        match &result.status {
+            RunStatus::Ok => writeln!(out, "ok {n} - {name}").unwrap(),
+            RunStatus::Skipped => writeln!(out, "ok {n} - {name} # SKIP not run").unwrap(),
+            // A failing TODO test is an expected failure in TAP.
+            RunStatus::ExpectedFailure { reason, message } => {
+                let reason = reason.replace('#', "_");
+                writeln!(out, "not ok {n} - {name} # TODO {reason}").unwrap();
+                write_diagnostic(&mut out, None, message);
+            }
+            RunStatus::Failed(message)
+            | RunStatus::ParseError(message)
+            | RunStatus::Empty(message)
+            | RunStatus::UnexpectedPass(message) => {
+                writeln!(out, "not ok {n} - {name}").unwrap();
+                let severity = match result.status {
+                    RunStatus::ParseError(_) => Some("parse error"),
+                    RunStatus::Empty(_) => Some("empty test file"),
+                    RunStatus::UnexpectedPass(_) => Some("unexpected pass"),
+                    _ => None,
+                };
+                write_diagnostic(&mut out, severity, message);
+            }
+        }
+    }
+    out
+}
+
+/// Writes the YAML diagnostic block of a failed file.
+fn write_diagnostic(out: &mut String, severity: Option<&str>, message: &str) {
+    writeln!(out, "  ---").unwrap();
+    if let Some(severity) = severity {
+        writeln!(out, "  severity: {severity}").unwrap();
+    }
+    writeln!(out, "  message: |-").unwrap();
+    for line in console::strip_ansi_codes(message).lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            writeln!(out).unwrap();
+        } else {
+            writeln!(out, "    {line}").unwrap();
+        }
+    }
+    writeln!(out, "  ...").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let results = [
+            RunResult {
+                file: "a.slt".to_string(),
+                status: RunStatus::Ok,
+                duration: Duration::from_millis(10),
+            },
+            RunResult {
+                file: "dir/b#1.slt".to_string(),
+                status: RunStatus::Failed(
+                    "query result mismatch:\n[SQL] select 1\n\n- 2\n+ 1".to_string(),
+                ),
+                duration: Duration::from_millis(0),
+            },
+            RunResult {
+                file: "c.slt".to_string(),
+                status: RunStatus::Skipped,
+                duration: Duration::from_millis(0),
+            },
+            RunResult {
+                file: "d.slt".to_string(),
+                status: RunStatus::ExpectedFailure {
+                    reason: "see #12".to_string(),
+                    message: "not supported".to_string(),
+                },
+                duration: Duration::from_millis(0),
+            },
+        ];
+        assert_eq!(
+            report(&results),
+            "\
+TAP version 13
+1..4
+ok 1 - a.slt
+not ok 2 - dir/b_1.slt
+  ---
+  message: |-
+    query result mismatch:
+    [SQL] select 1
+
+    - 2
+    + 1
+  ...
+ok 3 - c.slt # SKIP not run
+not ok 4 - d.slt # TODO see _12
+  ---
+  message: |-
+    not supported
+  ...
+"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! The `--watch` mode: re-run test files when they, or files included by them, change.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use console::style;
+use itertools::Itertools;
+use notify::{RecursiveMode, Watcher};
+use sqllogictest::{DefaultColumnType, Injected, MakeConnection, ParseCache, Record, Runner};
+
+use crate::engines::{self, EngineConfig, Engines};
+use crate::{add_labels, run_test_file, DBConfig};
+
+/// Events arriving within this period after the first one are handled together, since editors
+/// usually touch a file several times when saving it.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches `files` and the files they include, and re-runs the affected test files on change.
+/// Never returns unless the watcher fails. A change in a `--fixture` re-runs all test files.
+///
+/// Each test file keeps its runner, and thus its connections, across re-runs. The state of the
+/// runner, e.g., set by `control` records, is restored before each re-run. The runner is
+/// discarded after a failure so that the next run starts with fresh connections.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn watch(
+    files: Vec<PathBuf>,
+    engine: &EngineConfig,
+    config: &DBConfig,
+    labels: &[String],
+    fixtures: &[PathBuf],
+    dump_failures: Option<&Path>,
+    print_skip_reasons: bool,
+    fail_empty: bool,
+) -> Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // The receiver is only dropped when we stop watching.
+        let _ = tx.send(event);
+    })
+    .context("failed to create file watcher")?;
+
+    let mut cache = ParseCache::new();
+    let mut runners = HashMap::new();
+    let mut watched_dirs = HashSet::new();
+    let mut deps = dependencies(&files, fixtures, &mut cache);
+    watch_dirs(&mut watcher, &deps, &mut watched_dirs)?;
+
+    eprintln!(
+        "\n{} {} files for changes...",
+        style("[WATCHING]").blue().bold(),
+        deps.len()
+    );
+
+    while let Some(event) = rx.recv().await {
+        let mut changed = HashSet::new();
+        collect_changes(event?, &mut changed);
+        tokio::time::sleep(DEBOUNCE).await;
+        while let Ok(event) = rx.try_recv() {
+            collect_changes(event?, &mut changed);
+        }
+
+        let changed: BTreeSet<&PathBuf> = changed
+            .iter()
+            .filter_map(|path| deps.get_key_value(path).map(|(path, _)| path))
+            .collect();
+        let affected: BTreeSet<&PathBuf> = changed.iter().flat_map(|path| &deps[*path]).collect();
+        if affected.is_empty() {
+            continue;
+        }
+
+        let mut failed = vec![];
+        for file in &affected {
+            let (runner, state) = runners.entry((*file).clone()).or_insert_with(|| {
+                let runner = new_runner(engine, config, labels);
+                let state = runner.snapshot_state();
+                (runner, state)
+            });
+            runner.restore_state(state.clone());
+            if let Err(e) = run_test_file(
+                &mut std::io::stdout(),
+                runner,
+                file,
+                fixtures,
+                dump_failures,
+                print_skip_reasons,
+                fail_empty,
+            )
+            .await
+            {
+                println!("{}\n\n{:?}\n", style("[FAILED]").red().bold(), e);
+                runners.remove(*file);
+                failed.push(file.to_string_lossy());
+            }
+        }
+
+        eprintln!(
+            "{} {} changed, re-ran {} files: {} passed, {} failed{}",
+            style(format!("[{}]", Local::now().format("%H:%M:%S"))).blue(),
+            changed.iter().map(|p| display_path(p)).join(", "),
+            affected.len(),
+            affected.len() - failed.len(),
+            failed.len(),
+            if failed.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", failed.join(", "))
+            }
+        );
+
+        // Includes may have been added or removed.
+        deps = dependencies(&files, fixtures, &mut cache);
+        watch_dirs(&mut watcher, &deps, &mut watched_dirs)?;
+    }
+
+    Ok(())
+}
+
+fn new_runner<'a>(
+    engine: &'a EngineConfig,
+    config: &'a DBConfig,
+    labels: &[String],
+) -> Runner<Engines, impl MakeConnection<Conn = Engines> + 'a> {
+    let mut runner = Runner::new(move || engines::connect(engine, config));
+    add_labels(&mut runner, labels);
+    runner
+}
+
+/// Returns a map from each (canonicalized) watched file to the test files that need to be re-run
+/// when it changes, i.e., itself and the test files including it, or all of them for the
+/// `fixtures` and the files they include.
+pub(crate) fn dependencies(
+    files: &[PathBuf],
+    fixtures: &[PathBuf],
+    cache: &mut ParseCache<DefaultColumnType>,
+) -> HashMap<PathBuf, BTreeSet<PathBuf>> {
+    let mut deps: HashMap<PathBuf, BTreeSet<PathBuf>> = HashMap::new();
+    for file in files {
+        deps.entry(canonicalize(file))
+            .or_default()
+            .insert(file.clone());
+        for path in included_files(file, cache) {
+            deps.entry(path).or_default().insert(file.clone());
+        }
+    }
+    for fixture in fixtures {
+        for path in std::iter::once(canonicalize(fixture)).chain(included_files(fixture, cache)) {
+            deps.entry(path).or_default().extend(files.iter().cloned());
+        }
+    }
+    deps
+}
+
+/// Returns the (canonicalized) files included by the file.
+fn included_files(file: &Path, cache: &mut ParseCache<DefaultColumnType>) -> Vec<PathBuf> {
+    // Parse errors will be reported when running the file.
+    let Ok(records) = sqllogictest::parse_file_cached(file, cache) else {
+        return vec![];
+    };
+    records
+        .into_iter()
+        .filter_map(|record| match record {
+            Record::Injected(Injected::BeginInclude(included)) => {
+                Some(canonicalize(Path::new(&included)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Watches the directories containing the files, instead of the files themselves, so that files
+/// replaced by editors on save are still tracked.
+fn watch_dirs(
+    watcher: &mut impl Watcher,
+    deps: &HashMap<PathBuf, BTreeSet<PathBuf>>,
+    watched_dirs: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    for dir in deps.keys().filter_map(|path| path.parent()) {
+        if watched_dirs.insert(dir.to_path_buf()) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("failed to watch {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn collect_changes(event: notify::Event, changed: &mut HashSet<PathBuf>) {
+    if event.kind.is_access() {
+        return;
+    }
+    changed.extend(event.paths.iter().map(|path| canonicalize(path)));
+}
+
+/// Canonicalizes the path if it exists, so that it can be compared with the paths in events.
+pub(crate) fn canonicalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Displays the path relative to the current directory if possible.
+fn display_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
@@ -1,6 +1,7 @@
 use std::io;
 use std::marker::PhantomData;
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use async_trait::async_trait;
@@ -9,8 +10,9 @@ use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqllogictest::{AsyncDB, DBOutput, DefaultColumnType};
 use thiserror::Error;
-use tokio::io::AsyncWriteExt;
-use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::task::JoinHandle;
 use tokio_util::codec::{Decoder, FramedRead};
 
 /// Communicates with a subprocess via its stdin/stdout.
@@ -33,10 +35,27 @@ use tokio_util::codec::{Decoder, FramedRead};
 /// ```json
 /// {"err":"..."}
 /// ```
+///
+/// # Stderr
+///
+/// By default, the stderr of the subprocess is inherited. With
+/// [`ExternalDriver::connect_capturing_stderr`], it's captured instead, and the lines written since
+/// the previous `run` are attached to the error if a `run` fails, or logged at debug level
+/// otherwise. If the subprocess closed its stdin or stdout, e.g., because it crashed, the `run`
+/// waits for it to exit and attaches all of its stderr.
 pub struct ExternalDriver {
     child: Child,
     stdin: ChildStdin,
     stdout: FramedRead<ChildStdout, JsonDecoder<Output>>,
+    /// The captured stderr, if capturing.
+    stderr: Option<CapturedStderr>,
+}
+
+struct CapturedStderr {
+    /// The lines not yet taken by a `run`.
+    lines: Arc<Mutex<String>>,
+    /// The task reading the stderr until EOF, if not joined yet.
+    drain: Option<JoinHandle<()>>,
 }
 
 #[derive(Serialize)]
@@ -59,6 +78,11 @@ pub enum ExternalDriverError {
     Io(#[from] io::Error),
     #[error("sql failed {0}")]
     Sql(String),
+    #[error("{error}\n[stderr]\n{}", .stderr.trim_end())]
+    WithStderr {
+        error: Box<ExternalDriverError>,
+        stderr: String,
+    },
 }
 
 type Result<T> = std::result::Result<T, ExternalDriverError>;
@@ -78,22 +102,29 @@ impl ExternalDriver {
             child,
             stdin,
             stdout,
+            stderr: None,
         })
     }
-}
 
-impl Drop for ExternalDriver {
-    fn drop(&mut self) {
-        let _ = self.child.start_kill();
-    }
-}
+    /// Like [`ExternalDriver::connect`], but captures the stderr of the subprocess to help
+    /// diagnose failures. See [the type-level docs](ExternalDriver#stderr) for details.
+    pub async fn connect_capturing_stderr(mut cmd: Command) -> Result<Self> {
+        cmd.stderr(Stdio::piped());
+        let mut driver = Self::connect(cmd).await?;
 
-#[async_trait]
-impl AsyncDB for ExternalDriver {
-    type Error = ExternalDriverError;
-    type ColumnType = DefaultColumnType;
+        let lines = Arc::new(Mutex::new(String::new()));
+        let stderr = driver.child.stderr.take().unwrap();
+        // Drain it in the background, so that the subprocess never blocks on a full pipe.
+        let drain = tokio::spawn(drain_stderr(stderr, lines.clone()));
+        driver.stderr = Some(CapturedStderr {
+            lines,
+            drain: Some(drain),
+        });
 
-    async fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>> {
+        Ok(driver)
+    }
+
+    async fn run_inner(&mut self, sql: &str) -> Result<DBOutput<DefaultColumnType>> {
         let input = Input {
             sql: sql.to_string(),
         };
@@ -112,6 +143,56 @@ impl AsyncDB for ExternalDriver {
             Output::Failed { err } => Err(ExternalDriverError::Sql(err)),
         }
     }
+}
+
+async fn drain_stderr(stderr: ChildStderr, captured: Arc<Mutex<String>>) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut captured = captured.lock().unwrap();
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+}
+
+impl Drop for ExternalDriver {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+    }
+}
+
+#[async_trait]
+impl AsyncDB for ExternalDriver {
+    type Error = ExternalDriverError;
+    type ColumnType = DefaultColumnType;
+
+    async fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>> {
+        let result = self.run_inner(sql).await;
+        let Some(captured) = &mut self.stderr else {
+            return result;
+        };
+
+        if let Err(ExternalDriverError::Io(_)) = &result {
+            // The pipes are broken, so the subprocess is exiting: read its stderr until EOF.
+            let _ = self.child.wait().await;
+            if let Some(drain) = captured.drain.take() {
+                let _ = drain.await;
+            }
+        }
+        let stderr = std::mem::take(&mut *captured.lines.lock().unwrap());
+        if stderr.is_empty() {
+            return result;
+        }
+        match result {
+            Ok(output) => {
+                log::debug!("stderr of external engine while running {sql:?}:\n{stderr}");
+                Ok(output)
+            }
+            Err(error) => Err(ExternalDriverError::WithStderr {
+                error: Box::new(error),
+                stderr,
+            }),
+        }
+    }
 
     fn engine_name(&self) -> &str {
         "external"
@@ -155,3 +236,54 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A driver that logs to stderr and then exits without answering.
+    fn crashing_driver() -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", r#"echo "connecting to nowhere" >&2; exit 1"#]);
+        cmd
+    }
+
+    /// A driver that logs to stderr and then fails the first `run`.
+    fn failing_driver() -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            r#"echo "connecting to nowhere" >&2; echo '{"err":"protocol error"}'; sleep 10"#,
+        ]);
+        cmd
+    }
+
+    #[tokio::test]
+    async fn test_capture_stderr() {
+        let mut driver = ExternalDriver::connect_capturing_stderr(crashing_driver())
+            .await
+            .unwrap();
+        let Err(err) = driver.run("select 1").await else {
+            panic!("expected an error");
+        };
+        assert!(
+            matches!(&err, ExternalDriverError::WithStderr { error, stderr } if matches!(**error, ExternalDriverError::Io(_)) && stderr == "connecting to nowhere\n"),
+            "{err:?}"
+        );
+        assert_eq!(
+            err.to_string(),
+            "io failed\n[stderr]\nconnecting to nowhere"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_capture_stderr() {
+        let mut cmd = failing_driver();
+        cmd.stderr(Stdio::null());
+        let mut driver = ExternalDriver::connect(cmd).await.unwrap();
+        let Err(err) = driver.run("select 1").await else {
+            panic!("expected an error");
+        };
+        assert!(matches!(err, ExternalDriverError::Sql(_)), "{err:?}");
+    }
+}
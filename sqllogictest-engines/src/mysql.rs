@@ -70,6 +70,10 @@ impl sqllogictest::AsyncDB for MySql {
         "mysql"
     }
 
+    async fn ping(&mut self) -> Result<()> {
+        self.pool.get_conn().await?.ping().await
+    }
+
     async fn sleep(dur: Duration) {
         tokio::time::sleep(dur).await
     }
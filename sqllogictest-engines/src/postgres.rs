@@ -1,10 +1,16 @@
 mod extended;
+pub mod formatting;
 mod simple;
 
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use futures::StreamExt;
 use tokio::task::JoinHandle;
+use tokio_postgres::error::{Severity, SqlState};
+use tokio_postgres::AsyncMessage;
+
+use self::formatting::{DefaultValueFormatter, ValueFormatter};
 
 type Result<T> = std::result::Result<T, tokio_postgres::Error>;
 
@@ -18,6 +24,12 @@ pub struct Extended;
 pub struct Postgres<P> {
     client: Arc<tokio_postgres::Client>,
     join_handle: JoinHandle<()>,
+    formatter: Box<dyn ValueFormatter>,
+    /// The messages of the `WARNING` notices received since the last SQL run.
+    warnings: Arc<Mutex<Vec<String>>>,
+    /// The savepoint which started the current transaction, which is committed when it's
+    /// released. `None` if the savepoints are created in a transaction begun by the test.
+    transaction_savepoint: Option<String>,
     _protocol: PhantomData<P>,
 }
 
@@ -32,25 +44,101 @@ pub type PostgresConfig = tokio_postgres::Config;
 impl<P> Postgres<P> {
     /// Connects to the Postgres server with the given `config`.
     pub async fn connect(config: PostgresConfig) -> Result<Self> {
-        let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+        let (client, mut connection) = config.connect(tokio_postgres::NoTls).await?;
 
-        let join_handle = tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                log::error!("Postgres connection error: {:?}", e);
+        let warnings = Arc::new(Mutex::new(vec![]));
+        let join_handle = tokio::spawn({
+            let warnings = warnings.clone();
+            async move {
+                // Notices are received before the results of the SQL raising them, so they're
+                // collected by the time the SQL completes.
+                let mut messages = futures::stream::poll_fn(|cx| connection.poll_message(cx));
+                while let Some(message) = messages.next().await {
+                    match message {
+                        Ok(AsyncMessage::Notice(notice)) => {
+                            log::info!("{}: {}", notice.severity(), notice.message());
+                            if notice.parsed_severity() == Some(Severity::Warning) {
+                                warnings.lock().unwrap().push(notice.message().to_owned());
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            log::error!("Postgres connection error: {:?}", e);
+                            break;
+                        }
+                    }
+                }
             }
         });
 
         Ok(Self {
             client: Arc::new(client),
             join_handle,
+            formatter: Box::new(DefaultValueFormatter),
+            warnings,
+            transaction_savepoint: None,
             _protocol: PhantomData,
         })
     }
 
+    /// Takes the warnings received since the last call.
+    fn drain_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.lock().unwrap())
+    }
+
     /// Returns a reference of the inner Postgres client.
     pub fn pg_client(&self) -> &tokio_postgres::Client {
         &self.client
     }
+
+    /// Format the values in the results with `formatter`. Defaults to [`DefaultValueFormatter`].
+    pub fn with_formatter(&mut self, formatter: impl ValueFormatter + 'static) {
+        self.formatter = Box::new(formatter);
+    }
+
+    /// Runs a trivial query to check that the connection is still alive.
+    async fn ping_server(&self) -> Result<()> {
+        self.client.batch_execute("SELECT 1").await
+    }
+
+    /// Creates a savepoint, starting a transaction first if not in one.
+    async fn begin_savepoint(&mut self, name: &str) -> Result<()> {
+        match self
+            .client
+            .batch_execute(&format!("SAVEPOINT {name}"))
+            .await
+        {
+            Err(e) if e.code() == Some(&SqlState::NO_ACTIVE_SQL_TRANSACTION) => {
+                self.client
+                    .batch_execute(&format!("BEGIN; SAVEPOINT {name}"))
+                    .await?;
+                self.transaction_savepoint = Some(name.to_owned());
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    async fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.client
+            .batch_execute(&format!("ROLLBACK TO SAVEPOINT {name}"))
+            .await
+    }
+
+    /// Releases the savepoint, and commits the transaction if it's started by the savepoint.
+    /// A transaction begun by the test, around the savepoint, is left open.
+    async fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        if self.transaction_savepoint.as_deref() == Some(name) {
+            self.transaction_savepoint = None;
+            self.client
+                .batch_execute(&format!("RELEASE SAVEPOINT {name}; COMMIT"))
+                .await
+        } else {
+            self.client
+                .batch_execute(&format!("RELEASE SAVEPOINT {name}"))
+                .await
+        }
+    }
 }
 
 impl<P> Drop for Postgres<P> {
@@ -140,46 +140,6 @@ macro_rules! single_process {
     };
 }
 
-fn bool_to_str(value: &bool) -> &'static str {
-    if *value {
-        "t"
-    } else {
-        "f"
-    }
-}
-
-fn varchar_to_str(value: &str) -> String {
-    if value.is_empty() {
-        "(empty)".to_string()
-    } else {
-        value.to_string()
-    }
-}
-
-fn float4_to_str(value: &f32) -> String {
-    if value.is_nan() {
-        "NaN".to_string()
-    } else if *value == f32::INFINITY {
-        "Infinity".to_string()
-    } else if *value == f32::NEG_INFINITY {
-        "-Infinity".to_string()
-    } else {
-        value.to_string()
-    }
-}
-
-fn float8_to_str(value: &f64) -> String {
-    if value.is_nan() {
-        "NaN".to_string()
-    } else if *value == f64::INFINITY {
-        "Infinity".to_string()
-    } else if *value == f64::NEG_INFINITY {
-        "-Infinity".to_string()
-    } else {
-        value.to_string()
-    }
-}
-
 #[async_trait]
 impl sqllogictest::AsyncDB for Postgres<Extended> {
     type Error = tokio_postgres::error::Error;
@@ -188,6 +148,13 @@ impl sqllogictest::AsyncDB for Postgres<Extended> {
     async fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>> {
         let mut output = vec![];
 
+        let formatter = &*self.formatter;
+        let bool_to_str = |value: &bool| formatter.bool(*value);
+        let varchar_to_str = |value: &String| formatter.text(value);
+        let float4_to_str = |value: &f32| formatter.float4(*value);
+        let float8_to_str = |value: &f64| formatter.float8(*value);
+        let bytea_to_str = |value: &Vec<u8>| formatter.bytea(value);
+
         let stmt = self.client.prepare(sql).await?;
         let rows = self
             .client
@@ -268,6 +235,9 @@ impl sqllogictest::AsyncDB for Postgres<Extended> {
                     Type::FLOAT8 => {
                         single_process!(row, row_vec, idx, f64, float8_to_str);
                     }
+                    Type::BYTEA => {
+                        single_process!(row, row_vec, idx, Vec<u8>, bytea_to_str);
+                    }
                     Type::INTERVAL => {
                         single_process!(self, row, row_vec, idx, Interval, INTERVAL);
                     }
@@ -315,6 +285,34 @@ impl sqllogictest::AsyncDB for Postgres<Extended> {
         "postgres-extended"
     }
 
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        error.code().map(|code| code.code().to_owned())
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        self.drain_warnings()
+    }
+
+    fn supports_savepoints(&self) -> bool {
+        true
+    }
+
+    async fn savepoint(&mut self, name: &str) -> Result<()> {
+        self.begin_savepoint(name).await
+    }
+
+    async fn rollback_to(&mut self, name: &str) -> Result<()> {
+        self.rollback_to_savepoint(name).await
+    }
+
+    async fn release(&mut self, name: &str) -> Result<()> {
+        self.release_savepoint(name).await
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.ping_server().await
+    }
+
     async fn sleep(dur: Duration) {
         tokio::time::sleep(dur).await
     }
@@ -0,0 +1,123 @@
+//! Formatting of the values returned by Postgres as strings in the results.
+
+use std::fmt::Write;
+
+/// Formats the values returned by Postgres as strings in the results, shared by the simple and
+/// extended query protocols.
+///
+/// Each method defaults to the text output of Postgres, so that the same results are expected with
+/// both protocols. Override some of them to tweak the format without forking the engine, e.g.:
+///
+/// ```
+/// use sqllogictest_engines::postgres::formatting::ValueFormatter;
+///
+/// /// Prints `true` and `false` instead of `t` and `f`.
+/// struct VerboseBool;
+///
+/// impl ValueFormatter for VerboseBool {
+///     fn bool(&self, value: bool) -> String {
+///         value.to_string()
+///     }
+/// }
+/// ```
+///
+/// The simple query protocol only returns text, so only [`ValueFormatter::text`] applies to it.
+pub trait ValueFormatter: Send + Sync {
+    /// Formats a `text` or `varchar` value, or any value with the simple query protocol. Empty
+    /// strings are formatted as `(empty)`, since empty values can't be written in the results.
+    fn text(&self, value: &str) -> String {
+        if value.is_empty() {
+            "(empty)".to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Formats a `bool` value as `t` or `f`.
+    fn bool(&self, value: bool) -> String {
+        if value { "t" } else { "f" }.to_string()
+    }
+
+    /// Formats a `float4` value, with `NaN`, `Infinity` and `-Infinity` for special values.
+    fn float4(&self, value: f32) -> String {
+        if value.is_nan() {
+            "NaN".to_string()
+        } else if value == f32::INFINITY {
+            "Infinity".to_string()
+        } else if value == f32::NEG_INFINITY {
+            "-Infinity".to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Formats a `float8` value, with `NaN`, `Infinity` and `-Infinity` for special values.
+    fn float8(&self, value: f64) -> String {
+        if value.is_nan() {
+            "NaN".to_string()
+        } else if value == f64::INFINITY {
+            "Infinity".to_string()
+        } else if value == f64::NEG_INFINITY {
+            "-Infinity".to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Formats a `bytea` value in the hex format, e.g., `\xdeadbeef`.
+    fn bytea(&self, value: &[u8]) -> String {
+        let mut output = String::with_capacity(2 + value.len() * 2);
+        output.push_str("\\x");
+        for byte in value {
+            write!(output, "{byte:02x}").unwrap();
+        }
+        output
+    }
+}
+
+/// The [`ValueFormatter`] following the text output of Postgres.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultValueFormatter;
+
+impl ValueFormatter for DefaultValueFormatter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_value_formatter() {
+        let f = DefaultValueFormatter;
+        assert_eq!(f.text(""), "(empty)");
+        assert_eq!(f.text("abc"), "abc");
+        assert_eq!(f.bool(true), "t");
+        assert_eq!(f.bool(false), "f");
+        assert_eq!(f.float4(1.5), "1.5");
+        assert_eq!(f.float4(f32::NAN), "NaN");
+        assert_eq!(f.float4(f32::INFINITY), "Infinity");
+        assert_eq!(f.float4(f32::NEG_INFINITY), "-Infinity");
+        assert_eq!(f.float8(-0.25), "-0.25");
+        assert_eq!(f.float8(f64::NAN), "NaN");
+        assert_eq!(f.float8(f64::INFINITY), "Infinity");
+        assert_eq!(f.float8(f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(f.bytea(&[]), "\\x");
+        assert_eq!(f.bytea(&[0xde, 0xad, 0x01]), "\\xdead01");
+    }
+
+    #[test]
+    fn test_override_value_formatter() {
+        /// Formats `bytea` values as byte lists, and everything else as by default.
+        struct ByteList;
+
+        impl ValueFormatter for ByteList {
+            fn bytea(&self, value: &[u8]) -> String {
+                format!("{value:?}")
+            }
+        }
+
+        let f = ByteList;
+        assert_eq!(f.bytea(&[1, 2]), "[1, 2]");
+        assert_eq!(f.bool(true), "t");
+        assert_eq!(f.text(""), "(empty)");
+    }
+}
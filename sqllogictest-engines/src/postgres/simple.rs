@@ -28,13 +28,7 @@ impl sqllogictest::AsyncDB for Postgres<Simple> {
                 tokio_postgres::SimpleQueryMessage::Row(row) => {
                     for i in 0..row.len() {
                         match row.get(i) {
-                            Some(v) => {
-                                if v.is_empty() {
-                                    row_vec.push("(empty)".to_string());
-                                } else {
-                                    row_vec.push(v.to_string());
-                                }
-                            }
+                            Some(v) => row_vec.push(self.formatter.text(v)),
                             None => row_vec.push("NULL".to_string()),
                         }
                     }
@@ -66,6 +60,34 @@ impl sqllogictest::AsyncDB for Postgres<Simple> {
         "postgres"
     }
 
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        error.code().map(|code| code.code().to_owned())
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        self.drain_warnings()
+    }
+
+    fn supports_savepoints(&self) -> bool {
+        true
+    }
+
+    async fn savepoint(&mut self, name: &str) -> Result<()> {
+        self.begin_savepoint(name).await
+    }
+
+    async fn rollback_to(&mut self, name: &str) -> Result<()> {
+        self.rollback_to_savepoint(name).await
+    }
+
+    async fn release(&mut self, name: &str) -> Result<()> {
+        self.release_savepoint(name).await
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.ping_server().await
+    }
+
     async fn sleep(dur: Duration) {
         tokio::time::sleep(dur).await
     }
@@ -0,0 +1,82 @@
+//! Benchmarks validating a large query result.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use sqllogictest::{DBOutput, DefaultColumnType, Record, Runner};
+
+const NUM_ROWS: usize = 100_000;
+
+struct FakeDB {
+    rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug)]
+struct FakeDBError;
+
+impl std::fmt::Display for FakeDBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for FakeDBError {}
+
+impl sqllogictest::DB for FakeDB {
+    type Error = FakeDBError;
+    type ColumnType = DefaultColumnType;
+
+    fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, FakeDBError> {
+        Ok(DBOutput::Rows {
+            types: vec![DefaultColumnType::Integer, DefaultColumnType::Text],
+            rows: std::mem::take(&mut self.rows),
+        })
+    }
+}
+
+/// Returns the rows in reverse order, so that sorting is not a no-op.
+fn rows() -> Vec<Vec<String>> {
+    (0..NUM_ROWS)
+        .rev()
+        .map(|i| vec![format!("{i:06}"), format!("value {i}")])
+        .collect()
+}
+
+fn query(sort_mode: &str, results: impl Iterator<Item = String>) -> Record<DefaultColumnType> {
+    let script = format!(
+        "query IT {sort_mode}\nselect * from t\n----\n{}\n",
+        results.collect::<Vec<_>>().join("\n")
+    );
+    let mut records = sqllogictest::parse(&script).unwrap();
+    records.truncate(1);
+    records.pop().unwrap()
+}
+
+fn bench_query(c: &mut Criterion, name: &str, record: Record<DefaultColumnType>) {
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || (record.clone(), rows()),
+            |(record, rows)| {
+                let mut rows = Some(rows);
+                let mut runner = Runner::new(move || {
+                    let rows = rows.take().unwrap();
+                    async move { Ok(FakeDB { rows }) }
+                });
+                runner.run(record).unwrap();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn query_results(c: &mut Criterion) {
+    let sorted = || (0..NUM_ROWS).map(|i| format!("{i:06} value {i}"));
+    bench_query(c, "rowsort_100k", query("rowsort", sorted()));
+
+    let mut values = (0..NUM_ROWS)
+        .flat_map(|i| [format!("{i:06}"), format!("value {i}")])
+        .collect::<Vec<_>>();
+    values.sort_unstable();
+    bench_query(c, "valuesort_100k", query("valuesort", values.into_iter()));
+}
+
+criterion_group!(benches, query_results);
+criterion_main!(benches);
@@ -6,6 +6,16 @@ use std::fmt::Debug;
 pub trait ColumnType: Debug + PartialEq + Eq + Clone + Send + Sync {
     fn from_char(value: char) -> Option<Self>;
     fn to_char(&self) -> char;
+
+    /// Whether the trailing spaces of the values of this type are insignificant, e.g., for
+    /// fixed-width `CHAR(n)` columns padded by the engine. If so, they are removed from the
+    /// actual values before sorting, hashing and comparison.
+    ///
+    /// A column is trimmed if either its actual type or its type in the `query` header trims.
+    /// Defaults to `false`.
+    fn trims_trailing_space(&self) -> bool {
+        false
+    }
 }
 
 /// The default Sqllogictest type.
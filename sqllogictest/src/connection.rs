@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 use std::future::IntoFuture;
+use std::time::{Duration, Instant};
 
-use futures::Future;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
 
 use crate::{AsyncDB, Connection as ConnectionName, DBOutput};
 
@@ -33,20 +35,241 @@ where
     }
 }
 
+/// Makes connections to one of multiple engines, to run the same test files against each of them.
+///
+/// The connections are made by the selected engine, the first one added by default. Select the
+/// engine before making a [`Runner`](crate::Runner) with it, so that all the connections of the
+/// runner are made by the same engine. The engine name of the connections
+/// ([`AsyncDB::engine_name`]) is a label for the conditions as usual, so that `skipif` and
+/// `onlyif` work per engine.
+///
+/// ```
+/// # use sqllogictest::{DBOutput, DefaultColumnType, MultiEngine, Runner};
+/// # struct MyDB(&'static str);
+/// # impl sqllogictest::DB for MyDB {
+/// #     type Error = std::io::Error;
+/// #     type ColumnType = DefaultColumnType;
+/// #     fn run(&mut self, _sql: &str) -> Result<DBOutput<DefaultColumnType>, Self::Error> {
+/// #         Ok(DBOutput::StatementComplete(0))
+/// #     }
+/// #     fn engine_name(&self) -> &str {
+/// #         self.0
+/// #     }
+/// # }
+/// // The connection makers are of the same type, as they are made by the same closure.
+/// let make_conn = |name| move || async move { Ok(MyDB(name)) };
+/// let mut engines = MultiEngine::new();
+/// engines.add_engine("postgres", make_conn("postgres"));
+/// engines.add_engine("mysql", make_conn("mysql"));
+///
+/// for name in ["postgres", "mysql"] {
+///     let mut make_conn = engines.clone();
+///     make_conn.select(name);
+///     let mut runner = Runner::new(make_conn);
+///     runner
+///         .run_script("onlyif mysql\nstatement ok\nselect 1\n")
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultiEngine<M> {
+    engines: Vec<(String, M)>,
+    selected: usize,
+}
+
+impl<M> Default for MultiEngine<M> {
+    fn default() -> Self {
+        Self {
+            engines: vec![],
+            selected: 0,
+        }
+    }
+}
+
+impl<M: MakeConnection> MultiEngine<M> {
+    /// Creates a connection maker without any engine.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an engine with the name and its connection maker.
+    pub fn add_engine(&mut self, name: impl Into<String>, make_conn: M) {
+        self.engines.push((name.into(), make_conn));
+    }
+
+    /// Returns the names of the engines, in the order they were added.
+    pub fn engine_names(&self) -> impl Iterator<Item = &str> {
+        self.engines.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Selects the engine to make the connections with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no engine with the name was added.
+    pub fn select(&mut self, name: &str) {
+        self.selected = (self.engines.iter())
+            .position(|(engine, _)| engine == name)
+            .unwrap_or_else(|| panic!("unknown engine: {name}"));
+    }
+
+    /// Returns the name of the selected engine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no engine was added.
+    pub fn selected_engine(&self) -> &str {
+        &self.engines[self.selected].0
+    }
+}
+
+impl<M: MakeConnection> MakeConnection for MultiEngine<M> {
+    type Conn = M::Conn;
+    type MakeFuture = M::MakeFuture;
+
+    /// Makes a connection to the selected engine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no engine was added.
+    fn make(&mut self) -> Self::MakeFuture {
+        let (_, make_conn) = (self.engines.get_mut(self.selected)).expect("no engine added");
+        make_conn.make()
+    }
+}
+
+/// Configuration for retrying [`MakeConnection::make`] when the database is not ready yet.
+///
+/// The backoff starts from `initial_backoff` and doubles after each failed attempt, until either
+/// `retries` is exhausted or `timeout` has elapsed since the first attempt.
+#[derive(Debug, Clone)]
+pub struct ConnectRetry {
+    /// Maximum number of retries after the first failed attempt.
+    pub retries: usize,
+    /// Deadline for all attempts, measured from the first one.
+    pub timeout: Duration,
+    /// Backoff before the first retry.
+    pub initial_backoff: Duration,
+    /// Returns whether the connection error is transient and thus worth retrying.
+    ///
+    /// By default ([`is_transient_connect_error`]), errors like "connection refused" are retried
+    /// while others (e.g. authentication failures) are returned immediately.
+    pub is_transient: fn(&(dyn std::error::Error + 'static)) -> bool,
+}
+
+impl ConnectRetry {
+    /// Creates a retry configuration with the default backoff and error classification.
+    pub fn new(retries: usize, timeout: Duration) -> Self {
+        Self {
+            retries,
+            timeout,
+            initial_backoff: Duration::from_millis(100),
+            is_transient: is_transient_connect_error,
+        }
+    }
+}
+
+/// Returns whether the error (or any of its sources) indicates that the database is not reachable
+/// *yet*, e.g. the server is still starting up.
+pub fn is_transient_connect_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    use std::io::ErrorKind;
+
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            if matches!(
+                err.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::AddrNotAvailable
+                    | ErrorKind::TimedOut
+            ) {
+                return true;
+            }
+        }
+        let message = err.to_string();
+        if message.contains("Connection refused")
+            || message.contains("the database system is starting up")
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Makes a new connection with `make_conn`, retrying transient failures according to `retry`.
+pub async fn connect_with_retry<M: MakeConnection>(
+    make_conn: &mut M,
+    retry: &ConnectRetry,
+) -> Result<M::Conn, <M::Conn as AsyncDB>::Error> {
+    let start = Instant::now();
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 0;
+    loop {
+        let err = match make_conn.make().await {
+            Ok(conn) => return Ok(conn),
+            Err(err) => err,
+        };
+        let elapsed = start.elapsed();
+        if attempt >= retry.retries || elapsed >= retry.timeout || !(retry.is_transient)(&err) {
+            return Err(err);
+        }
+        attempt += 1;
+        let backoff_now = backoff.min(retry.timeout - elapsed);
+        tracing::warn!(target: "sqllogictest::connect", attempt, backoff = ?backoff_now, error = %err, "failed to connect, retrying");
+        M::Conn::sleep(backoff_now).await;
+        backoff *= 2;
+    }
+}
+
+/// A type-erased [`MakeConnection`], used for the named connections with their own makers.
+type BoxMakeConnection<D> =
+    Box<dyn FnMut() -> BoxFuture<'static, Result<D, <D as AsyncDB>::Error>> + Send>;
+
 /// Connections established in a [`Runner`](crate::Runner).
-pub(crate) struct Connections<D, M> {
+pub(crate) struct Connections<D: AsyncDB, M> {
     make_conn: M,
+    /// Makers for specific named connections, used instead of `make_conn`.
+    factories: HashMap<String, BoxMakeConnection<D>>,
     conns: HashMap<ConnectionName, D>,
+    /// Retry configuration for making new connections. `None` means no retry.
+    retry: Option<ConnectRetry>,
+    /// The number of connections made so far, including those already dropped.
+    made: usize,
 }
 
 impl<D: AsyncDB, M: MakeConnection<Conn = D>> Connections<D, M> {
     pub fn new(make_conn: M) -> Self {
         Connections {
             make_conn,
+            factories: HashMap::new(),
             conns: HashMap::new(),
+            retry: None,
+            made: 0,
         }
     }
 
+    pub fn set_retry(&mut self, retry: Option<ConnectRetry>) {
+        self.retry = retry;
+    }
+
+    pub fn retry(&self) -> Option<&ConnectRetry> {
+        self.retry.as_ref()
+    }
+
+    /// Use `make_conn` to make the connection with the given name.
+    pub fn add_factory<F>(&mut self, name: String, mut make_conn: F)
+    where
+        F: MakeConnection<Conn = D> + Send + 'static,
+        F::MakeFuture: Send + 'static,
+    {
+        self.factories
+            .insert(name, Box::new(move || make_conn.make().boxed()));
+    }
+
     /// Get a connection by name. Make a new connection if it doesn't exist.
     pub async fn get(&mut self, name: ConnectionName) -> Result<&mut D, D::Error> {
         use std::collections::hash_map::Entry;
@@ -54,7 +277,17 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Connections<D, M> {
         let conn = match self.conns.entry(name) {
             Entry::Occupied(o) => o.into_mut(),
             Entry::Vacant(v) => {
-                let conn = self.make_conn.make().await?;
+                let factory = match v.key() {
+                    ConnectionName::Named(name) => self.factories.get_mut(name),
+                    ConnectionName::Default => None,
+                };
+                let conn = match (factory, &self.retry) {
+                    (Some(factory), Some(retry)) => connect_with_retry(factory, retry).await?,
+                    (Some(factory), None) => factory().await?,
+                    (None, Some(retry)) => connect_with_retry(&mut self.make_conn, retry).await?,
+                    (None, None) => self.make_conn.make().await?,
+                };
+                self.made += 1;
                 v.insert(conn)
             }
         };
@@ -62,6 +295,53 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Connections<D, M> {
         Ok(conn)
     }
 
+    /// Takes the connection with the given name out of the connections, making it if it doesn't
+    /// exist. Give it back with [`Connections::put`].
+    pub async fn take(&mut self, name: ConnectionName) -> Result<D, D::Error> {
+        self.get(name.clone()).await?;
+        Ok(self.conns.remove(&name).unwrap())
+    }
+
+    /// Puts back the connection taken with [`Connections::take`].
+    pub fn put(&mut self, name: ConnectionName, conn: D) {
+        self.conns.insert(name, conn);
+    }
+
+    /// Makes a new connection with `make_conn`, which is not kept in the connections.
+    pub async fn make(&mut self) -> Result<D, D::Error> {
+        let conn = match &self.retry {
+            Some(retry) => connect_with_retry(&mut self.make_conn, retry).await?,
+            None => self.make_conn.make().await?,
+        };
+        self.made += 1;
+        Ok(conn)
+    }
+
+    /// Returns the number of connections made so far, including those already dropped.
+    pub fn made(&self) -> usize {
+        self.made
+    }
+
+    /// Drops all the connections, so that new ones are made when they're used next time.
+    pub fn close_all(&mut self) {
+        self.conns.clear();
+    }
+
+    /// Pings all the connections, and drops those failing it, so that new ones are made when
+    /// they're used next time.
+    pub async fn ping_all(&mut self) {
+        let mut dead = vec![];
+        for (name, conn) in &mut self.conns {
+            if let Err(err) = conn.ping().await {
+                tracing::warn!(target: "sqllogictest::connect", connection = ?name, error = %err, "connection is dead, reconnecting");
+                dead.push(name.clone());
+            }
+        }
+        for name in dead {
+            self.conns.remove(&name);
+        }
+    }
+
     /// Run a SQL statement on the default connection.
     ///
     /// This is a shortcut for calling `get(Default)` then `run`.
@@ -1,3 +1,7 @@
+//! Integration with [`libtest_mimic`], for running `.slt` files as Rust tests.
+//!
+//! Requires the `harness` feature, which is enabled by default.
+
 use std::path::Path;
 
 pub use glob::glob;
@@ -5,6 +9,11 @@ pub use libtest_mimic::{run, Arguments, Failed, Trial};
 
 use crate::{MakeConnection, Runner};
 
+/// Generates a `main` function that runs each file matching the glob as a test case.
+///
+/// Use it in a test target with `harness = false`. To customize the test cases, or to run them
+/// along with other tests, use [`trials_from_glob`] instead.
+///
 /// * `db_fn`: `fn() -> sqllogictest::AsyncDB`
 /// * `pattern`: The glob used to match against and select each file to be tested. It is relative to
 ///   the root of the crate.
@@ -12,16 +21,7 @@ use crate::{MakeConnection, Runner};
 macro_rules! harness {
     ($db_fn:path, $pattern:expr) => {
         fn main() {
-            let paths = $crate::harness::glob($pattern).expect("failed to find test files");
-            let mut tests = vec![];
-
-            for entry in paths {
-                let path = entry.expect("failed to read glob entry");
-                tests.push($crate::harness::Trial::test(
-                    path.to_str().unwrap().to_string(),
-                    move || $crate::harness::test(&path, || async { Ok($db_fn()) }),
-                ));
-            }
+            let tests = $crate::harness::trials_from_glob($pattern, || async { Ok($db_fn()) });
 
             if tests.is_empty() {
                 panic!("no test found for sqllogictest under: {}", $pattern);
@@ -32,6 +32,47 @@ macro_rules! harness {
     };
 }
 
+/// Creates a [`Trial`] for each file matching the glob, named by the path of the file.
+///
+/// Unlike the [`harness!`](crate::harness!) macro, which generates the whole `main` function,
+/// this returns the test cases so that they can be filtered, modified (e.g., with
+/// [`Trial::with_ignored_flag`]), or combined with other trials before being passed to [`run`].
+/// Failure messages contain the location of the failed record.
+///
+/// ```no_run
+/// # struct MyDatabase;
+/// # impl sqllogictest::DB for MyDatabase {
+/// #     type Error = std::io::Error;
+/// #     type ColumnType = sqllogictest::DefaultColumnType;
+/// #     fn run(&mut self, _: &str) -> Result<sqllogictest::DBOutput<Self::ColumnType>, Self::Error> {
+/// #         Ok(sqllogictest::DBOutput::StatementComplete(0))
+/// #     }
+/// # }
+/// use sqllogictest::harness::{run, trials_from_glob, Arguments};
+///
+/// let trials = trials_from_glob("tests/slt/**/*.slt", || async { Ok(MyDatabase) });
+/// run(&Arguments::from_args(), trials).exit();
+/// ```
+///
+/// # Panics
+///
+/// Panics if the glob pattern is invalid or a matched path can't be read.
+pub fn trials_from_glob<M>(pattern: &str, make_conn: M) -> Vec<Trial>
+where
+    M: MakeConnection + Clone + Send + 'static,
+{
+    let paths = glob(pattern).expect("failed to find test files");
+    paths
+        .map(|entry| {
+            let path = entry.expect("failed to read glob entry");
+            let make_conn = make_conn.clone();
+            Trial::test(path.to_str().unwrap().to_string(), move || {
+                test(&path, make_conn)
+            })
+        })
+        .collect()
+}
+
 pub fn test(filename: impl AsRef<Path>, make_conn: impl MakeConnection) -> Result<(), Failed> {
     let mut tester = Runner::new(make_conn);
     tester.run_file(filename)?;
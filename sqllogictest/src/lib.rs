@@ -54,6 +54,7 @@
 
 pub mod column_type;
 pub mod connection;
+#[cfg(feature = "harness")]
 pub mod harness;
 pub mod parser;
 pub mod runner;
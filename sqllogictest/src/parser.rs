@@ -1,10 +1,11 @@
 //! Sqllogictest parser.
 
+use std::collections::HashMap;
 use std::fmt;
 use std::iter::Peekable;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use itertools::Itertools;
 use regex::Regex;
@@ -12,6 +13,8 @@ use regex::Regex;
 use crate::ColumnType;
 
 const RESULTS_DELIMITER: &str = "----";
+/// The prefix of an expected error matched against the `Debug` form of the error.
+const DEBUG_ERROR_PREFIX: &str = "debug:";
 
 /// The location in source file.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -42,6 +45,11 @@ impl Location {
         self.line
     }
 
+    /// The location of the `include` record, if this is in an included file.
+    pub fn upper(&self) -> Option<&Location> {
+        self.upper.as_deref()
+    }
+
     fn new(file: impl Into<Arc<str>>, line: u32) -> Self {
         Self {
             file: file.into(),
@@ -85,6 +93,9 @@ pub enum StatementExpect {
     Count(u64),
     /// Statement should fail with the given error message.
     Error(ExpectedError),
+    /// The semicolon-separated statements of the SQL should all succeed when executed one by
+    /// one, set by `statement batch`.
+    Batch,
 }
 
 /// Expectation for a query.
@@ -96,6 +107,23 @@ pub enum QueryExpect<T: ColumnType> {
         sort_mode: Option<SortMode>,
         result_mode: Option<ResultMode>,
         label: Option<String>,
+        /// Whether the results are always compared by their hash, regardless of the hash
+        /// threshold. If so, `results` is a single line of the hash in hex, prefixed by the
+        /// algorithm unless it's MD5 (see [`HashAlgo`]).
+        hashed: bool,
+        /// Whether the results are compared case-insensitively, set by `nocase` or `nocase=off`.
+        /// If set, it overrides [`Runner::with_case_insensitive`](crate::Runner::with_case_insensitive).
+        nocase: Option<bool>,
+        /// The number of significant figures to round numeric values to before comparison, set
+        /// by `sigfig=<n>`.
+        sigfig: Option<usize>,
+        /// The indices of the columns to compare, set by `cols=<i>,<j>,...`. The other columns
+        /// are not included in the expected results.
+        columns: Option<Vec<usize>>,
+        /// The index of the result set to compare, set by `resultset=<n>`, if the query
+        /// produces [multiple result sets](crate::DBOutput::MultipleResultSets). The last one is
+        /// compared by default.
+        result_set: Option<usize>,
         results: Vec<String>,
     },
     /// Query should fail with the given error message.
@@ -110,6 +138,11 @@ impl<T: ColumnType> QueryExpect<T> {
             sort_mode: None,
             result_mode: None,
             label: None,
+            hashed: false,
+            nocase: None,
+            sigfig: None,
+            columns: None,
+            result_set: None,
             results: Vec::new(),
         }
     }
@@ -120,10 +153,15 @@ impl<T: ColumnType> QueryExpect<T> {
 #[non_exhaustive]
 pub enum Record<T: ColumnType> {
     /// An include copies all records from another files.
+    ///
+    /// The included records are in the order of the `include` records, and the files matched by
+    /// each glob pattern are in the lexicographic order of their paths.
     Include {
         loc: Location,
         /// A glob pattern
         filename: String,
+        /// The variables bound in the included records, given by `include <file>(name=value, ...)`.
+        args: Vec<(String, String)>,
     },
     /// A statement is an SQL command that is to be evaluated but from which we do not expect to
     /// get results (other than success or failure).
@@ -131,9 +169,17 @@ pub enum Record<T: ColumnType> {
         loc: Location,
         conditions: Vec<Condition>,
         connection: Connection,
+        /// Whether the connection is specified inline with `connection=<name>`, instead of by a
+        /// preceding `connection` record.
+        inline_connection: bool,
         /// The SQL command.
         sql: String,
         expected: StatementExpect,
+        /// The expected number of warnings emitted by the statement, set by `warnings <n>` or
+        /// `warnings ><n>`. Warnings are ignored if not set.
+        warnings: Option<ExpectedWarnings>,
+        /// The maximum time the statement may take to run, set by `maxtime <duration>`.
+        maxtime: Option<Duration>,
         /// Optional retry configuration
         retry: Option<RetryConfig>,
     },
@@ -143,9 +189,17 @@ pub enum Record<T: ColumnType> {
         loc: Location,
         conditions: Vec<Condition>,
         connection: Connection,
+        /// Whether the connection is specified inline with `connection=<name>`, instead of by a
+        /// preceding `connection` record.
+        inline_connection: bool,
         /// The SQL command.
         sql: String,
         expected: QueryExpect<T>,
+        /// The expected number of warnings emitted by the query, set by `warnings <n>` or
+        /// `warnings ><n>`. Warnings are ignored if not set.
+        warnings: Option<ExpectedWarnings>,
+        /// The maximum time the query may take to run, set by `maxtime <duration>`.
+        maxtime: Option<Duration>,
         /// Optional retry configuration
         retry: Option<RetryConfig>,
     },
@@ -171,6 +225,11 @@ pub enum Record<T: ColumnType> {
         loc: Location,
         name: String,
     },
+    /// Print the text, with variables substituted if substitution is enabled.
+    Echo {
+        loc: Location,
+        text: String,
+    },
     /// A halt record merely causes sqllogictest to ignore the rest of the test script.
     /// For debugging use only.
     Halt {
@@ -206,31 +265,91 @@ impl<T: ColumnType> Record<T> {
     pub fn unparse(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
         write!(w, "{self}")
     }
+
+    /// Returns the `onlyif` and `skipif` conditions of the record, or an empty slice if the record
+    /// can't have conditions.
+    pub fn conditions(&self) -> &[Condition] {
+        match self {
+            Record::Statement { conditions, .. }
+            | Record::Query { conditions, .. }
+            | Record::System { conditions, .. } => conditions,
+            _ => &[],
+        }
+    }
+
+    /// Returns the location of the record, if it has one.
+    pub(crate) fn loc(&self) -> Option<&Location> {
+        match self {
+            Record::Include { loc, .. }
+            | Record::Statement { loc, .. }
+            | Record::Query { loc, .. }
+            | Record::System { loc, .. }
+            | Record::Sleep { loc, .. }
+            | Record::Subtest { loc, .. }
+            | Record::Echo { loc, .. }
+            | Record::Halt { loc }
+            | Record::HashThreshold { loc, .. } => Some(loc),
+            Record::Control(_)
+            | Record::Condition(_)
+            | Record::Connection(_)
+            | Record::Comment(_)
+            | Record::Newline
+            | Record::Injected(_) => None,
+        }
+    }
 }
 
 /// As is the standard for Display, does not print any trailing
 /// newline except for records that always end with a blank line such
 /// as Query and Statement.
+///
+/// Directives continued onto multiple lines with trailing backslashes are
+/// printed on a single line.
 impl<T: ColumnType> std::fmt::Display for Record<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Record::Include { loc: _, filename } => {
-                write!(f, "include {filename}")
+            Record::Include {
+                loc: _,
+                filename,
+                args,
+            } => {
+                write!(f, "include {filename}")?;
+                if !args.is_empty() {
+                    let args = args.iter().map(|(k, v)| format!("{k}={v}")).join(", ");
+                    write!(f, "({args})")?;
+                }
+                Ok(())
             }
             Record::Statement {
                 loc: _,
                 conditions: _,
-                connection: _,
+                connection,
+                inline_connection,
                 sql,
                 expected,
+                maxtime,
+                warnings,
                 retry,
             } => {
                 write!(f, "statement ")?;
+                let connection = inline_connection.then_some(connection);
                 match expected {
-                    StatementExpect::Ok => write!(f, "ok")?,
-                    StatementExpect::Count(cnt) => write!(f, "count {cnt}")?,
-                    StatementExpect::Error(err) => err.fmt_inline(f)?,
+                    StatementExpect::Ok => {
+                        write!(f, "ok")?;
+                        fmt_inline_connection(f, connection)?;
+                    }
+                    StatementExpect::Count(cnt) => {
+                        write!(f, "count {cnt}")?;
+                        fmt_inline_connection(f, connection)?;
+                    }
+                    StatementExpect::Batch => {
+                        write!(f, "batch")?;
+                        fmt_inline_connection(f, connection)?;
+                    }
+                    StatementExpect::Error(err) => err.fmt_inline(f, connection)?,
                 }
+                fmt_warnings(f, warnings)?;
+                fmt_maxtime(f, maxtime)?;
                 if let Some(retry) = retry {
                     write!(
                         f,
@@ -251,29 +370,58 @@ impl<T: ColumnType> std::fmt::Display for Record<T> {
             Record::Query {
                 loc: _,
                 conditions: _,
-                connection: _,
+                connection,
+                inline_connection,
                 sql,
                 expected,
+                maxtime,
+                warnings,
                 retry,
             } => {
                 write!(f, "query ")?;
+                let connection = inline_connection.then_some(connection);
                 match expected {
                     QueryExpect::Results {
                         types,
                         sort_mode,
                         label,
+                        hashed,
+                        nocase,
+                        sigfig,
+                        columns,
+                        result_set,
                         ..
                     } => {
                         write!(f, "{}", types.iter().map(|c| c.to_char()).join(""))?;
+                        fmt_inline_connection(f, connection)?;
                         if let Some(sort_mode) = sort_mode {
                             write!(f, " {}", sort_mode.as_str())?;
                         }
+                        if *hashed {
+                            write!(f, " hashed")?;
+                        }
+                        match nocase {
+                            Some(true) => write!(f, " nocase")?,
+                            Some(false) => write!(f, " nocase=off")?,
+                            None => {}
+                        }
+                        if let Some(sigfig) = sigfig {
+                            write!(f, " sigfig={sigfig}")?;
+                        }
+                        if let Some(columns) = columns {
+                            write!(f, " cols={}", columns.iter().join(","))?;
+                        }
+                        if let Some(result_set) = result_set {
+                            write!(f, " resultset={result_set}")?;
+                        }
                         if let Some(label) = label {
                             write!(f, " {label}")?;
                         }
                     }
-                    QueryExpect::Error(err) => err.fmt_inline(f)?,
+                    QueryExpect::Error(err) => err.fmt_inline(f, connection)?,
                 }
+                fmt_warnings(f, warnings)?;
+                fmt_maxtime(f, maxtime)?;
                 if let Some(retry) = retry {
                     write!(
                         f,
@@ -326,6 +474,8 @@ impl<T: ColumnType> std::fmt::Display for Record<T> {
             Record::Subtest { loc: _, name } => {
                 write!(f, "subtest {name}")
             }
+            Record::Echo { loc: _, text } if text.is_empty() => write!(f, "echo"),
+            Record::Echo { loc: _, text } => write!(f, "echo {text}"),
             Record::Halt { loc: _ } => {
                 write!(f, "halt")
             }
@@ -333,6 +483,35 @@ impl<T: ColumnType> std::fmt::Display for Record<T> {
                 Control::SortMode(m) => write!(f, "control sortmode {}", m.as_str()),
                 Control::ResultMode(m) => write!(f, "control resultmode {}", m.as_str()),
                 Control::Substitution(s) => write!(f, "control substitution {}", s.as_str()),
+                Control::ErrorMode(m) => write!(f, "control error_mode {}", m.as_str()),
+                Control::HashNormalize(s) => write!(f, "control hash_normalize {}", s.as_str()),
+                Control::HashAlgo(a) => write!(f, "control hash_algo {}", a.as_str()),
+                Control::HashThreshold(t) => write!(f, "control hash-threshold {t}"),
+                Control::Scope(s) => write!(f, "control scope {}", s.as_str()),
+                Control::ForbidError(Some(pattern)) => write!(f, "control forbid_error {pattern}"),
+                Control::ForbidError(None) => write!(f, "control forbid_error off"),
+                Control::Repeat(n) => write!(f, "control repeat {n}"),
+                Control::RowSortNullsLast(s) => {
+                    write!(f, "control rowsort_nulls_last {}", s.as_str())
+                }
+                Control::Parallel(s) => write!(f, "control parallel {}", s.as_str()),
+                Control::Concurrent(s) => write!(f, "control concurrent {}", s.as_str()),
+                Control::Await {
+                    connection,
+                    record,
+                    timeout,
+                    loc: _,
+                } => {
+                    write!(
+                        f,
+                        "control await connection={} record={record}",
+                        connection.name()
+                    )?;
+                    if let Some(timeout) = timeout {
+                        write!(f, " timeout={}", humantime::format_duration(*timeout))?;
+                    }
+                    Ok(())
+                }
             },
             Record::Condition(cond) => match cond {
                 Condition::OnlyIf { label } => write!(f, "onlyif {label}"),
@@ -377,12 +556,24 @@ pub enum ExpectedError {
     /// The actual error message that's exactly the same as the expected one is considered as a
     /// match.
     Multiline(String),
+    /// An inline regular expression after `error debug:`.
+    ///
+    /// The actual error whose `Debug` form or chain of sources matches the regex is considered as
+    /// a match. Useful when the message of the top-level error is generic.
+    Debug(Regex),
 }
 
 impl ExpectedError {
-    /// Parses an inline regex variant from tokens.
+    /// Parses an inline regex variant from tokens, which is [`ExpectedError::Debug`] if prefixed
+    /// with `debug:`.
     fn parse_inline_tokens(tokens: &[&str]) -> Result<Self, ParseErrorKind> {
-        Self::new_inline(tokens.join(" "))
+        let regex = tokens.join(" ");
+        match regex.strip_prefix(DEBUG_ERROR_PREFIX) {
+            Some(debug) => Regex::new(debug)
+                .map(Self::Debug)
+                .map_err(|_| ParseErrorKind::InvalidErrorMessage(regex)),
+            None => Self::new_inline(regex),
+        }
     }
 
     /// Creates an inline expected error message from a regex string.
@@ -403,11 +594,18 @@ impl ExpectedError {
         matches!(self, Self::Empty)
     }
 
-    /// Unparses the expected message after `statement`.
-    fn fmt_inline(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Unparses the expected message after `statement`, with the inline connection if any.
+    fn fmt_inline(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        connection: Option<&Connection>,
+    ) -> fmt::Result {
         write!(f, "error")?;
-        if let Self::Inline(regex) = self {
-            write!(f, " {regex}")?;
+        fmt_inline_connection(f, connection)?;
+        match self {
+            Self::Inline(regex) => write!(f, " {regex}")?,
+            Self::Debug(regex) => write!(f, " {DEBUG_ERROR_PREFIX}{regex}")?,
+            Self::Empty | Self::Multiline(_) => {}
         }
         Ok(())
     }
@@ -428,6 +626,50 @@ impl ExpectedError {
             Self::Empty => true,
             Self::Inline(regex) => regex.is_match(err),
             Self::Multiline(results) => results.trim() == err.trim(),
+            Self::Debug(regex) => regex.is_match(err),
+        }
+    }
+
+    /// Returns whether the given error matches the expected one under the error mode.
+    ///
+    /// Unlike [`ExpectedError::is_match_with_mode`], [`ExpectedError::Debug`] is matched against
+    /// the `Debug` form and the chain of sources of the error.
+    pub fn is_match_error(
+        &self,
+        mode: ErrorMode,
+        err: &(dyn std::error::Error + 'static),
+        sqlstate: Option<&str>,
+    ) -> bool {
+        let Self::Debug(regex) = self else {
+            return self.is_match_with_mode(mode, &err.to_string(), sqlstate);
+        };
+        let chain = std::iter::successors(Some(err), |e| e.source()).join(": ");
+        regex.is_match(&format!("{err:?}")) || regex.is_match(&chain)
+    }
+
+    /// Returns whether the given error matches the expected one under the error mode.
+    ///
+    /// Only inline expected errors are affected by the mode. If the SQLSTATE is required but the
+    /// error has none, it doesn't match.
+    pub fn is_match_with_mode(&self, mode: ErrorMode, err: &str, sqlstate: Option<&str>) -> bool {
+        let Self::Inline(regex) = self else {
+            return self.is_match(err);
+        };
+        match mode {
+            ErrorMode::Message => self.is_match(err),
+            ErrorMode::SqlState => sqlstate == Some(regex.as_str()),
+            ErrorMode::Both => {
+                let (code, message) = regex
+                    .as_str()
+                    .split_once(' ')
+                    .unwrap_or((regex.as_str(), ""));
+                if !is_sqlstate(code) {
+                    return self.is_match(err);
+                }
+                sqlstate == Some(code)
+                    && (message.is_empty()
+                        || Regex::new(message).is_ok_and(|regex| regex.is_match(err)))
+            }
         }
     }
 
@@ -452,6 +694,34 @@ impl ExpectedError {
             Self::new_inline(regex::escape(actual_err)).expect("escaped regex should be valid")
         }
     }
+
+    /// Creates an expected error from the actual error like [`ExpectedError::from_actual_error`],
+    /// in the form matched under the error mode, i.e., the SQLSTATE code under
+    /// [`ErrorMode::SqlState`], and the code followed by the message under [`ErrorMode::Both`].
+    /// Falls back to the message if the error has no SQLSTATE.
+    pub fn from_actual_error_with_mode(
+        reference: Option<&Self>,
+        actual_err: &str,
+        mode: ErrorMode,
+        sqlstate: Option<&str>,
+    ) -> Self {
+        let expected = Self::from_actual_error(reference, actual_err);
+        let Some(code) = sqlstate else {
+            return expected;
+        };
+        match (mode, &expected) {
+            (ErrorMode::SqlState, _) => {
+                Self::new_inline(regex::escape(code)).expect("escaped regex should be valid")
+            }
+            (ErrorMode::Both, Self::Inline(regex)) => {
+                Self::new_inline(format!("{code} {regex}")).expect("escaped regex should be valid")
+            }
+            (ErrorMode::Both, Self::Empty) => {
+                Self::new_inline(regex::escape(code)).expect("escaped regex should be valid")
+            }
+            _ => expected,
+        }
+    }
 }
 
 impl std::fmt::Display for ExpectedError {
@@ -460,6 +730,7 @@ impl std::fmt::Display for ExpectedError {
             ExpectedError::Empty => write!(f, "(any)"),
             ExpectedError::Inline(regex) => write!(f, "(regex) {}", regex),
             ExpectedError::Multiline(results) => write!(f, "(multiline) {}", results.trim()),
+            ExpectedError::Debug(regex) => write!(f, "(debug regex) {}", regex),
         }
     }
 }
@@ -470,12 +741,13 @@ impl PartialEq for ExpectedError {
             (Self::Empty, Self::Empty) => true,
             (Self::Inline(l0), Self::Inline(r0)) => l0.as_str() == r0.as_str(),
             (Self::Multiline(l0), Self::Multiline(r0)) => l0 == r0,
+            (Self::Debug(l0), Self::Debug(r0)) => l0.as_str() == r0.as_str(),
             _ => false,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Control {
     /// Control sort mode.
@@ -484,8 +756,89 @@ pub enum Control {
     ResultMode(ResultMode),
     /// Control whether or not to substitute variables in the SQL.
     Substitution(bool),
+    /// Control how inline expected errors are matched against the actual errors.
+    ErrorMode(ErrorMode),
+    /// Control whether or not to normalize the values before hashing the results.
+    HashNormalize(bool),
+    /// Control the algorithm to hash the results with.
+    HashAlgo(HashAlgo),
+    /// Set the hash threshold, the same as the `hash-threshold` record.
+    HashThreshold(u64),
+    /// Control the isolation scope of the changes made by the records.
+    Scope(Scope),
+    /// Fail the subsequent statements and queries with errors matching the regex pattern, even if
+    /// they are expected to fail. `None` (`control forbid_error off`) clears the pattern.
+    ForbidError(Option<Regex>),
+    /// Run the next statement or query the given number of times. The output of every run is
+    /// validated.
+    Repeat(u64),
+    /// Control whether `rowsort` places the rows with `NULL` values after those with non-NULL
+    /// values in the same column, as `ORDER BY ... NULLS LAST` does, instead of sorting `NULL`
+    /// as text.
+    RowSortNullsLast(bool),
+    /// Control whether consecutive queries run concurrently on new connections. See
+    /// [`Runner::take_parallel_queries`](crate::Runner::take_parallel_queries).
+    Parallel(bool),
+    /// Control whether the statements and queries on named connections run in the background,
+    /// so that the records after them go on while they are, e.g., blocked by a lock. See
+    /// [`Runner::take_concurrent_records`](crate::Runner::take_concurrent_records).
+    Concurrent(bool),
+    /// Wait until the connection has run at least `record` statements or queries, to order the
+    /// side effects across connections. Under `control concurrent on`, it fails if the connection
+    /// doesn't get there within `timeout`, 10 seconds by default. Otherwise, records run one at a
+    /// time in the order of the file, so it fails right away if the connection hasn't got there.
+    Await {
+        connection: Connection,
+        record: usize,
+        timeout: Option<Duration>,
+        loc: Location,
+    },
+}
+
+impl PartialEq for Control {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::SortMode(l0), Self::SortMode(r0)) => l0 == r0,
+            (Self::ResultMode(l0), Self::ResultMode(r0)) => l0 == r0,
+            (Self::Substitution(l0), Self::Substitution(r0)) => l0 == r0,
+            (Self::ErrorMode(l0), Self::ErrorMode(r0)) => l0 == r0,
+            (Self::HashNormalize(l0), Self::HashNormalize(r0)) => l0 == r0,
+            (Self::HashAlgo(l0), Self::HashAlgo(r0)) => l0 == r0,
+            (Self::HashThreshold(l0), Self::HashThreshold(r0)) => l0 == r0,
+            (Self::Scope(l0), Self::Scope(r0)) => l0 == r0,
+            (Self::ForbidError(l0), Self::ForbidError(r0)) => {
+                l0.as_ref().map(Regex::as_str) == r0.as_ref().map(Regex::as_str)
+            }
+            (Self::Repeat(l0), Self::Repeat(r0)) => l0 == r0,
+            (Self::RowSortNullsLast(l0), Self::RowSortNullsLast(r0)) => l0 == r0,
+            (Self::Parallel(l0), Self::Parallel(r0)) => l0 == r0,
+            (Self::Concurrent(l0), Self::Concurrent(r0)) => l0 == r0,
+            (
+                Self::Await {
+                    connection: l_connection,
+                    record: l_record,
+                    timeout: l_timeout,
+                    loc: l_loc,
+                },
+                Self::Await {
+                    connection: r_connection,
+                    record: r_record,
+                    timeout: r_timeout,
+                    loc: r_loc,
+                },
+            ) => {
+                l_connection == r_connection
+                    && l_record == r_record
+                    && l_timeout == r_timeout
+                    && l_loc == r_loc
+            }
+            _ => false,
+        }
+    }
 }
 
+impl Eq for Control {}
+
 trait ControlItem: Sized {
     /// Try to parse from string.
     fn try_from_str(s: &str) -> Result<Self, ParseErrorKind>;
@@ -520,6 +873,12 @@ pub enum Injected {
     /// Pseudo control command to indicate the end of an include statement. Automatically injected
     /// by sqllogictest parser.
     EndInclude(String),
+    /// Pseudo control command to bind the arguments of an include statement as variables, right
+    /// after `BeginInclude`. Automatically injected by sqllogictest parser.
+    PushVariables(Vec<(String, String)>),
+    /// Pseudo control command to unbind the variables of the last `PushVariables`, right before
+    /// `EndInclude`. Automatically injected by sqllogictest parser.
+    PopVariables,
 }
 
 /// The condition to run a query.
@@ -532,6 +891,13 @@ pub enum Condition {
 }
 
 impl Condition {
+    /// Returns the label of the condition.
+    pub fn label(&self) -> &str {
+        match self {
+            Condition::OnlyIf { label } | Condition::SkipIf { label } => label,
+        }
+    }
+
     /// Evaluate condition on given `label`, returns whether to skip this record.
     pub(crate) fn should_skip<'a>(&'a self, labels: impl IntoIterator<Item = &'a str>) -> bool {
         match self {
@@ -558,6 +924,134 @@ impl Connection {
             name => Self::Named(name.to_owned()),
         }
     }
+
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Self::Default => "default",
+            Self::Named(name) => name,
+        }
+    }
+}
+
+/// Unparses the inline `connection=<name>` of a record, if any.
+fn fmt_inline_connection(
+    f: &mut fmt::Formatter<'_>,
+    connection: Option<&Connection>,
+) -> fmt::Result {
+    if let Some(connection) = connection {
+        write!(f, " connection={}", connection.name())?;
+    }
+    Ok(())
+}
+
+/// Parses the inline `connection=<name>` at the beginning of the tokens, if any.
+fn parse_inline_connection<'a>(
+    tokens: &'a [&'a str],
+) -> Result<(Option<Connection>, &'a [&'a str]), ParseErrorKind> {
+    match tokens {
+        [token, rest @ ..] => match token.strip_prefix("connection=") {
+            Some("") => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+            Some(name) => Ok((Some(Connection::new(name)), rest)),
+            None => Ok((None, tokens)),
+        },
+        [] => Ok((None, tokens)),
+    }
+}
+
+/// Parses the column indices of `cols=<i>,<j>,...`, each of which must be less than the number of
+/// columns in the type string.
+fn parse_columns(list: &str, num_columns: usize) -> Result<Vec<usize>, ParseErrorKind> {
+    list.split(',')
+        .map(|index| {
+            let index = index
+                .parse::<usize>()
+                .map_err(|_| ParseErrorKind::InvalidNumber(index.to_string()))?;
+            if index >= num_columns {
+                return Err(ParseErrorKind::ColumnOutOfRange { index, num_columns });
+            }
+            Ok(index)
+        })
+        .collect()
+}
+
+/// The expected number of warnings emitted by a statement or query, checked against
+/// [`AsyncDB::take_warnings`](crate::AsyncDB::take_warnings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedWarnings {
+    /// Exactly the given number of warnings, set by `warnings <n>`.
+    Exactly(usize),
+    /// More than the given number of warnings, set by `warnings ><n>`.
+    MoreThan(usize),
+}
+
+impl ExpectedWarnings {
+    /// Whether the actual number of warnings is expected.
+    pub fn matches(&self, count: usize) -> bool {
+        match *self {
+            Self::Exactly(n) => count == n,
+            Self::MoreThan(n) => count > n,
+        }
+    }
+}
+
+impl fmt::Display for ExpectedWarnings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exactly(n) => write!(f, "{n}"),
+            Self::MoreThan(n) => write!(f, ">{n}"),
+        }
+    }
+}
+
+/// Parses the optional `warnings <n>` or `warnings ><n>` at the beginning of the tokens,
+/// returning the rest.
+fn parse_warnings<'a>(
+    tokens: &'a [&'a str],
+) -> Result<(Option<ExpectedWarnings>, &'a [&'a str]), ParseErrorKind> {
+    match tokens {
+        ["warnings", count, rest @ ..] => {
+            let parse = |n: &str| {
+                n.parse::<usize>()
+                    .map_err(|_| ParseErrorKind::InvalidNumber(count.to_string()))
+            };
+            let warnings = match count.strip_prefix('>') {
+                Some(n) => ExpectedWarnings::MoreThan(parse(n)?),
+                None => ExpectedWarnings::Exactly(parse(count)?),
+            };
+            Ok((Some(warnings), rest))
+        }
+        ["warnings"] => Err(ParseErrorKind::InvalidNumber(String::new())),
+        _ => Ok((None, tokens)),
+    }
+}
+
+fn fmt_warnings(f: &mut fmt::Formatter<'_>, warnings: &Option<ExpectedWarnings>) -> fmt::Result {
+    if let Some(warnings) = warnings {
+        write!(f, " warnings {warnings}")?;
+    }
+    Ok(())
+}
+
+/// Parses the optional `maxtime <duration>` at the beginning of the tokens, returning the rest.
+fn parse_maxtime<'a>(
+    tokens: &'a [&'a str],
+) -> Result<(Option<Duration>, &'a [&'a str]), ParseErrorKind> {
+    match tokens {
+        ["maxtime", duration, rest @ ..] => {
+            let duration = humantime::parse_duration(duration)
+                .map_err(|_| ParseErrorKind::InvalidDuration(duration.to_string()))?;
+            Ok((Some(duration), rest))
+        }
+        ["maxtime"] => Err(ParseErrorKind::InvalidDuration(String::new())),
+        _ => Ok((None, tokens)),
+    }
+}
+
+fn fmt_maxtime(f: &mut fmt::Formatter<'_>, maxtime: &Option<Duration>) -> fmt::Result {
+    if let Some(maxtime) = maxtime {
+        write!(f, " maxtime {}", humantime::format_duration(*maxtime))?;
+    }
+    Ok(())
 }
 
 /// Whether to apply sorting before checking the results of a query.
@@ -624,6 +1118,134 @@ impl fmt::Display for ResultMode {
     }
 }
 
+/// How an inline expected error (`statement error <expected>`) is matched against the actual
+/// error.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorMode {
+    /// The default option. The expected error is a regex matching the error message.
+    #[default]
+    Message,
+    /// The expected error is the SQLSTATE code of the error, e.g., `42P01`.
+    SqlState,
+    /// The expected error is an optional SQLSTATE code followed by a regex matching the error
+    /// message, e.g., `42P01 relation .* does not exist`. Both must match.
+    Both,
+}
+
+impl ControlItem for ErrorMode {
+    fn try_from_str(s: &str) -> Result<Self, ParseErrorKind> {
+        match s {
+            "message" => Ok(Self::Message),
+            "sqlstate" => Ok(Self::SqlState),
+            "both" => Ok(Self::Both),
+            _ => Err(ParseErrorKind::InvalidControl(s.to_string())),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Message => "message",
+            Self::SqlState => "sqlstate",
+            Self::Both => "both",
+        }
+    }
+}
+
+impl fmt::Display for ErrorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The isolation scope of the changes made by the records.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Scope {
+    /// The default option. Changes are visible until the end of the file.
+    #[default]
+    File,
+    /// Each `subtest` is wrapped in a savepoint on the default connection, which is rolled back
+    /// when the next `subtest` begins or the file ends, so that the changes made by a subtest are
+    /// not visible to the following ones.
+    Subtest,
+}
+
+impl ControlItem for Scope {
+    fn try_from_str(s: &str) -> Result<Self, ParseErrorKind> {
+        match s {
+            "file" => Ok(Self::File),
+            "subtest" => Ok(Self::Subtest),
+            _ => Err(ParseErrorKind::InvalidControl(s.to_string())),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Subtest => "subtest",
+        }
+    }
+}
+
+/// The algorithm to hash the results of queries with, under `hashed` or when the hash threshold is
+/// exceeded.
+///
+/// The hashes of algorithms other than MD5 are prefixed by the algorithm, e.g., `sha256:<hex>`, so
+/// that a hash computed by another algorithm never matches.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum HashAlgo {
+    /// The default option, compatible with other sqllogictest implementations.
+    #[default]
+    Md5,
+    /// SHA-256, for where MD5 is not allowed.
+    Sha256,
+    /// The 128-bit XXH3, which is much faster than the others for large results.
+    Xxh3,
+}
+
+impl HashAlgo {
+    /// The number of hex digits of a hash.
+    fn hex_len(self) -> usize {
+        match self {
+            Self::Md5 | Self::Xxh3 => 32,
+            Self::Sha256 => 64,
+        }
+    }
+
+    /// Returns whether the string is a hash of this algorithm, with the prefix if any.
+    pub(crate) fn is_hash(self, s: &str) -> bool {
+        let hex = match self {
+            Self::Md5 => s,
+            _ => match s
+                .strip_prefix(self.as_str())
+                .and_then(|s| s.strip_prefix(':'))
+            {
+                Some(hex) => hex,
+                None => return false,
+            },
+        };
+        hex.len() == self.hex_len() && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+impl ControlItem for HashAlgo {
+    fn try_from_str(s: &str) -> Result<Self, ParseErrorKind> {
+        match s {
+            "md5" => Ok(Self::Md5),
+            "sha256" => Ok(Self::Sha256),
+            "xxh3" => Ok(Self::Xxh3),
+            _ => Err(ParseErrorKind::InvalidControl(s.to_string())),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha256 => "sha256",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+}
+
 /// The error type for parsing sqllogictest.
 #[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
 #[error("parse error at {loc}: {kind}")]
@@ -656,6 +1278,13 @@ pub enum ParseErrorKind {
     InvalidSortMode(String),
     #[error("invalid line: {0:?}")]
     InvalidLine(String),
+    #[error("`{directive}` expects exactly one {expected}, got {actual}")]
+    WrongArity {
+        directive: String,
+        /// What the argument is, e.g., `label`.
+        expected: String,
+        actual: usize,
+    },
     #[error("invalid type character: {0:?} in type string")]
     InvalidType(char),
     #[error("invalid number: {0:?}")]
@@ -678,6 +1307,23 @@ pub enum ParseErrorKind {
     EmptyIncludeFile(String),
     #[error("no such file")]
     FileNotFound,
+    #[error("failed to read file: {message}")]
+    ReadFile {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+    #[error("invalid UTF-8 at byte offset {byte_offset}")]
+    InvalidUtf8 { byte_offset: usize },
+    #[error("expected a single hash as the results of a `hashed` query, got: {0:?}")]
+    InvalidHashedResults(String),
+    #[error("invalid include argument {0:?}, expected `name=value`")]
+    InvalidIncludeArgument(String),
+    #[error("column {index} in `cols=` is out of range for the {num_columns} columns in the type string")]
+    ColumnOutOfRange { index: usize, num_columns: usize },
+    #[error("invalid requirement {0:?}, expected `<label>` or `<label>>=<version>`")]
+    InvalidRequirement(String),
+    #[error("`{0}` can't be used along with `{1}`")]
+    ConflictingOptions(&'static str, &'static str),
 }
 
 impl ParseErrorKind {
@@ -686,6 +1332,214 @@ impl ParseErrorKind {
     }
 }
 
+/// A problem in a sqllogictest script that doesn't prevent it from running, but likely makes it
+/// not test what the author intends. See [`check_records`].
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
+#[error("warning at {loc}: {kind}")]
+pub struct ParseWarning {
+    kind: ParseWarningKind,
+    loc: Location,
+}
+
+impl ParseWarning {
+    /// Returns the corresponding [`ParseWarningKind`] for this warning.
+    pub fn kind(&self) -> ParseWarningKind {
+        self.kind.clone()
+    }
+
+    /// Returns the location of the record the warning is about.
+    pub fn location(&self) -> Location {
+        self.loc.clone()
+    }
+}
+
+/// The warning kind for sqllogictest scripts.
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum ParseWarningKind {
+    #[error("unreachable record after `halt` at {halt}")]
+    UnreachableAfterHalt { halt: Location },
+}
+
+/// Checks the parsed records for problems that don't prevent them from running, e.g., records
+/// after a `halt`, which never run.
+pub fn check_records<T: ColumnType>(records: &[Record<T>]) -> Vec<ParseWarning> {
+    let mut warnings = vec![];
+    let mut halt: Option<Location> = None;
+    for record in records {
+        let Some(loc) = record.loc() else {
+            continue;
+        };
+        match &halt {
+            Some(halt) => warnings.push(ParseWarning {
+                kind: ParseWarningKind::UnreachableAfterHalt { halt: halt.clone() },
+                loc: loc.clone(),
+            }),
+            None if matches!(record, Record::Halt { .. }) => halt = Some(loc.clone()),
+            None => {}
+        }
+    }
+    warnings
+}
+
+/// Returns the number of statements and queries in the records that run, i.e., not after a
+/// `halt`. A test file without any is usually blanked by mistake.
+pub fn count_executable_records<T: ColumnType>(records: &[Record<T>]) -> usize {
+    records
+        .iter()
+        .take_while(|record| !matches!(record, Record::Halt { .. }))
+        .filter(|record| matches!(record, Record::Statement { .. } | Record::Query { .. }))
+        .count()
+}
+
+/// The metadata of a test file, from the `# <key>: <value>` comments in the block of comments at
+/// its beginning, i.e., before the first blank line or record. Comments with other keys, or
+/// without keys, are plain comments.
+///
+/// ```text
+/// # slt-version: 2
+/// # requires: postgres>=14, replica
+/// # xfail: wrong results of `NULLS FIRST`, see #123
+/// ```
+///
+/// The header comments are still parsed as [`Record::Comment`]s, so that they are kept by
+/// `--override`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FileMetadata {
+    /// The version of the format the file is written in, from `# slt-version: <version>`.
+    pub slt_version: Option<String>,
+    /// The requirements to run the file, from `# requires: <requirement>, ...`, which can be
+    /// repeated.
+    pub requires: Vec<Requirement>,
+    /// The reason why the file is expected to fail, from `# xfail: <reason>`. The CLI reports
+    /// such a file as an expected failure if it fails, and as an unexpected pass otherwise, to
+    /// track known-broken tests without deleting them.
+    pub xfail: Option<String>,
+}
+
+/// A requirement to run a test file, `<label>` or `<label>>=<version>`. See
+/// [`Runner::unmet_requirements`](crate::Runner::unmet_requirements).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    /// The label that must be set.
+    pub label: String,
+    /// The minimum version of the label, compared by the dot-separated numbers.
+    pub min_version: Option<String>,
+}
+
+impl Requirement {
+    /// Returns whether the requirement is met by the label with the given version, if any.
+    pub fn is_met_by(&self, label: &str, version: Option<&str>) -> bool {
+        if label != self.label {
+            return false;
+        }
+        match (&self.min_version, version) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(min_version), Some(version)) => {
+                match (parse_version(version), parse_version(min_version)) {
+                    (Some(version), Some(min_version)) => version >= min_version,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)?;
+        if let Some(min_version) = &self.min_version {
+            write!(f, ">={min_version}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a requirement in the `# requires:` header.
+fn parse_requirement(s: &str) -> Result<Requirement, ParseErrorKind> {
+    let invalid = || ParseErrorKind::InvalidRequirement(s.to_string());
+    let (label, min_version) = match s.split_once(">=") {
+        Some((label, version)) => {
+            parse_version(version).ok_or_else(invalid)?;
+            (label, Some(version.to_string()))
+        }
+        None => (s, None),
+    };
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok(Requirement {
+        label: label.to_string(),
+        min_version,
+    })
+}
+
+/// Parses a version like `14.2` into its numbers, so that they can be compared.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|n| n.parse().ok()).collect()
+}
+
+/// A parsed test file, with its [`FileMetadata`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedFile<T: ColumnType> {
+    pub metadata: FileMetadata,
+    pub records: Vec<Record<T>>,
+}
+
+/// Parses the [`FileMetadata`] in the header comments of the script.
+fn parse_metadata(loc: &Location, script: &str) -> Result<FileMetadata, ParseError> {
+    let mut metadata = FileMetadata::default();
+    for (num, line) in script.lines().enumerate() {
+        let Some(comment) = line.trim().strip_prefix('#') else {
+            break;
+        };
+        let loc = Location {
+            line: num as u32 + 1,
+            ..loc.clone()
+        };
+        match comment.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("slt-version", version)) => metadata.slt_version = Some(version.to_string()),
+            Some(("xfail", reason)) => metadata.xfail = Some(reason.to_string()),
+            Some(("requires", requirements)) => {
+                for requirement in requirements.split(',') {
+                    let requirement =
+                        parse_requirement(requirement.trim()).map_err(|k| k.at(loc.clone()))?;
+                    metadata.requires.push(requirement);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Parse a sqllogictest script into a list of records, along with the [`FileMetadata`] in its
+/// header comments.
+pub fn parse_with_metadata<T: ColumnType>(script: &str) -> Result<ParsedFile<T>, ParseError> {
+    let loc = Location::new("<unknown>", 0);
+    Ok(ParsedFile {
+        metadata: parse_metadata(&loc, script)?,
+        records: parse_inner(&loc, script)?,
+    })
+}
+
+/// Parse a sqllogictest file like [`parse_file`], along with the [`FileMetadata`] in its header
+/// comments. The header comments of the included files are ignored.
+pub fn parse_file_with_metadata<T: ColumnType>(
+    filename: impl AsRef<Path>,
+) -> Result<ParsedFile<T>, ParseError> {
+    let path = filename.as_ref();
+    let loc = Location::new(path.to_string_lossy(), 0);
+    let script = read_file(path, &loc)?;
+    let records = parse_file_script(path, &loc, &script)?;
+    Ok(ParsedFile {
+        metadata: parse_metadata(&loc, &script)?,
+        records,
+    })
+}
+
 /// Parse a sqllogictest script into a list of records.
 pub fn parse<T: ColumnType>(script: &str) -> Result<Vec<Record<T>>, ParseError> {
     parse_inner(&Location::new("<unknown>", 0), script)
@@ -699,10 +1553,21 @@ pub fn parse_with_name<T: ColumnType>(
     parse_inner(&Location::new(name, 0), script)
 }
 
-#[allow(clippy::collapsible_match)]
 fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record<T>>, ParseError> {
     let mut lines = script.lines().enumerate().peekable();
     let mut records = vec![];
+    parse_records(loc, &mut lines, &mut records)?;
+    Ok(records)
+}
+
+/// Parse the lines into records, until the end or the first error. The records parsed before the
+/// error are kept in `records`, and `lines` is left after the erroneous line.
+#[allow(clippy::collapsible_match)]
+fn parse_records<'a, T: ColumnType>(
+    loc: &Location,
+    lines: &mut Peekable<impl Iterator<Item = (usize, &'a str)>>,
+    records: &mut Vec<Record<T>>,
+) -> Result<(), ParseError> {
     let mut conditions = vec![];
     let mut connection = Connection::Default;
     let mut comments = vec![];
@@ -730,13 +1595,53 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
         let mut loc = loc.clone();
         loc.line = num as u32 + 1;
 
-        let tokens: Vec<&str> = line.split_whitespace().collect();
-        match tokens.as_slice() {
-            [] => continue,
-            ["include", included] => records.push(Record::Include {
-                loc,
-                filename: included.to_string(),
-            }),
+        // A trailing backslash continues the directive onto the next line.
+        let continued;
+        let line = match line.strip_suffix('\\') {
+            Some(first) => {
+                let mut directive = first.to_owned();
+                let mut last = line;
+                while last.ends_with('\\') {
+                    let Some((_, next)) = lines.next() else {
+                        break;
+                    };
+                    directive.push(' ');
+                    directive.push_str(next.strip_suffix('\\').unwrap_or(next));
+                    last = next;
+                }
+                continued = directive;
+                continued.as_str()
+            }
+            None => line,
+        };
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            [] => continue,
+            ["include", ..] => {
+                let included = line.trim_start()["include".len()..].trim();
+                let (filename, args) = match included.split_once('(') {
+                    Some((filename, args)) => {
+                        let Some(args) = args.strip_suffix(')') else {
+                            return Err(ParseErrorKind::InvalidLine(line.into()).at(loc));
+                        };
+                        (
+                            filename.trim(),
+                            parse_include_args(args).map_err(|k| k.at(loc.clone()))?,
+                        )
+                    }
+                    None => (included, vec![]),
+                };
+                let num_filenames = filename.split_whitespace().count();
+                if num_filenames != 1 {
+                    return Err(wrong_arity("include", "file pattern", num_filenames).at(loc));
+                }
+                records.push(Record::Include {
+                    loc,
+                    filename: filename.to_string(),
+                    args,
+                })
+            }
             ["halt"] => {
                 records.push(Record::Halt { loc });
             }
@@ -746,6 +1651,13 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                     name: name.to_string(),
                 });
             }
+            ["echo", ..] => {
+                let text = line.trim_start().strip_prefix("echo").unwrap().trim();
+                records.push(Record::Echo {
+                    loc,
+                    text: text.to_string(),
+                });
+            }
             ["sleep", dur] => {
                 records.push(Record::Sleep {
                     duration: humantime::parse_duration(dur).map_err(|_| {
@@ -773,38 +1685,62 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                 connection = conn.clone();
                 records.push(Record::Connection(conn));
             }
+            [directive @ ("skipif" | "onlyif"), args @ ..] => {
+                return Err(wrong_arity(directive, "label", args.len()).at(loc));
+            }
+            [directive @ ("subtest" | "connection"), args @ ..] => {
+                return Err(wrong_arity(directive, "name", args.len()).at(loc));
+            }
+            ["sleep", args @ ..] => {
+                return Err(wrong_arity("sleep", "duration", args.len()).at(loc));
+            }
             ["statement", res @ ..] => {
-                let (mut expected, res) = match res {
-                    ["ok", retry @ ..] => (StatementExpect::Ok, retry),
+                let (mut expected, inline_connection, res) = match res {
+                    ["ok", res @ ..] => {
+                        let (conn, retry) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
+                        (StatementExpect::Ok, conn, retry)
+                    }
                     ["error", res @ ..] => {
+                        let (conn, res) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
                         if res.len() == 4 && res[0] == "retry" && res[2] == "backoff" {
                             // `statement error retry <num> backoff <duration>`
                             // To keep syntax simple, let's assume the error message must be multiline.
-                            (StatementExpect::Error(ExpectedError::Empty), res)
+                            (StatementExpect::Error(ExpectedError::Empty), conn, res)
                         } else {
                             let error = ExpectedError::parse_inline_tokens(res)
                                 .map_err(|e| e.at(loc.clone()))?;
-                            (StatementExpect::Error(error), &[][..])
+                            (StatementExpect::Error(error), conn, &[][..])
                         }
                     }
-                    ["count", count_str, retry @ ..] => {
+                    ["count", count_str, res @ ..] => {
                         let count = count_str.parse::<u64>().map_err(|_| {
                             ParseErrorKind::InvalidNumber((*count_str).into()).at(loc.clone())
                         })?;
-                        (StatementExpect::Count(count), retry)
+                        let (conn, retry) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
+                        (StatementExpect::Count(count), conn, retry)
+                    }
+                    ["batch", res @ ..] => {
+                        let (conn, retry) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
+                        (StatementExpect::Batch, conn, retry)
                     }
                     _ => return Err(ParseErrorKind::InvalidLine(line.into()).at(loc)),
                 };
 
+                let (warnings, res) = parse_warnings(res).map_err(|e| e.at(loc.clone()))?;
+                let (maxtime, res) = parse_maxtime(res).map_err(|e| e.at(loc.clone()))?;
                 let retry = parse_retry_config(res).map_err(|e| e.at(loc.clone()))?;
 
-                let (sql, has_results) = parse_lines(&mut lines, &loc, Some(RESULTS_DELIMITER))?;
+                let (sql, has_results) = parse_lines(lines, &loc, Some(RESULTS_DELIMITER))?;
 
                 if has_results {
                     if let StatementExpect::Error(e) = &mut expected {
                         // If no inline error message is specified, it might be a multiline error.
                         if e.is_empty() {
-                            *e = parse_multiline_error(&mut lines);
+                            *e = parse_multiline_error(lines);
                         } else {
                             return Err(ParseErrorKind::DuplicatedErrorMessage.at(loc.clone()));
                         }
@@ -813,31 +1749,39 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                     }
                 }
 
+                let preceding_connection = std::mem::take(&mut connection);
                 records.push(Record::Statement {
                     loc,
                     conditions: std::mem::take(&mut conditions),
-                    connection: std::mem::take(&mut connection),
+                    inline_connection: inline_connection.is_some(),
+                    connection: inline_connection.unwrap_or(preceding_connection),
                     sql,
                     expected,
+                    maxtime,
+                    warnings,
                     retry,
                 });
             }
             ["query", res @ ..] => {
-                let (mut expected, res) = match res {
+                let (mut expected, inline_connection, res) = match res {
                     ["error", res @ ..] => {
+                        let (conn, res) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
                         if res.len() == 4 && res[0] == "retry" && res[2] == "backoff" {
                             // `query error retry <num> backoff <duration>`
                             // To keep syntax simple, let's assume the error message must be multiline.
-                            (QueryExpect::Error(ExpectedError::Empty), res)
+                            (QueryExpect::Error(ExpectedError::Empty), conn, res)
                         } else {
                             let error = ExpectedError::parse_inline_tokens(res)
                                 .map_err(|e| e.at(loc.clone()))?;
-                            (QueryExpect::Error(error), &[][..])
+                            (QueryExpect::Error(error), conn, &[][..])
                         }
                     }
                     [type_str, res @ ..] => {
-                        // query <type-string> [<sort-mode>] [<label>] [retry <attempts> backoff <backoff>]
-                        let types = type_str
+                        // query <type-string> [connection=<name>] [<sort-mode>] [hashed] [nocase[=off]] [sigfig=<n>] [cols=<i>,<j>,...] [resultset=<n>] [<label>] [warnings [>]<n>] [maxtime <duration>] [retry <attempts> backoff <backoff>]
+                        let (conn, res) =
+                            parse_inline_connection(res).map_err(|e| e.at(loc.clone()))?;
+                        let types: Vec<T> = type_str
                             .chars()
                             .map(|ch| {
                                 T::from_char(ch)
@@ -846,14 +1790,61 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                             .try_collect()?;
                         let sort_mode = res.first().and_then(|&s| SortMode::try_from_str(s).ok()); // Could be `retry` or label
 
-                        // To support `retry`, we assume the label must *not* be "retry"
-                        let label_start = if sort_mode.is_some() { 1 } else { 0 };
+                        // To support `warnings`, `maxtime` and `retry`, we assume the label must *not* be them
+                        let hashed_start = if sort_mode.is_some() { 1 } else { 0 };
+                        let res = &res[hashed_start..];
+                        let hashed = res.first() == Some(&"hashed");
+
+                        let nocase_start = if hashed { 1 } else { 0 };
+                        let res = &res[nocase_start..];
+                        let nocase = match res.first() {
+                            Some(&"nocase") => Some(true),
+                            Some(&"nocase=off") => Some(false),
+                            _ => None,
+                        };
+
+                        let sigfig_start = if nocase.is_some() { 1 } else { 0 };
+                        let res = &res[sigfig_start..];
+                        let sigfig = match res.first().and_then(|s| s.strip_prefix("sigfig=")) {
+                            Some(n) => {
+                                Some(n.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(
+                                    || ParseErrorKind::InvalidNumber(n.to_string()).at(loc.clone()),
+                                )?)
+                            }
+                            None => None,
+                        };
+                        if hashed && sigfig.is_some() {
+                            // The hash is computed from the values as is.
+                            return Err(ParseErrorKind::ConflictingOptions("hashed", "sigfig")
+                                .at(loc.clone()));
+                        }
+
+                        let columns_start = if sigfig.is_some() { 1 } else { 0 };
+                        let res = &res[columns_start..];
+                        let columns = match res.first().and_then(|s| s.strip_prefix("cols=")) {
+                            Some(list) => Some(
+                                parse_columns(list, types.len()).map_err(|e| e.at(loc.clone()))?,
+                            ),
+                            None => None,
+                        };
+
+                        let result_set_start = if columns.is_some() { 1 } else { 0 };
+                        let res = &res[result_set_start..];
+                        let result_set =
+                            match res.first().and_then(|s| s.strip_prefix("resultset=")) {
+                                Some(n) => Some(n.parse::<usize>().map_err(|_| {
+                                    ParseErrorKind::InvalidNumber(n.to_string()).at(loc.clone())
+                                })?),
+                                None => None,
+                            };
+
+                        let label_start = if result_set.is_some() { 1 } else { 0 };
                         let res = &res[label_start..];
                         let label = res.first().and_then(|&s| {
-                            if s != "retry" {
+                            if s != "retry" && s != "maxtime" && s != "warnings" {
                                 Some(s.to_owned())
                             } else {
-                                None // `retry` is not a valid label
+                                None // `retry`, `maxtime` and `warnings` are not valid labels
                             }
                         });
 
@@ -865,47 +1856,67 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                                 sort_mode,
                                 result_mode: None,
                                 label,
+                                hashed,
+                                nocase,
+                                sigfig,
+                                columns,
+                                result_set,
                                 results: Vec::new(),
                             },
+                            conn,
                             res,
                         )
                     }
-                    [] => (QueryExpect::empty_results(), &[][..]),
+                    [] => (QueryExpect::empty_results(), None, &[][..]),
                 };
 
+                let (warnings, res) = parse_warnings(res).map_err(|e| e.at(loc.clone()))?;
+                let (maxtime, res) = parse_maxtime(res).map_err(|e| e.at(loc.clone()))?;
                 let retry = parse_retry_config(res).map_err(|e| e.at(loc.clone()))?;
 
                 // The SQL for the query is found on second and subsequent lines of the record
                 // up to first line of the form "----" or until the end of the record.
-                let (sql, has_result) = parse_lines(&mut lines, &loc, Some(RESULTS_DELIMITER))?;
+                let (sql, has_result) = parse_lines(lines, &loc, Some(RESULTS_DELIMITER))?;
                 if has_result {
                     match &mut expected {
                         // Lines following the "----" are expected results of the query, one value
                         // per line.
-                        QueryExpect::Results { results, .. } => {
-                            for (_, line) in &mut lines {
+                        QueryExpect::Results {
+                            results, hashed, ..
+                        } => {
+                            for (_, line) in &mut *lines {
                                 if line.is_empty() {
                                     break;
                                 }
                                 results.push(line.to_string());
                             }
+                            if *hashed && !results.is_empty() && !is_hash(results) {
+                                return Err(ParseErrorKind::InvalidHashedResults(
+                                    results.join("\n"),
+                                )
+                                .at(loc.clone()));
+                            }
                         }
                         // If no inline error message is specified, it might be a multiline error.
                         QueryExpect::Error(e) => {
                             if e.is_empty() {
-                                *e = parse_multiline_error(&mut lines);
+                                *e = parse_multiline_error(lines);
                             } else {
                                 return Err(ParseErrorKind::DuplicatedErrorMessage.at(loc.clone()));
                             }
                         }
                     }
                 }
+                let preceding_connection = std::mem::take(&mut connection);
                 records.push(Record::Query {
                     loc,
                     conditions: std::mem::take(&mut conditions),
-                    connection: std::mem::take(&mut connection),
+                    inline_connection: inline_connection.is_some(),
+                    connection: inline_connection.unwrap_or(preceding_connection),
                     sql,
                     expected,
+                    maxtime,
+                    warnings,
                     retry,
                 });
             }
@@ -915,9 +1926,9 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                 // TODO: we don't support asserting error message for system command
                 // The command is found on second and subsequent lines of the record
                 // up to first line of the form "----" or until the end of the record.
-                let (command, has_result) = parse_lines(&mut lines, &loc, Some(RESULTS_DELIMITER))?;
+                let (command, has_result) = parse_lines(lines, &loc, Some(RESULTS_DELIMITER))?;
                 let stdout = if has_result {
-                    Some(parse_multiple_result(&mut lines))
+                    Some(parse_multiple_result(lines))
                 } else {
                     None
                 };
@@ -944,6 +1955,106 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
                     Ok(on_off) => records.push(Record::Control(Control::Substitution(on_off))),
                     Err(k) => return Err(k.at(loc)),
                 },
+                ["hash_normalize", on_off] => match bool::try_from_str(on_off) {
+                    Ok(on_off) => records.push(Record::Control(Control::HashNormalize(on_off))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["hash_algo", algo] => match HashAlgo::try_from_str(algo) {
+                    Ok(algo) => records.push(Record::Control(Control::HashAlgo(algo))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["rowsort_nulls_last", on_off] => match bool::try_from_str(on_off) {
+                    Ok(on_off) => records.push(Record::Control(Control::RowSortNullsLast(on_off))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["parallel", on_off] => match bool::try_from_str(on_off) {
+                    Ok(on_off) => records.push(Record::Control(Control::Parallel(on_off))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["error_mode", error_mode] => match ErrorMode::try_from_str(error_mode) {
+                    Ok(error_mode) => records.push(Record::Control(Control::ErrorMode(error_mode))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["scope", scope] => match Scope::try_from_str(scope) {
+                    Ok(scope) => records.push(Record::Control(Control::Scope(scope))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["repeat", n] => match n.parse::<u64>() {
+                    Ok(n) if n > 0 => records.push(Record::Control(Control::Repeat(n))),
+                    _ => return Err(ParseErrorKind::InvalidNumber((*n).into()).at(loc)),
+                },
+                ["forbid_error", "off"] => {
+                    records.push(Record::Control(Control::ForbidError(None)))
+                }
+                ["forbid_error", _, ..] => {
+                    // Take the rest of the line as is, since whitespace is significant in regexes.
+                    let pattern = (line.trim().strip_prefix("control").unwrap())
+                        .trim_start()
+                        .strip_prefix("forbid_error")
+                        .unwrap()
+                        .trim()
+                        .to_string();
+                    let Ok(regex) = Regex::new(&pattern) else {
+                        return Err(ParseErrorKind::InvalidErrorMessage(pattern).at(loc));
+                    };
+                    records.push(Record::Control(Control::ForbidError(Some(regex))));
+                }
+                ["concurrent", on_off] => match bool::try_from_str(on_off) {
+                    Ok(on_off) => records.push(Record::Control(Control::Concurrent(on_off))),
+                    Err(k) => return Err(k.at(loc)),
+                },
+                ["await", connection, record, rest @ ..] => {
+                    let connection = match connection.strip_prefix("connection=") {
+                        Some(name) if !name.is_empty() => Connection::new(name),
+                        _ => {
+                            return Err(
+                                ParseErrorKind::UnexpectedToken(connection.to_string()).at(loc)
+                            )
+                        }
+                    };
+                    let Some(n) = record.strip_prefix("record=") else {
+                        return Err(ParseErrorKind::UnexpectedToken(record.to_string()).at(loc));
+                    };
+                    let record = match n.parse::<usize>() {
+                        Ok(record) if record > 0 => record,
+                        _ => return Err(ParseErrorKind::InvalidNumber(n.into()).at(loc)),
+                    };
+                    let timeout = match rest {
+                        [] => None,
+                        [timeout] => {
+                            let Some(duration) = timeout.strip_prefix("timeout=") else {
+                                return Err(
+                                    ParseErrorKind::UnexpectedToken(timeout.to_string()).at(loc)
+                                );
+                            };
+                            match humantime::parse_duration(duration) {
+                                Ok(duration) => Some(duration),
+                                Err(_) => {
+                                    return Err(
+                                        ParseErrorKind::InvalidDuration(duration.into()).at(loc)
+                                    )
+                                }
+                            }
+                        }
+                        [_, extra, ..] => {
+                            return Err(ParseErrorKind::UnexpectedToken(extra.to_string()).at(loc))
+                        }
+                    };
+                    records.push(Record::Control(Control::Await {
+                        connection,
+                        record,
+                        timeout,
+                        loc,
+                    }));
+                }
+                ["hash-threshold", threshold] => match threshold.parse::<u64>() {
+                    Ok(threshold) => {
+                        records.push(Record::Control(Control::HashThreshold(threshold)))
+                    }
+                    Err(_) => {
+                        return Err(ParseErrorKind::InvalidNumber((*threshold).into()).at(loc))
+                    }
+                },
                 _ => return Err(ParseErrorKind::InvalidLine(line.into()).at(loc)),
             },
             ["hash-threshold", threshold] => {
@@ -957,49 +2068,175 @@ fn parse_inner<T: ColumnType>(loc: &Location, script: &str) -> Result<Vec<Record
             _ => return Err(ParseErrorKind::InvalidLine(line.into()).at(loc)),
         }
     }
-    Ok(records)
+    Ok(())
+}
+
+/// The error for `directive` taking `expected` arguments, but given `actual` ones.
+fn wrong_arity(directive: &str, expected: &str, actual: usize) -> ParseErrorKind {
+    ParseErrorKind::WrongArity {
+        directive: directive.to_string(),
+        expected: expected.to_string(),
+        actual,
+    }
+}
+
+/// Parses the arguments of `include <file>(name=value, ...)`.
+fn parse_include_args(args: &str) -> Result<Vec<(String, String)>, ParseErrorKind> {
+    if args.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    args.split(',')
+        .map(|arg| {
+            let (name, value) = arg
+                .split_once('=')
+                .ok_or_else(|| ParseErrorKind::InvalidIncludeArgument(arg.trim().to_string()))?;
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(ParseErrorKind::InvalidIncludeArgument(
+                    arg.trim().to_string(),
+                ));
+            }
+            Ok((name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parse a sqllogictest script into a list of records, recovering from errors to collect all of
+/// them, e.g., to report every syntax problem of a script in one pass.
+///
+/// After an error, lines are skipped until a blank line or a line starting a record, and parsing
+/// continues from there. The recovery is heuristic, so an error may cause more errors. Use
+/// [`parse`] for strict parsing.
+pub fn parse_all<T: ColumnType>(script: &str) -> (Vec<Record<T>>, Vec<ParseError>) {
+    let loc = Location::new("<unknown>", 0);
+    let mut lines = script.lines().enumerate().peekable();
+    let mut records = vec![];
+    let mut errors = vec![];
+    while let Err(e) = parse_records(&loc, &mut lines, &mut records) {
+        errors.push(e);
+        while lines
+            .next_if(|(_, line)| !line.is_empty() && !starts_record(line))
+            .is_some()
+        {}
+    }
+    (records, errors)
+}
+
+/// Returns whether the line starts a record, which is where [`parse_all`] resumes after an error.
+fn starts_record(line: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "include",
+        "halt",
+        "subtest",
+        "sleep",
+        "echo",
+        "skipif",
+        "onlyif",
+        "connection",
+        "statement",
+        "query",
+        "system",
+        "control",
+        "hash-threshold",
+    ];
+    line.starts_with('#')
+        || line
+            .split_whitespace()
+            .next()
+            .is_some_and(|token| KEYWORDS.contains(&token))
 }
 
 /// Parse a sqllogictest file. The included scripts are inserted after the `include` record.
 pub fn parse_file<T: ColumnType>(filename: impl AsRef<Path>) -> Result<Vec<Record<T>>, ParseError> {
-    let filename = filename.as_ref().to_str().unwrap();
-    parse_file_inner(Location::new(filename, 0))
+    let path = filename.as_ref();
+    parse_file_inner(path, Location::new(path.to_string_lossy(), 0))
 }
 
-fn parse_file_inner<T: ColumnType>(loc: Location) -> Result<Vec<Record<T>>, ParseError> {
-    let path = Path::new(loc.file());
-    if !path.exists() {
-        return Err(ParseErrorKind::FileNotFound.at(loc.clone()));
+/// Returns the parse error kind of failing to read a file.
+fn read_error(e: std::io::Error) -> ParseErrorKind {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ParseErrorKind::FileNotFound,
+        kind => ParseErrorKind::ReadFile {
+            kind,
+            message: e.to_string(),
+        },
     }
-    let script = std::fs::read_to_string(path).unwrap();
+}
+
+fn parse_file_inner<T: ColumnType>(
+    path: &Path,
+    loc: Location,
+) -> Result<Vec<Record<T>>, ParseError> {
+    let script = read_file(path, &loc)?;
+    parse_file_script(path, &loc, &script)
+}
+
+/// Reads the script of a file at `loc`, failing if it's not valid UTF-8.
+fn read_file(path: &Path, loc: &Location) -> Result<String, ParseError> {
+    let bytes = std::fs::read(path).map_err(|e| read_error(e).at(loc.clone()))?;
+    String::from_utf8(bytes).map_err(|e| {
+        let byte_offset = e.utf8_error().valid_up_to();
+        let lines = e.as_bytes()[..byte_offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+        let loc = Location {
+            line: lines as u32 + 1,
+            ..loc.clone()
+        };
+        ParseErrorKind::InvalidUtf8 { byte_offset }.at(loc)
+    })
+}
+
+/// Parses the script of the file at `path`, inserting the included scripts after the `include`
+/// records.
+fn parse_file_script<T: ColumnType>(
+    path: &Path,
+    loc: &Location,
+    script: &str,
+) -> Result<Vec<Record<T>>, ParseError> {
     let mut records = vec![];
-    for rec in parse_inner(&loc, &script)? {
+    for rec in parse_inner(loc, script)? {
         records.push(rec.clone());
 
-        if let Record::Include { filename, loc } = rec {
-            let complete_filename = {
-                let mut path_buf = path.to_path_buf();
-                path_buf.pop();
-                path_buf.push(filename.clone());
-                path_buf.as_os_str().to_string_lossy().to_string()
-            };
+        if let Record::Include {
+            filename,
+            loc,
+            args,
+        } = rec
+        {
+            let complete_filename = include_pattern(path, &filename);
 
-            let mut iter = glob::glob(&complete_filename)
+            let mut included_files = glob::glob(&complete_filename)
                 .map_err(|e| ParseErrorKind::InvalidIncludeFile(e.to_string()).at(loc.clone()))?
-                .peekable();
-            if iter.peek().is_none() {
+                .map(|included_file| {
+                    included_file.map_err(|e| {
+                        ParseErrorKind::InvalidIncludeFile(e.to_string()).at(loc.clone())
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if included_files.is_empty() {
                 return Err(ParseErrorKind::EmptyIncludeFile(filename).at(loc.clone()));
             }
-            for included_file in iter {
-                let included_file = included_file.map_err(|e| {
-                    ParseErrorKind::InvalidIncludeFile(e.to_string()).at(loc.clone())
-                })?;
-                let included_file = included_file.as_os_str().to_string_lossy().to_string();
+            // Sorted here rather than relying on the order of the matches, which is not
+            // guaranteed by every platform and glob implementation.
+            included_files.sort();
+            for included_path in included_files {
+                let included_file = included_path.as_os_str().to_string_lossy().to_string();
 
                 records.push(Record::Injected(Injected::BeginInclude(
                     included_file.clone(),
                 )));
-                records.extend(parse_file_inner(loc.include(&included_file))?);
+                if !args.is_empty() {
+                    records.push(Record::Injected(Injected::PushVariables(args.clone())));
+                }
+                records.extend(parse_file_inner(
+                    &included_path,
+                    loc.include(&included_file),
+                )?);
+                if !args.is_empty() {
+                    records.push(Record::Injected(Injected::PopVariables));
+                }
                 records.push(Record::Injected(Injected::EndInclude(included_file)));
             }
         }
@@ -1007,6 +2244,152 @@ fn parse_file_inner<T: ColumnType>(loc: Location) -> Result<Vec<Record<T>>, Pars
     Ok(records)
 }
 
+/// Returns the glob pattern of an `include` record in file `path`, which is relative to the
+/// directory of `path`.
+fn include_pattern(path: &Path, filename: &str) -> String {
+    let mut path_buf = path.to_path_buf();
+    path_buf.pop();
+    path_buf.push(filename);
+    path_buf.as_os_str().to_string_lossy().to_string()
+}
+
+/// The modification time and length of a file, used to detect changes for [`ParseCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    mtime: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: impl AsRef<Path>) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            mtime: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+struct CacheEntry<T: ColumnType> {
+    records: Vec<Record<T>>,
+    /// The file itself and all files included by it (recursively), with their stamps when parsed.
+    files: Vec<(PathBuf, FileStamp)>,
+    /// The expanded glob patterns of `include` records, and the files they matched when parsed.
+    includes: Vec<(String, Vec<String>)>,
+}
+
+impl<T: ColumnType> CacheEntry<T> {
+    fn new(path: &Path, records: Vec<Record<T>>) -> Option<Self> {
+        let mut files = vec![(path.to_path_buf(), FileStamp::of(path)?)];
+        let mut includes: Vec<(String, Vec<String>)> = vec![];
+        // Included files are injected right after their `include` record, so the owner of a
+        // `BeginInclude` is the last `include` pattern seen at the same depth.
+        let mut owners: Vec<usize> = vec![];
+        let mut depth = 0;
+        for record in &records {
+            match record {
+                Record::Include { loc, filename, .. } => {
+                    includes.push((include_pattern(Path::new(loc.file()), filename), vec![]));
+                    owners.truncate(depth);
+                    owners.push(includes.len() - 1);
+                }
+                Record::Injected(Injected::BeginInclude(file)) => {
+                    files.push((PathBuf::from(file), FileStamp::of(file)?));
+                    includes[owners[depth]].1.push(file.clone());
+                    depth += 1;
+                }
+                Record::Injected(Injected::EndInclude(_)) => depth -= 1,
+                _ => {}
+            }
+        }
+        Some(Self {
+            records,
+            files,
+            includes,
+        })
+    }
+
+    /// Returns whether none of the files have changed since they were parsed.
+    fn is_fresh(&self) -> bool {
+        self.files
+            .iter()
+            .all(|(file, stamp)| FileStamp::of(file).as_ref() == Some(stamp))
+            && self.includes.iter().all(|(pattern, matched)| {
+                glob::glob(pattern).is_ok_and(|paths| {
+                    paths
+                        .map(|p| p.map(|p| p.as_os_str().to_string_lossy().to_string()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .is_ok_and(|paths| &paths == matched)
+                })
+            })
+    }
+}
+
+/// A cache of parsed files for [`parse_file_cached`].
+///
+/// Entries are invalidated when the modification time or length of the file, or any file it
+/// includes, changes, or when an `include` pattern matches a different set of files.
+pub struct ParseCache<T: ColumnType> {
+    entries: HashMap<PathBuf, CacheEntry<T>>,
+}
+
+impl<T: ColumnType> Default for ParseCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T: ColumnType> ParseCache<T> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes all cached entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Parse a sqllogictest file like [`parse_file`], reusing the records in `cache` if neither the
+/// file nor any of its included files have changed since they were last parsed.
+///
+/// This is useful when the same files are parsed repeatedly, e.g., in a watch mode.
+pub fn parse_file_cached<T: ColumnType>(
+    filename: impl AsRef<Path>,
+    cache: &mut ParseCache<T>,
+) -> Result<Vec<Record<T>>, ParseError> {
+    let path = filename.as_ref();
+    if let Some(entry) = cache.entries.get(path) {
+        if entry.is_fresh() {
+            return Ok(entry.records.clone());
+        }
+    }
+    cache.entries.remove(path);
+
+    let records = parse_file(path)?;
+    if let Some(entry) = CacheEntry::new(path, records.clone()) {
+        cache.entries.insert(path.to_path_buf(), entry);
+    }
+    Ok(records)
+}
+
+/// Returns whether the string looks like a SQLSTATE code, i.e., 5 digits or uppercase letters
+/// with at least one digit, to tell it apart from words like `ERROR`.
+fn is_sqlstate(s: &str) -> bool {
+    s.len() == 5
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || b.is_ascii_uppercase())
+        && s.bytes().any(|b| b.is_ascii_digit())
+}
+
+/// Returns whether the results consist of a single line of hash in hex, of any algorithm.
+fn is_hash(results: &[String]) -> bool {
+    matches!(results, [hash] if [HashAlgo::Md5, HashAlgo::Sha256, HashAlgo::Xxh3].iter().any(|algo| algo.is_hash(hash)))
+}
+
 /// Parse one or more lines until empty line or a delimiter.
 fn parse_lines<'a>(
     lines: &mut impl Iterator<Item = (usize, &'a str)>,
@@ -1163,6 +2546,116 @@ mod tests {
         assert_eq!(15, records.len());
     }
 
+    #[test]
+    fn test_include_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("setup")).unwrap();
+        std::fs::create_dir(dir.path().join("cases")).unwrap();
+        // Created out of order, and `cases` sorts before `setup`.
+        for file in [
+            "setup/b.slt",
+            "setup/a.slt",
+            "cases/b.slt",
+            "cases/10.slt",
+            "cases/2.slt",
+        ] {
+            std::fs::write(dir.path().join(file), format!("statement ok\n{file}\n")).unwrap();
+        }
+        let main = dir.path().join("main.slt");
+        std::fs::write(
+            &main,
+            "include setup/*.slt\n\nstatement ok\nmain\n\ninclude cases/*.slt\n",
+        )
+        .unwrap();
+
+        let records = parse_file::<DefaultColumnType>(&main).unwrap();
+        let sqls = records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Statement { sql, .. } => Some(sql.as_str()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            sqls,
+            [
+                "setup/a.slt",
+                "setup/b.slt",
+                "main",
+                "cases/10.slt",
+                "cases/2.slt",
+                "cases/b.slt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_file_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.slt");
+        let included = dir.path().join("included.slt");
+        std::fs::write(&main, "statement ok\nselect 1\n\ninclude included*.slt\n").unwrap();
+        std::fs::write(&included, "statement ok\nselect 2\n").unwrap();
+
+        let mut cache = ParseCache::<DefaultColumnType>::new();
+        let records = parse_file_cached(&main, &mut cache).unwrap();
+        assert_eq!(records, parse_file(&main).unwrap());
+
+        // Rewrite the file with the same length and mtime: the cached records are returned.
+        let mtime = std::fs::metadata(&main).unwrap().modified().unwrap();
+        std::fs::write(&main, "statement ok\nselect 3\n\ninclude included*.slt\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&main)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert_eq!(parse_file_cached(&main, &mut cache).unwrap(), records);
+
+        // A change in an included file invalidates the entry.
+        std::fs::write(&included, "statement ok\nselect 22\n").unwrap();
+        let records = parse_file_cached(&main, &mut cache).unwrap();
+        assert_eq!(records, parse_file(&main).unwrap());
+
+        // So does a new file matching an include pattern.
+        std::fs::write(
+            dir.path().join("included_2.slt"),
+            "statement ok\nselect 4\n",
+        )
+        .unwrap();
+        let records = parse_file_cached(&main, &mut cache).unwrap();
+        assert_eq!(records, parse_file(&main).unwrap());
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| matches!(r, Record::Injected(Injected::BeginInclude(_))))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_fail_invalid_utf8() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"statement ok\nselect 1\n\nstatement ok\nselect '\xff'\n")
+            .unwrap();
+
+        let error = parse_file::<DefaultColumnType>(file.path()).unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::InvalidUtf8 { byte_offset: 44 });
+        assert_eq!(error.loc.line(), 5);
+
+        // Unreadable files fail cleanly too, with the reason unless the file is not found.
+        let dir = tempfile::tempdir().unwrap();
+        let error = parse_file::<DefaultColumnType>(dir.path()).unwrap_err();
+        assert!(
+            matches!(error.kind, ParseErrorKind::ReadFile { kind, .. } if kind != std::io::ErrorKind::NotFound),
+            "{error}"
+        );
+        let error = parse_file::<DefaultColumnType>(dir.path().join("missing.slt")).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::FileNotFound);
+    }
+
     #[test]
     fn test_basic() {
         parse_roundtrip::<DefaultColumnType>("../tests/slt/basic.slt")
@@ -1226,6 +2719,802 @@ select * from unknown_type
         assert_eq!(error_kind, ParseErrorKind::InvalidType('A'));
     }
 
+    #[test]
+    fn test_parse_all() {
+        let script = "\
+statement ok
+create table t(v int)
+
+statement oops
+insert into t values (1)
+
+query I
+select * from t
+----
+1
+
+query I hashed
+select * from t
+----
+1
+2
+
+sleep forever
+statement ok
+drop table t
+
+control sortmode unknown
+";
+
+        parse::<DefaultColumnType>(script).unwrap_err();
+
+        let (records, errors) = parse_all::<DefaultColumnType>(script);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.location().line(), e.kind()))
+                .collect_vec(),
+            vec![
+                (4, ParseErrorKind::InvalidLine("statement oops".into())),
+                (12, ParseErrorKind::InvalidHashedResults("1\n2".into())),
+                (18, ParseErrorKind::InvalidDuration("forever".into())),
+                (22, ParseErrorKind::InvalidSortMode("unknown".into())),
+            ]
+        );
+        // The valid records are kept, including the one right after an error.
+        assert_eq!(
+            records
+                .iter()
+                .filter_map(|r| match r {
+                    Record::Statement { sql, .. } | Record::Query { sql, .. } => Some(sql.as_str()),
+                    _ => None,
+                })
+                .collect_vec(),
+            vec!["create table t(v int)", "select * from t", "drop table t"]
+        );
+
+        let (records, errors) = parse_all::<DefaultColumnType>("query I\nselect 1\n----\n1\n");
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn test_error_mode() {
+        parse_roundtrip::<DefaultColumnType>("../tests/slt/error_mode.slt")
+    }
+
+    #[test]
+    fn test_scope() {
+        let script = "control scope subtest\n\ncontrol scope file\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let controls = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Control(c) => Some(c.clone()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            controls,
+            vec![Control::Scope(Scope::Subtest), Control::Scope(Scope::File)]
+        );
+        assert_eq!(records.iter().join("\n"), script.trim_end());
+
+        let err = parse::<DefaultColumnType>("control scope test").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidControl("test".into()));
+    }
+
+    #[test]
+    fn test_await() {
+        let script = "control await connection=tx1 record=2\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(
+            records[0],
+            Record::Control(Control::Await {
+                connection: Connection::Named("tx1".into()),
+                record: 2,
+                timeout: None,
+                loc: Location::new("<unknown>", 1),
+            })
+        );
+        assert_eq!(records.iter().join("\n"), script.trim_end());
+
+        let script = "control concurrent on\ncontrol await connection=tx1 record=2 timeout=5s\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(records[0], Record::Control(Control::Concurrent(true)));
+        assert_eq!(
+            records[1],
+            Record::Control(Control::Await {
+                connection: Connection::Named("tx1".into()),
+                record: 2,
+                timeout: Some(Duration::from_secs(5)),
+                loc: Location::new("<unknown>", 2),
+            })
+        );
+        assert_eq!(records.iter().join("\n"), script.trim_end());
+
+        let err = parse::<DefaultColumnType>("control await connection=tx1 record=0").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidNumber("0".into()));
+        let err = parse::<DefaultColumnType>("control await tx1 record=2").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::UnexpectedToken("tx1".into()));
+        let err = parse::<DefaultColumnType>("control await connection=tx1 record=2 timeout=soon")
+            .unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidDuration("soon".into()));
+    }
+
+    #[test]
+    fn test_repeat() {
+        let script = "control repeat 100\nstatement ok\ninsert into t values (1)\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(records[0], Record::Control(Control::Repeat(100)));
+        assert_eq!(records.iter().join("\n"), script);
+
+        for n in ["0", "-1", "many"] {
+            let err = parse::<DefaultColumnType>(&format!("control repeat {n}")).unwrap_err();
+            assert_eq!(err.kind(), ParseErrorKind::InvalidNumber(n.into()));
+        }
+    }
+
+    #[test]
+    fn test_hash_algo() {
+        let script = "\
+control hash_algo sha256
+
+query I hashed
+select 1
+----
+sha256:911169ddaaf146aff539f58c26c489af3b892dff0fe283c1c264c65ae5aa59a2
+
+control hash_algo xxh3
+
+query I hashed
+select 1
+----
+xxh3:0123456789abcdef0123456789abcdef
+
+control hash_algo md5";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(
+            records[0],
+            Record::Control(Control::HashAlgo(HashAlgo::Sha256))
+        );
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("control hash_algo crc32").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidControl("crc32".into()));
+        // The length must match the algorithm.
+        let err = parse::<DefaultColumnType>(
+            "query I hashed\nselect 1\n----\nsha256:0123456789abcdef0123456789abcdef\n",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ParseErrorKind::InvalidHashedResults(_)
+        ));
+    }
+
+    #[test]
+    fn test_parallel() {
+        let script = "control parallel on\n\ncontrol parallel off";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(records[0], Record::Control(Control::Parallel(true)));
+        assert_eq!(records[2], Record::Control(Control::Parallel(false)));
+        assert_eq!(records.iter().join("\n"), script);
+    }
+
+    #[test]
+    fn test_echo() {
+        let script = "echo\n\necho  progress:  50%\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let texts = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Echo { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(texts, ["", "progress:  50%"]);
+        assert_eq!(records.iter().join("\n"), "echo\n\necho progress:  50%");
+    }
+
+    #[test]
+    fn test_forbid_error() {
+        let script = "control forbid_error (?i)internal  error\n\ncontrol forbid_error off\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let controls = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Control(c) => Some(c.clone()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            controls,
+            vec![
+                Control::ForbidError(Some(Regex::new("(?i)internal  error").unwrap())),
+                Control::ForbidError(None)
+            ]
+        );
+        assert_eq!(
+            records.iter().join("\n"),
+            "control forbid_error (?i)internal  error\n\ncontrol forbid_error off"
+        );
+
+        let err = parse::<DefaultColumnType>("control forbid_error (").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidErrorMessage("(".into()));
+    }
+
+    #[test]
+    fn test_debug_error() {
+        let script = "\
+statement error debug:connection (reset|refused)
+insert into t values (1)
+
+query error connection=c debug:timeout
+select * from t
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let errors = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Statement {
+                    expected: StatementExpect::Error(e),
+                    ..
+                }
+                | Record::Query {
+                    expected: QueryExpect::Error(e),
+                    ..
+                } => Some(e.clone()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            errors,
+            vec![
+                ExpectedError::Debug(Regex::new("connection (reset|refused)").unwrap()),
+                ExpectedError::Debug(Regex::new("timeout").unwrap()),
+            ]
+        );
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("statement error debug:(\nselect 1\n").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ParseErrorKind::InvalidErrorMessage("debug:(".into())
+        );
+    }
+
+    #[test]
+    fn test_sigfig() {
+        let script = "\
+query R rowsort sigfig=6 label retry 3 backoff 1s
+select 1.0
+----
+1.00000
+
+query RR sigfig=3
+select 1.0, 2.0
+----
+1.00 2.00
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let sigfigs = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Query {
+                    expected: QueryExpect::Results { sigfig, label, .. },
+                    ..
+                } => Some((*sigfig, label.clone())),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            sigfigs,
+            vec![(Some(6), Some("label".to_string())), (Some(3), None)]
+        );
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("query R sigfig=0\nselect 1.0\n").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidNumber("0".into()));
+
+        // The hash is not rounded.
+        let err = parse::<DefaultColumnType>("query R hashed sigfig=6\nselect 1.0\n").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ParseErrorKind::ConflictingOptions("hashed", "sigfig")
+        );
+    }
+
+    #[test]
+    fn test_check_records() {
+        let script = "\
+statement ok
+select 1
+
+halt
+
+# comment
+query I
+select 1
+----
+1
+
+halt
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let warnings = check_records(&records)
+            .into_iter()
+            .map(|w| (w.location().line(), w.kind()))
+            .collect_vec();
+        let halt = ParseWarningKind::UnreachableAfterHalt {
+            halt: Location::new("<unknown>", 4),
+        };
+        assert_eq!(warnings, vec![(7, halt.clone()), (12, halt)]);
+
+        let records = parse::<DefaultColumnType>("statement ok\nselect 1\n\nhalt\n").unwrap();
+        assert!(check_records(&records).is_empty());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let script = "\
+# slt-version: 2
+# A plain comment: with a colon.
+# requires: postgres>=14, replica
+# xfail: known bug
+
+# requires: not-in-the-header
+statement ok
+select 1
+";
+        let ParsedFile { metadata, records } =
+            parse_with_metadata::<DefaultColumnType>(script).unwrap();
+        assert_eq!(metadata.slt_version.as_deref(), Some("2"));
+        assert_eq!(metadata.xfail.as_deref(), Some("known bug"));
+        assert_eq!(
+            metadata.requires,
+            vec![
+                Requirement {
+                    label: "postgres".into(),
+                    min_version: Some("14".into()),
+                },
+                Requirement {
+                    label: "replica".into(),
+                    min_version: None,
+                },
+            ]
+        );
+        assert_eq!(metadata.requires.iter().join(", "), "postgres>=14, replica");
+        // The header is kept as comments.
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse_with_metadata::<DefaultColumnType>("# slt-version: 2\n# requires: pg>=x\n")
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ParseErrorKind::InvalidRequirement("pg>=x".into())
+        );
+        assert_eq!(err.location().line(), 2);
+
+        let requirement = &metadata.requires[0];
+        assert!(requirement.is_met_by("postgres", Some("14")));
+        assert!(requirement.is_met_by("postgres", Some("14.2")));
+        assert!(!requirement.is_met_by("postgres", Some("9.6")));
+        assert!(!requirement.is_met_by("postgres", None));
+        assert!(!requirement.is_met_by("mysql", Some("14")));
+    }
+
+    #[test]
+    fn test_count_executable_records() {
+        let records = parse::<DefaultColumnType>("# comment\n\n# another comment\n").unwrap();
+        assert_eq!(count_executable_records(&records), 0);
+
+        let script = "\
+control sortmode rowsort
+
+statement ok
+select 1
+
+query I
+select 1
+----
+1
+
+halt
+
+statement ok
+select 2
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(count_executable_records(&records), 2);
+    }
+
+    #[test]
+    fn test_rowsort_nulls_last() {
+        let script = "control rowsort_nulls_last on\ncontrol rowsort_nulls_last off";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                Record::Control(Control::RowSortNullsLast(true)),
+                Record::Control(Control::RowSortNullsLast(false)),
+            ]
+        );
+        assert_eq!(records.iter().join("\n"), script);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let script = "\
+onlyif postgres
+skipif mysql
+statement ok
+select 1
+
+skipif sqlite
+system ok
+echo 1
+
+query I
+select 1
+----
+1
+
+halt
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let conditions = records
+            .iter()
+            .filter(|r| !matches!(r, Record::Newline | Record::Condition(_)))
+            .map(|r| r.conditions().to_vec())
+            .collect_vec();
+        assert_eq!(
+            conditions,
+            vec![
+                vec![
+                    Condition::OnlyIf {
+                        label: "postgres".to_string()
+                    },
+                    Condition::SkipIf {
+                        label: "mysql".to_string()
+                    },
+                ],
+                vec![Condition::SkipIf {
+                    label: "sqlite".to_string()
+                }],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_args() {
+        let script = "include setup.slt(table=foo, n=10)\ninclude *.slt";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let args = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Include { filename, args, .. } => Some((filename.as_str(), args.clone())),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            args,
+            vec![
+                (
+                    "setup.slt",
+                    vec![
+                        ("table".to_string(), "foo".to_string()),
+                        ("n".to_string(), "10".to_string())
+                    ]
+                ),
+                ("*.slt", vec![]),
+            ]
+        );
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("include setup.slt(table)").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ParseErrorKind::InvalidIncludeArgument("table".into())
+        );
+        let script = "include setup.slt(table=foo";
+        let err = parse::<DefaultColumnType>(script).unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidLine(script.into()));
+    }
+
+    #[test]
+    fn test_wrong_arity() {
+        for (script, directive, expected, actual) in [
+            ("skipif", "skipif", "label", 0),
+            ("skipif mysql postgres", "skipif", "label", 2),
+            ("onlyif", "onlyif", "label", 0),
+            ("onlyif mysql postgres", "onlyif", "label", 2),
+            ("include", "include", "file pattern", 0),
+            ("include a.slt b.slt", "include", "file pattern", 2),
+            ("include (n=1)", "include", "file pattern", 0),
+            ("include a.slt b.slt(n=1)", "include", "file pattern", 2),
+            ("subtest", "subtest", "name", 0),
+            ("subtest a b", "subtest", "name", 2),
+            ("connection", "connection", "name", 0),
+            ("sleep 1s 2s", "sleep", "duration", 2),
+        ] {
+            let err = parse::<DefaultColumnType>(&format!("statement ok\nselect 1\n\n{script}\n"))
+                .unwrap_err();
+            assert_eq!(
+                err.kind(),
+                ParseErrorKind::WrongArity {
+                    directive: directive.into(),
+                    expected: expected.into(),
+                    actual,
+                },
+                "{script}"
+            );
+            assert_eq!(err.location().line(), 4);
+        }
+
+        let err = parse::<DefaultColumnType>("skipif").unwrap_err();
+        assert_eq!(
+            err.kind().to_string(),
+            "`skipif` expects exactly one label, got 0"
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        let script = "\
+query III rowsort sigfig=3 cols=2,0
+select 1, 2, 3
+----
+3 1
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let Record::Query {
+            expected: QueryExpect::Results { columns, .. },
+            ..
+        } = &records[0]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(columns, &Some(vec![2, 0]));
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("query III cols=0,3\nselect 1, 2, 3\n").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ParseErrorKind::ColumnOutOfRange {
+                index: 3,
+                num_columns: 3
+            }
+        );
+        let err = parse::<DefaultColumnType>("query III cols=a\nselect 1, 2, 3\n").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidNumber("a".into()));
+    }
+
+    #[test]
+    fn test_result_set() {
+        let script = "\
+query I rowsort cols=0 resultset=1 my_label
+select 1; select 2
+----
+2
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let Record::Query {
+            expected: QueryExpect::Results {
+                result_set, label, ..
+            },
+            ..
+        } = &records[0]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(result_set, &Some(1));
+        assert_eq!(label.as_deref(), Some("my_label"));
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("query I resultset=-1\nselect 1\n").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidNumber("-1".into()));
+    }
+
+    #[test]
+    fn test_error_match_with_mode() {
+        let err = "relation \"t\" does not exist";
+        let expected = ExpectedError::new_inline("42P01".to_string()).unwrap();
+        assert!(!expected.is_match_with_mode(ErrorMode::Message, err, Some("42P01")));
+        assert!(expected.is_match_with_mode(ErrorMode::SqlState, err, Some("42P01")));
+        assert!(!expected.is_match_with_mode(ErrorMode::SqlState, err, Some("42601")));
+        assert!(!expected.is_match_with_mode(ErrorMode::SqlState, err, None));
+
+        let expected = ExpectedError::new_inline("42P01 relation .* does not".to_string()).unwrap();
+        assert!(expected.is_match_with_mode(ErrorMode::Both, err, Some("42P01")));
+        assert!(!expected.is_match_with_mode(ErrorMode::Both, err, Some("42601")));
+        assert!(!expected.is_match_with_mode(ErrorMode::Both, "syntax error", Some("42P01")));
+
+        // Without a SQLSTATE code, only the message is matched.
+        let expected = ExpectedError::new_inline("ERROR does not".to_string()).unwrap();
+        assert!(!expected.is_match_with_mode(ErrorMode::Both, err, None));
+        assert!(expected.is_match_with_mode(ErrorMode::Both, "ERROR does not exist", None));
+    }
+
+    #[test]
+    fn test_statement_batch() {
+        let script = "\
+statement batch connection=other
+create table t (s text);
+insert into t values (';');
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert!(matches!(
+            &records[0],
+            Record::Statement {
+                expected: StatementExpect::Batch,
+                ..
+            }
+        ));
+        assert_eq!(records.iter().join("\n"), script);
+    }
+
+    #[test]
+    fn test_continued_directive() {
+        let script = "\
+skipif \\
+  mysql
+query III \\
+  rowsort \\
+  label1
+select 1, 2, 3
+----
+1 2 3
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        assert_eq!(
+            records[0],
+            Record::Condition(Condition::SkipIf {
+                label: "mysql".to_string()
+            })
+        );
+        let Record::Query {
+            loc,
+            conditions,
+            expected:
+                QueryExpect::Results {
+                    types,
+                    sort_mode,
+                    label,
+                    ..
+                },
+            ..
+        } = &records[1]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(loc.line(), 3);
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(types.len(), 3);
+        assert_eq!(sort_mode, &Some(SortMode::RowSort));
+        assert_eq!(label.as_deref(), Some("label1"));
+        // The directives are printed on a single line.
+        assert_eq!(
+            records.iter().join("\n"),
+            "skipif mysql\nquery III rowsort label1\nselect 1, 2, 3\n----\n1 2 3\n"
+        );
+    }
+
+    #[test]
+    fn test_nocase() {
+        let script = "\
+query T rowsort nocase my_label
+select 'Hello'
+----
+hello
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let Record::Query {
+            expected: QueryExpect::Results { nocase, label, .. },
+            ..
+        } = &records[0]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(*nocase, Some(true));
+        assert_eq!(label.as_deref(), Some("my_label"));
+        assert_eq!(records.iter().join("\n"), script);
+
+        let script = "query T nocase=off\nselect 'Hello'\n----\nHello\n";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let Record::Query {
+            expected: QueryExpect::Results { nocase, .. },
+            ..
+        } = &records[0]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(*nocase, Some(false));
+        assert_eq!(records.iter().join("\n"), script);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let script = "\
+statement ok warnings 2 maxtime 1s
+create index on t using old_method
+
+query I rowsort my_label warnings >0
+select old_func()
+----
+1
+";
+        let records = parse::<DefaultColumnType>(script).unwrap();
+        let warnings = records
+            .iter()
+            .filter_map(|r| match r {
+                Record::Statement { warnings, .. } | Record::Query { warnings, .. } => {
+                    Some(*warnings)
+                }
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(
+            warnings,
+            vec![
+                Some(ExpectedWarnings::Exactly(2)),
+                Some(ExpectedWarnings::MoreThan(0))
+            ]
+        );
+        let Record::Query {
+            expected: QueryExpect::Results { label, .. },
+            ..
+        } = &records[1]
+        else {
+            panic!("expected a query record");
+        };
+        assert_eq!(label.as_deref(), Some("my_label"));
+        assert_eq!(records.iter().join("\n"), script);
+
+        let err = parse::<DefaultColumnType>("statement ok warnings many\nselect 1\n").unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::InvalidNumber("many".into()));
+    }
+
+    #[test]
+    fn test_inline_connection() {
+        parse_roundtrip::<DefaultColumnType>("../tests/slt/connection/inline.slt")
+    }
+
+    #[test]
+    fn test_hash_threshold() {
+        parse_roundtrip::<DefaultColumnType>("../tests/slt/hash_threshold.slt")
+    }
+
+    #[test]
+    fn test_hash_normalize() {
+        parse_roundtrip::<DefaultColumnType>("../tests/slt/hash_normalize.slt")
+    }
+
+    #[test]
+    fn test_hashed() {
+        parse_roundtrip::<DefaultColumnType>("../tests/slt/hashed.slt")
+    }
+
+    #[test]
+    fn test_fail_invalid_hashed_results() {
+        let script = "\
+query I hashed
+select * from t
+----
+1
+2
+";
+
+        let error_kind = parse::<DefaultColumnType>(script).unwrap_err().kind;
+
+        assert_eq!(
+            error_kind,
+            ParseErrorKind::InvalidHashedResults("1\n2".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_no_types() {
         let script = "\
@@ -1241,8 +3530,11 @@ select * from foo;
                 loc: Location::new("<unknown>", 1),
                 conditions: vec![],
                 connection: Connection::Default,
+                inline_connection: false,
                 sql: "select * from foo;".to_string(),
                 expected: QueryExpect::empty_results(),
+                maxtime: None,
+                warnings: None,
                 retry: None,
             }]
         );
@@ -1293,6 +3585,7 @@ select * from foo;
                     Record::Query { loc, .. } => normalize_loc(loc),
                     Record::Sleep { loc, .. } => normalize_loc(loc),
                     Record::Subtest { loc, .. } => normalize_loc(loc),
+                    Record::Echo { loc, .. } => normalize_loc(loc),
                     Record::Halt { loc, .. } => normalize_loc(loc),
                     Record::HashThreshold { loc, .. } => normalize_loc(loc),
                     // even though these variants don't include a
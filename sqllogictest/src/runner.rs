@@ -1,25 +1,29 @@
 //! Sqllogictest runner.
 
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Output};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec;
 
 use async_trait::async_trait;
 use futures::executor::block_on;
+use futures::future::{self, BoxFuture, Either};
+use futures::stream::FuturesUnordered;
 use futures::{stream, Future, FutureExt, StreamExt};
 use itertools::Itertools;
 use md5::Digest;
 use owo_colors::OwoColorize;
 use rand::Rng;
+use regex::Regex;
 use similar::{Change, ChangeTag, TextDiff};
 
 use crate::parser::*;
 use crate::substitution::Substitution;
-use crate::{ColumnType, Connections, MakeConnection};
+use crate::{ColumnType, ConnectRetry, Connections, MakeConnection};
 
 /// Type-erased error type.
 type AnyError = Arc<dyn std::error::Error + Send + Sync>;
@@ -59,11 +63,162 @@ pub enum DBOutput<T: ColumnType> {
     ///
     /// If the test case doesn't specify `statement count <n>`, the number is simply ignored.
     StatementComplete(u64),
+    /// The SQL produced several outputs, e.g., a batch of statements or a stored procedure
+    /// returning more than one result set.
+    ///
+    /// A query validates the last output by default, or the one selected by `resultset=<n>`. A
+    /// statement always uses the last output.
+    MultipleResultSets(Vec<DBOutput<T>>),
+}
+
+/// The state of a [`Runner`] changed by running scripts, taken by [`Runner::snapshot_state`].
+///
+/// This includes the labels, the settings of `control` records, the `hash-threshold`, the variables
+/// bound by `include` arguments and the number of records run on each connection, but not the
+/// connections.
+#[derive(Clone)]
+pub struct RunnerState {
+    labels: HashSet<String>,
+    label_versions: HashMap<String, String>,
+    sort_mode: Option<SortMode>,
+    result_mode: Option<ResultMode>,
+    error_mode: ErrorMode,
+    forbidden_error: Option<Regex>,
+    hash_threshold: usize,
+    hash_normalize: bool,
+    hash_algo: HashAlgo,
+    rowsort_nulls_last: bool,
+    parallel: bool,
+    scope: Scope,
+    repeat: Option<u64>,
+    substitution: Option<Substitution>,
+    variables: Vec<Vec<(String, String)>>,
+    executed: HashMap<Connection, usize>,
+}
+
+/// The name of the savepoint wrapping each subtest under `control scope subtest`.
+const SUBTEST_SAVEPOINT: &str = "sqllogictest_subtest";
+
+/// The name of the savepoint wrapping each record expecting an error in a subtest, so that the
+/// error doesn't abort the transaction of the subtest, e.g., in Postgres.
+const RECORD_SAVEPOINT: &str = "sqllogictest_record";
+
+/// Returns the location of the record if it expects an error on the default connection.
+fn expected_error_loc<T: ColumnType>(record: &Record<T>) -> Option<Location> {
+    match record {
+        Record::Statement {
+            loc,
+            connection: Connection::Default,
+            expected: StatementExpect::Error(_),
+            ..
+        }
+        | Record::Query {
+            loc,
+            connection: Connection::Default,
+            expected: QueryExpect::Error(_),
+            ..
+        } => Some(loc.clone()),
+        _ => None,
+    }
+}
+
+/// The default of [`Runner::with_max_parallel_queries`].
+const DEFAULT_MAX_PARALLEL_QUERIES: usize = 4;
+
+/// How long `control await` waits for a connection under `control concurrent on`, if no
+/// `timeout` is given. Also the limit to wait for the background records of a connection before
+/// running a record in the foreground on it, and at the end of `control concurrent on`.
+const DEFAULT_AWAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the background records are checked while waiting for them, since the default
+/// [`AsyncDB::sleep`] blocks the thread.
+const AWAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A record skipped by a `skipif` or `onlyif` condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRecord {
+    /// The location of the record.
+    pub loc: Location,
+    /// The first condition that caused the record to be skipped.
+    pub condition: Condition,
+    /// The labels when the record was skipped, including the engine name if it's implicitly a
+    /// label. Sorted.
+    pub labels: Vec<String>,
+}
+
+/// A label in a `skipif` or `onlyif` condition that is not known to the runner, usually a typo
+/// like `skipif postgre`. Returned by [`Runner::check_conditions`].
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("unknown label {:?} in condition at {loc}", .condition.label())]
+pub struct UnknownLabelWarning {
+    /// The location of the record with the condition.
+    pub loc: Location,
+    /// The condition with the unknown label.
+    pub condition: Condition,
+}
+
+/// The status of a test file run by [`Runner::run_dir_async`].
+#[derive(Debug, Clone)]
+pub enum FileStatus {
+    /// All the records passed.
+    Passed,
+    /// The file failed to parse or run.
+    Failed(TestError),
+    /// The file was not run due to the unmet requirements in its `# requires:` header.
+    Skipped(Vec<Requirement>),
+}
+
+/// The report of a test file run by [`Runner::run_dir_async`].
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    /// The path of the file, including the directory.
+    pub path: PathBuf,
+    /// Whether the file passed, failed or was skipped.
+    pub status: FileStatus,
+    /// The records skipped by conditions `skipif` and `onlyif`.
+    pub skipped_records: Vec<SkippedRecord>,
+    /// How long it took to parse and run the file.
+    pub duration: Duration,
+}
+
+/// The report of the test files run by [`Runner::run_dir_async`], in the order they were run.
+#[derive(Debug, Clone, Default)]
+pub struct DirReport {
+    /// The report of each file.
+    pub files: Vec<FileReport>,
+}
+
+impl DirReport {
+    /// Returns the files that passed.
+    pub fn passed(&self) -> Vec<&FileReport> {
+        self.filter(|status| matches!(status, FileStatus::Passed))
+    }
+
+    /// Returns the files that failed.
+    pub fn failed(&self) -> Vec<&FileReport> {
+        self.filter(|status| matches!(status, FileStatus::Failed(_)))
+    }
+
+    /// Returns the files skipped due to unmet requirements.
+    pub fn skipped(&self) -> Vec<&FileReport> {
+        self.filter(|status| matches!(status, FileStatus::Skipped(_)))
+    }
+
+    /// Whether no file failed.
+    pub fn is_ok(&self) -> bool {
+        self.failed().is_empty()
+    }
+
+    fn filter(&self, f: impl Fn(&FileStatus) -> bool) -> Vec<&FileReport> {
+        self.files.iter().filter(|file| f(&file.status)).collect()
+    }
 }
 
 /// The async database to be tested.
+///
+/// It's `Send` since the futures returned by the methods, which borrow the database, are `Send`.
 #[async_trait]
-pub trait AsyncDB {
+pub trait AsyncDB: Send {
     /// The error type of SQL execution.
     type Error: std::error::Error + Send + Sync + 'static;
     /// The type of result columns
@@ -77,6 +232,64 @@ pub trait AsyncDB {
         ""
     }
 
+    /// Returns the SQLSTATE code of the error, if any. Used to match expected errors under
+    /// `control error_mode sqlstate` or `both`.
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        let _ = error;
+        None
+    }
+
+    /// Returns the warnings emitted since the last call, i.e., by the last SQL run, and clears
+    /// them. Checked against `warnings <n>` of statements and queries. The default implementation
+    /// returns no warnings.
+    fn take_warnings(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether the database supports savepoints, i.e., implements [`AsyncDB::savepoint`],
+    /// [`AsyncDB::rollback_to`] and [`AsyncDB::release`]. They are required by
+    /// `control scope subtest`. Defaults to `false`.
+    fn supports_savepoints(&self) -> bool {
+        false
+    }
+
+    /// Creates a savepoint with the given name, starting a transaction first if not in one.
+    ///
+    /// Only called if [`AsyncDB::supports_savepoints`] returns `true`. The default implementation
+    /// does nothing.
+    async fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Rolls back the changes made after the savepoint with the given name.
+    ///
+    /// Only called if [`AsyncDB::supports_savepoints`] returns `true`. The default implementation
+    /// does nothing.
+    async fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Releases the savepoint with the given name, and ends the transaction started by
+    /// [`AsyncDB::savepoint`], if any.
+    ///
+    /// Only called if [`AsyncDB::supports_savepoints`] returns `true`. The default implementation
+    /// does nothing.
+    async fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Checks whether the connection is still alive. Called by the runner on the connections
+    /// kept from previous files before starting a new file, and the connections failing it are
+    /// dropped and made again when they're used next time.
+    ///
+    /// The default implementation does nothing, i.e., always considers the connection alive.
+    async fn ping(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// [`Runner`] calls this function to perform sleep.
     ///
     /// The default implementation is `std::thread::sleep`, which is universal to any async runtime
@@ -110,6 +323,45 @@ pub trait DB {
     fn engine_name(&self) -> &str {
         ""
     }
+
+    /// Returns the SQLSTATE code of the error, if any.
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        let _ = error;
+        None
+    }
+
+    /// Returns the warnings emitted by the last SQL run. See [`AsyncDB::take_warnings`].
+    fn take_warnings(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether the database supports savepoints. See [`AsyncDB::supports_savepoints`].
+    fn supports_savepoints(&self) -> bool {
+        false
+    }
+
+    /// Creates a savepoint. See [`AsyncDB::savepoint`].
+    fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Rolls back to a savepoint. See [`AsyncDB::rollback_to`].
+    fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Releases a savepoint. See [`AsyncDB::release`].
+    fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+        let _ = name;
+        Ok(())
+    }
+
+    /// Checks whether the connection is still alive. See [`AsyncDB::ping`].
+    fn ping(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// Compat-layer for the new AsyncDB and DB trait
@@ -128,16 +380,179 @@ where
     fn engine_name(&self) -> &str {
         D::engine_name(self)
     }
+
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        D::error_sqlstate(error)
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        D::take_warnings(self)
+    }
+
+    fn supports_savepoints(&self) -> bool {
+        D::supports_savepoints(self)
+    }
+
+    async fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+        D::savepoint(self, name)
+    }
+
+    async fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+        D::rollback_to(self, name)
+    }
+
+    async fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+        D::release(self, name)
+    }
+
+    async fn ping(&mut self) -> Result<(), Self::Error> {
+        D::ping(self)
+    }
+}
+
+/// A database shared between runners, or between a runner and other code, e.g., to inspect the
+/// database in between test files. The clones share the same database.
+///
+/// ```
+/// # use sqllogictest::{DBOutput, DefaultColumnType, Runner, SharedDB};
+/// # struct MyDB;
+/// # impl sqllogictest::DB for MyDB {
+/// #     type Error = std::io::Error;
+/// #     type ColumnType = DefaultColumnType;
+/// #     fn run(&mut self, _sql: &str) -> Result<DBOutput<DefaultColumnType>, Self::Error> {
+/// #         Ok(DBOutput::StatementComplete(0))
+/// #     }
+/// # }
+/// let db = SharedDB::new(MyDB);
+/// let mut runner = Runner::new({
+///     let db = db.clone();
+///     move || {
+///         let db = db.clone();
+///         async move { Ok(db) }
+///     }
+/// });
+/// runner.run_script("statement ok\ncreate table t (v int)\n").unwrap();
+/// // Inspect the database in between runs.
+/// let _db = futures::executor::block_on(db.lock());
+/// ```
+///
+/// The lock is held while each SQL runs, so the sharers wait for each other. Don't hold it across
+/// a run of the runner, which would block forever. The savepoints of `control scope subtest` are
+/// not isolated from the SQL of other sharers in between. The mutex is `futures::lock::Mutex` to
+/// be universal to any async runtime, like the other defaults of [`AsyncDB`]. Sync code can lock
+/// it with `futures::executor::block_on`.
+///
+/// The engine name and whether savepoints are supported are read when it's created, so that
+/// `skipif`/`onlyif` match the engine name. The warnings are taken with the lock held right after
+/// each SQL runs, so that they're not mixed up between the sharers.
+///
+/// Both an [`AsyncDB`] and a [`DB`] can be shared, since every `DB` is an `AsyncDB`. It's a
+/// wrapper type rather than an implementation of [`AsyncDB`] for `Arc<Mutex<D>>`, since
+/// [`AsyncDB::engine_name`] borrows the engine name from the database, which can't outlive the
+/// lock, and the warnings must be kept by each sharer. Such an implementation would also conflict
+/// with the implementation of [`AsyncDB`] for every [`DB`].
+pub struct SharedDB<D> {
+    db: Arc<futures::lock::Mutex<D>>,
+    engine_name: String,
+    supports_savepoints: bool,
+    warnings: Vec<String>,
+}
+
+impl<D: AsyncDB> SharedDB<D> {
+    /// Shares the database.
+    pub fn new(db: D) -> Self {
+        Self {
+            engine_name: db.engine_name().to_owned(),
+            supports_savepoints: db.supports_savepoints(),
+            db: Arc::new(futures::lock::Mutex::new(db)),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Locks the database, waiting for the SQL run by other sharers, if any.
+    pub async fn lock(&self) -> futures::lock::MutexGuard<'_, D> {
+        self.db.lock().await
+    }
+}
+
+impl<D> Clone for SharedDB<D> {
+    /// Shares the same database. The warnings are not cloned.
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            engine_name: self.engine_name.clone(),
+            supports_savepoints: self.supports_savepoints,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl<D> AsyncDB for SharedDB<D>
+where
+    D: AsyncDB,
+{
+    type Error = D::Error;
+    type ColumnType = D::ColumnType;
+
+    async fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+        let mut db = self.db.lock().await;
+        let output = db.run(sql).await;
+        self.warnings = db.take_warnings();
+        output
+    }
+
+    fn engine_name(&self) -> &str {
+        &self.engine_name
+    }
+
+    fn error_sqlstate(error: &Self::Error) -> Option<String> {
+        D::error_sqlstate(error)
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn supports_savepoints(&self) -> bool {
+        self.supports_savepoints
+    }
+
+    async fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+        self.db.lock().await.savepoint(name).await
+    }
+
+    async fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+        self.db.lock().await.rollback_to(name).await
+    }
+
+    async fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+        self.db.lock().await.release(name).await
+    }
+
+    async fn ping(&mut self) -> Result<(), Self::Error> {
+        self.db.lock().await.ping().await
+    }
+
+    async fn sleep(dur: Duration) {
+        D::sleep(dur).await
+    }
+
+    async fn run_command(command: Command) -> std::io::Result<std::process::Output> {
+        D::run_command(command).await
+    }
 }
 
 /// The error type for running sqllogictest.
 ///
 /// For colored error message, use `self.display()`.
-#[derive(thiserror::Error, Clone)]
-#[error("{kind}\nat {loc}\n")]
+#[derive(Clone)]
 pub struct TestError {
     kind: TestErrorKind,
     loc: Location,
+    /// The maximum number of lines of the diff to display, or 0 for unlimited. See
+    /// [`Runner::with_max_diff_lines`].
+    max_diff_lines: usize,
 }
 
 impl TestError {
@@ -147,8 +562,21 @@ impl TestError {
             colorize,
         }
     }
+
+    fn with_max_diff_lines(mut self, max_diff_lines: usize) -> Self {
+        self.max_diff_lines = max_diff_lines;
+        self
+    }
+}
+
+impl Display for TestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(false))
+    }
 }
 
+impl std::error::Error for TestError {}
+
 /// Overrides the `Display` implementation of [`TestError`] to support controlling colorization.
 pub struct TestErrorDisplay<'a> {
     err: &'a TestError,
@@ -160,7 +588,7 @@ impl Display for TestErrorDisplay<'_> {
         write!(
             f,
             "{}\nat {}\n",
-            self.err.kind.display(self.colorize),
+            (self.err.kind.display(self.colorize)).max_diff_lines(self.err.max_diff_lines),
             self.err.loc
         )
     }
@@ -259,6 +687,12 @@ pub enum TestErrorKind {
         err: AnyError,
         kind: RecordKind,
     },
+    #[error("{kind} failed to connect: {err}\n[SQL] {sql}")]
+    ConnectFail {
+        sql: String,
+        err: AnyError,
+        kind: RecordKind,
+    },
     #[error("system command failed: {err}\n[CMD] {command}")]
     SystemFail { command: String, err: AnyError },
     #[error(
@@ -287,7 +721,7 @@ pub enum TestErrorKind {
     // Remember to also update [`TestErrorKindDisplay`] if this message is changed.
     #[error(
         "query result mismatch:\n[SQL] {sql}\n[Diff] (-expected|+actual)\n{}",
-        TextDiff::from_lines(.expected, .actual).iter_all_changes().format_with("\n", |diff, f| format_diff(&diff, f, false))
+        format_lines_diff(expected, actual, 0, false)
     )]
     QueryResultMismatch {
         sql: String,
@@ -303,6 +737,48 @@ pub enum TestErrorKind {
         expected: String,
         actual: String,
     },
+    #[error("`control scope subtest` requires savepoints, which are not supported by the engine {engine:?}")]
+    SavepointUnsupported { engine: String },
+    #[error("failed to isolate subtest with savepoint: {err}")]
+    SavepointFail { err: AnyError },
+    #[error("{kind} took {elapsed:?}, longer than maxtime {limit:?}\n[SQL] {sql}")]
+    TooSlow {
+        sql: String,
+        elapsed: Duration,
+        limit: Duration,
+        kind: RecordKind,
+    },
+    #[error("echo failed: {err}\n[TEXT] {text}")]
+    EchoFail { text: String, err: AnyError },
+    #[error("{kind} failed with an empty error message, which is not accepted as the expected error\n[SQL] {sql}")]
+    EmptyErrorMessage { sql: String, kind: RecordKind },
+    #[error("connection {connection} is expected to have run {expected} statements or queries, but actually {actual}")]
+    AwaitNotReached {
+        connection: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("timed out after {timeout:?} waiting for connection {connection} to have run {expected} statements or queries, but it has run {actual}")]
+    AwaitTimeout {
+        connection: String,
+        expected: usize,
+        actual: usize,
+        timeout: Duration,
+    },
+    #[error("{kind} is expected to emit {expected} warnings, but actually emitted {}: {actual:?}\n[SQL] {sql}", .actual.len())]
+    WarningsMismatch {
+        sql: String,
+        expected: ExpectedWarnings,
+        actual: Vec<String>,
+        kind: RecordKind,
+    },
+    #[error("{kind} failed with an error matching the forbidden pattern {pattern:?}: {err}\n[SQL] {sql}")]
+    ForbiddenError {
+        sql: String,
+        err: AnyError,
+        pattern: String,
+        kind: RecordKind,
+    },
 }
 
 impl From<ParseError> for TestError {
@@ -310,19 +786,25 @@ impl From<ParseError> for TestError {
         TestError {
             kind: TestErrorKind::ParseError(e.kind()),
             loc: e.location(),
+            max_diff_lines: 0,
         }
     }
 }
 
 impl TestErrorKind {
     fn at(self, loc: Location) -> TestError {
-        TestError { kind: self, loc }
+        TestError {
+            kind: self,
+            loc,
+            max_diff_lines: 0,
+        }
     }
 
     pub fn display(&self, colorize: bool) -> TestErrorKindDisplay<'_> {
         TestErrorKindDisplay {
             error: self,
             colorize,
+            max_diff_lines: 0,
         }
     }
 }
@@ -331,11 +813,33 @@ impl TestErrorKind {
 pub struct TestErrorKindDisplay<'a> {
     error: &'a TestErrorKind,
     colorize: bool,
+    max_diff_lines: usize,
+}
+
+impl TestErrorKindDisplay<'_> {
+    /// Truncate the diff of [`TestErrorKind::QueryResultMismatch`] to the given number of lines.
+    /// 0 means unlimited, the default. See [`Runner::with_max_diff_lines`].
+    pub fn max_diff_lines(mut self, max_diff_lines: usize) -> Self {
+        self.max_diff_lines = max_diff_lines;
+        self
+    }
 }
 
 impl Display for TestErrorKindDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if !self.colorize {
+            if let TestErrorKind::QueryResultMismatch {
+                sql,
+                expected,
+                actual,
+            } = self.error
+            {
+                return write!(
+                    f,
+                    "query result mismatch:\n[SQL] {sql}\n[Diff] (-expected|+actual)\n{}",
+                    format_lines_diff(expected, actual, self.max_diff_lines, false)
+                );
+            }
             return write!(f, "{}", self.error);
         }
         match self.error {
@@ -359,9 +863,7 @@ impl Display for TestErrorKindDisplay<'_> {
                 "query result mismatch:\n[SQL] {sql}\n[Diff] ({}|{})\n{}",
                 "-expected".bright_red(),
                 "+actual".bright_green(),
-                TextDiff::from_lines(expected, actual)
-                    .iter_all_changes()
-                    .format_with("\n", |diff, f| format_diff(&diff, f, true))
+                format_lines_diff(expected, actual, self.max_diff_lines, true)
             ),
             TestErrorKind::QueryResultColumnsMismatch {
                 sql,
@@ -392,6 +894,41 @@ impl Display for TestErrorKindDisplay<'_> {
     }
 }
 
+/// Returns the hash in hex of the values, each followed by a newline, prefixed by the algorithm
+/// unless it's MD5.
+fn hash_rows(algo: HashAlgo, rows: &[Vec<String>]) -> String {
+    let values = rows.iter().flatten();
+    match algo {
+        HashAlgo::Md5 => {
+            let mut md5 = md5::Md5::new();
+            for value in values {
+                md5.update(value.as_bytes());
+                md5.update(b"\n");
+            }
+            format!("{:2x}", md5.finalize())
+        }
+        HashAlgo::Sha256 => {
+            let mut sha256 = sha2::Sha256::new();
+            for value in values {
+                sha256.update(value.as_bytes());
+                sha256.update(b"\n");
+            }
+            format!("sha256:{:2x}", sha256.finalize())
+        }
+        HashAlgo::Xxh3 => {
+            use std::hash::Hasher;
+            use twox_hash::xxh3::HasherExt;
+
+            let mut xxh3 = twox_hash::xxh3::Hash128::with_seed(0);
+            for value in values {
+                xxh3.write(value.as_bytes());
+                xxh3.write(b"\n");
+            }
+            format!("xxh3:{:032x}", xxh3.finish_ext())
+        }
+    }
+}
+
 fn format_diff(
     diff: &Change<&str>,
     f: &mut dyn FnMut(&dyn std::fmt::Display) -> std::fmt::Result,
@@ -419,6 +956,30 @@ fn format_diff(
     }
 }
 
+/// Formats the line-by-line diff of the expected and actual texts, keeping the first `max_lines`
+/// lines (0 for unlimited) followed by a marker for the rest.
+fn format_lines_diff(expected: &str, actual: &str, max_lines: usize, colorize: bool) -> String {
+    let diff = TextDiff::from_lines(expected, actual);
+    let changes = diff.iter_all_changes().collect_vec();
+    let max_lines = if max_lines == 0 {
+        changes.len()
+    } else {
+        max_lines
+    };
+    let mut out = changes
+        .iter()
+        .take(max_lines)
+        .format_with("\n", |diff, f| format_diff(diff, f, colorize))
+        .to_string();
+    if changes.len() > max_lines {
+        out += &format!(
+            "\n(diff truncated, {} more lines)",
+            changes.len() - max_lines
+        );
+    }
+    out
+}
+
 fn format_column_diff(expected: &str, actual: &str, colorize: bool) -> String {
     let (expected, actual) = TextDiff::from_chars(expected, actual)
         .iter_all_changes()
@@ -463,72 +1024,779 @@ pub fn default_normalizer(s: &String) -> String {
     s.trim().split_ascii_whitespace().join(" ")
 }
 
-/// Validator will be used by [`Runner`] to validate the output.
+/// Normalizes like [`default_normalizer`], and also collapses the runs of the same character in
+/// the borders of rendered tables, e.g., `+----+-----+` or `────┼────`, so that tables only
+/// differing in the widths of columns are equal. Use it with [`Runner::with_normalizer`] for
+/// queries returning rendered tables, e.g., the output of `EXPLAIN`.
+///
+/// A border is a line of only `+`, `-`, `=`, `|`, `:` and box-drawing characters, apart from the
+/// leading and trailing whitespace, with both a horizontal line (`-`, `=`, `─`, `━` or `═`) and a
+/// junction or edge (`+`, `|` or the other box-drawing characters). So values like `--`, and rows
+/// like `| -- |`, whose cells are padded, are kept as is. The padding in the other lines is already
+/// collapsed by [`default_normalizer`].
+#[allow(clippy::ptr_arg)]
+pub fn table_normalizer(s: &String) -> String {
+    let is_horizontal = |c: char| matches!(c, '-' | '=' | '\u{2500}' | '\u{2501}' | '\u{2550}');
+    let is_junction =
+        |c: char| matches!(c, '+' | '|' | '\u{2500}'..='\u{257F}') && !is_horizontal(c);
+    let is_border = |c: char| is_horizontal(c) || is_junction(c) || c == ':';
+    let line = s.trim();
+    if line.chars().all(is_border)
+        && line.chars().any(is_horizontal)
+        && line.chars().any(is_junction)
+    {
+        line.chars().dedup().collect()
+    } else {
+        default_normalizer(s)
+    }
+}
+
+/// ErrorTransformer will be used by [`Runner`] to transform the messages of the errors of
+/// statements and queries, before matching them against the expected errors and before writing
+/// them with `--override`.
 ///
 /// # Default
 ///
-/// By default, the ([`default_validator`]) will be used compare normalized results.
-pub type Validator =
-    fn(normalizer: Normalizer, actual: &[Vec<String>], expected: &[String]) -> bool;
+/// By default, the ([`default_error_transformer`]) will be used to keep messages as is.
+pub type ErrorTransformer = fn(err: &str) -> String;
 
-pub fn default_validator(
-    normalizer: Normalizer,
-    actual: &[Vec<String>],
-    expected: &[String],
-) -> bool {
-    let expected_results = expected.iter().map(normalizer).collect_vec();
-    // Default, we compare normalized results. Whitespace characters are ignored.
-    let normalized_rows = actual
-        .iter()
-        .map(|strs| strs.iter().map(normalizer).join(" "))
-        .collect_vec();
+/// Keeps the error message as is.
+pub fn default_error_transformer(err: &str) -> String {
+    err.to_owned()
+}
+
+/// An error making the connection of a record, which fails the record even if it expects an
+/// error.
+struct ConnectError(AnyError);
 
-    normalized_rows == expected_results
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
 }
 
-/// [`Runner`] uses this validator to check that the expected column types match an actual output.
-///
-/// # Default
-///
-/// By default ([`default_column_validator`]), columns are not validated.
-pub type ColumnTypeValidator<T> = fn(actual: &Vec<T>, expected: &Vec<T>) -> bool;
+impl std::fmt::Debug for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.0, f)
+    }
+}
 
-/// The default validator always returns success for any inputs of expected and actual sets of
-/// columns.
-pub fn default_column_validator<T: ColumnType>(_: &Vec<T>, _: &Vec<T>) -> bool {
-    true
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
 }
 
-/// The strict validator checks:
-/// - the number of columns is as expected
-/// - each column has the same type as expected
-#[allow(clippy::ptr_arg)]
-pub fn strict_column_validator<T: ColumnType>(actual: &Vec<T>, expected: &Vec<T>) -> bool {
-    actual.len() == expected.len()
-        && !actual
-            .iter()
-            .zip(expected.iter())
-            .any(|(actual_column, expected_column)| actual_column != expected_column)
+/// An error whose message is transformed by an [`ErrorTransformer`].
+struct TransformedError {
+    message: String,
+    original: AnyError,
 }
 
-/// Sqllogictest runner.
-pub struct Runner<D: AsyncDB, M: MakeConnection> {
-    conn: Connections<D, M>,
-    // validator is used for validate if the result of query equals to expected.
-    validator: Validator,
-    // normalizer is used to normalize the result text
-    normalizer: Normalizer,
-    column_type_validator: ColumnTypeValidator<D::ColumnType>,
-    substitution: Option<Substitution>,
-    sort_mode: Option<SortMode>,
-    result_mode: Option<ResultMode>,
-    /// 0 means never hashing
-    hash_threshold: usize,
-    /// Labels for condition `skipif` and `onlyif`.
-    labels: HashSet<String>,
+impl std::fmt::Display for TransformedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
-impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
+impl std::fmt::Debug for TransformedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.original, f)
+    }
+}
+
+impl std::error::Error for TransformedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.original.source()
+    }
+}
+
+/// Applies the transformer to the message of the error. The error is kept as is if the message
+/// is unchanged.
+fn transform_error(
+    transformer: ErrorTransformer,
+    err: impl std::error::Error + Send + Sync + 'static,
+) -> AnyError {
+    let original = err.to_string();
+    let message = transformer(&original);
+    if message == original {
+        return Arc::new(err);
+    }
+    Arc::new(TransformedError {
+        message,
+        original: Arc::new(err),
+    })
+}
+
+/// Normalizes a single actual value with the normalizer.
+///
+/// Together with [`normalize_row`] and [`normalize_expected`], these are the building blocks of
+/// [`default_validator`], so that custom validators can normalize values the same way.
+#[allow(clippy::ptr_arg)]
+pub fn normalize_cell(normalizer: Normalizer, cell: &String) -> String {
+    normalizer(cell)
+}
+
+/// Normalizes a row of actual values into a line, by joining the normalized values with a space.
+/// The line equals the corresponding expected line normalized by [`normalize_expected`] if they
+/// match by [`default_validator`].
+pub fn normalize_row(normalizer: Normalizer, row: &[String]) -> String {
+    row.iter()
+        .map(|cell| normalize_cell(normalizer, cell))
+        .join(" ")
+}
+
+/// Normalizes a line of the expected results with the normalizer.
+#[allow(clippy::ptr_arg)]
+pub fn normalize_expected(normalizer: Normalizer, line: &String) -> String {
+    normalizer(line)
+}
+
+/// Sorts the rows for `rowsort`. If `nulls_last`, `NULL` values are placed after the non-NULL
+/// values in the same column, instead of being sorted as text.
+fn sort_rows(rows: &mut [Vec<String>], nulls_last: bool) {
+    if !nulls_last {
+        rows.sort_unstable();
+        return;
+    }
+    rows.sort_unstable_by(|a, b| {
+        a.iter()
+            .map(|v| (v == "NULL", v))
+            .cmp(b.iter().map(|v| (v == "NULL", v)))
+    });
+}
+
+/// Lowercases the actual and expected results to compare them case-insensitively.
+fn lowercase_results(
+    actual: &[Vec<String>],
+    expected: &[String],
+) -> (Vec<Vec<String>>, Vec<String>) {
+    (
+        actual
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_lowercase()).collect())
+            .collect(),
+        expected.iter().map(|v| v.to_lowercase()).collect(),
+    )
+}
+
+/// Rounds the numeric values in the actual and expected results to `sigfigs` significant figures,
+/// for `sigfig=<n>`, so that the validator compares the rounded values.
+fn round_results(
+    actual: &[Vec<String>],
+    expected: &[String],
+    sigfigs: usize,
+) -> (Vec<Vec<String>>, Vec<String>) {
+    let round = |s: &str| {
+        s.split(' ')
+            .map(|v| match v.parse::<f64>() {
+                Ok(x) => round_sigfig(x, sigfigs),
+                Err(_) => v.to_string(),
+            })
+            .join(" ")
+    };
+    (
+        actual
+            .iter()
+            .map(|row| row.iter().map(|v| round(v)).collect())
+            .collect(),
+        expected.iter().map(|v| round(v)).collect(),
+    )
+}
+
+/// Removes the trailing spaces of the values in the columns whose actual or expected type
+/// [trims them](ColumnType::trims_trailing_space), e.g., padded `CHAR(n)` values.
+fn trim_trailing_spaces<T: ColumnType>(
+    rows: &mut [Vec<String>],
+    types: &[T],
+    expected_types: &[T],
+) {
+    let trims = |types: &[T], i: usize| types.get(i).is_some_and(T::trims_trailing_space);
+    let columns = (0..types.len().max(expected_types.len()))
+        .filter(|&i| trims(types, i) || trims(expected_types, i))
+        .collect_vec();
+    if columns.is_empty() {
+        return;
+    }
+    for row in rows {
+        for &i in &columns {
+            if let Some(value) = row.get_mut(i) {
+                value.truncate(value.trim_end_matches(' ').len());
+            }
+        }
+    }
+}
+
+/// The error of a `cols=` index out of range for the actual results.
+#[derive(thiserror::Error, Debug)]
+#[error("column {index} in `cols=` is out of range for a row of {num_columns} columns")]
+struct ColumnOutOfRange {
+    index: usize,
+    num_columns: usize,
+}
+
+/// Keeps only the given columns of the rows, in the given order. A column may be given more than
+/// once.
+fn project_columns(
+    rows: Vec<Vec<String>>,
+    columns: &[usize],
+) -> Result<Vec<Vec<String>>, ColumnOutOfRange> {
+    rows.into_iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|&index| {
+                    row.get(index).cloned().ok_or(ColumnOutOfRange {
+                        index,
+                        num_columns: row.len(),
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The error of a `resultset=` index out of range for the actual outputs.
+#[derive(thiserror::Error, Debug)]
+#[error("result set {index} in `resultset=` is out of range for {num_sets} result sets")]
+struct ResultSetOutOfRange {
+    index: usize,
+    num_sets: usize,
+}
+
+/// Picks the output to validate out of [`DBOutput::MultipleResultSets`]: the one at `index`, or
+/// the last one if not given. Nested sets are flattened the same way, and no sets at all are
+/// treated as a completed statement.
+fn select_result_set<T: ColumnType>(
+    output: DBOutput<T>,
+    index: Option<usize>,
+) -> Result<DBOutput<T>, ResultSetOutOfRange> {
+    match output {
+        DBOutput::MultipleResultSets(mut sets) => {
+            let num_sets = sets.len();
+            let set = match index {
+                Some(index) if index < num_sets => sets.swap_remove(index),
+                Some(index) => return Err(ResultSetOutOfRange { index, num_sets }),
+                None => match sets.pop() {
+                    Some(set) => set,
+                    None => return Ok(DBOutput::StatementComplete(0)),
+                },
+            };
+            select_result_set(set, None)
+        }
+        output => Ok(output),
+    }
+}
+
+/// The error of a statement in a `statement batch`.
+#[derive(thiserror::Error, Debug)]
+#[error("error in statement {index} of the batch at offset {offset}: {err}")]
+struct BatchStatementError {
+    /// The 1-based index of the statement in the batch.
+    index: usize,
+    /// The byte offset of the statement in the SQL.
+    offset: usize,
+    err: AnyError,
+}
+
+/// Splits the SQL of a `statement batch` into statements, each with its byte offset in the SQL
+/// and its terminating semicolon, if any. Semicolons in quoted strings and identifiers,
+/// dollar-quoted strings and comments don't terminate statements.
+fn split_statements(sql: &str) -> Vec<(usize, &str)> {
+    let bytes = sql.as_bytes();
+    let mut statements = vec![];
+    let mut push = |start: usize, end: usize| {
+        let statement = sql[start..end].trim_start();
+        if !statement.trim_end_matches(';').trim().is_empty() {
+            statements.push((end - statement.len(), statement.trim_end()));
+        }
+    };
+    // Returns the end of the first `pat` in `sql[from..]`, or the end of the SQL if unclosed.
+    let skip_past = |from: usize, pat: &str| {
+        sql[from..]
+            .find(pat)
+            .map_or(sql.len(), |i| from + i + pat.len())
+    };
+
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        i = match bytes[i] {
+            // An escaped quote (`''`) is skipped as two adjacent strings.
+            quote @ (b'\'' | b'"') => skip_past(i + 1, if quote == b'"' { "\"" } else { "'" }),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => skip_past(i + 2, "\n"),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => skip_past(i + 2, "*/"),
+            // A dollar quote is `$$` or `$tag$`, but not a parameter like `$1`.
+            b'$' => {
+                let tag_len = sql[i + 1..]
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(sql.len() - i - 1);
+                let tag_end = i + 1 + tag_len;
+                if bytes.get(tag_end) == Some(&b'$')
+                    && !bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+                {
+                    skip_past(tag_end + 1, &sql[i..=tag_end])
+                } else {
+                    i + 1
+                }
+            }
+            b';' => {
+                push(start, i + 1);
+                start = i + 1;
+                i + 1
+            }
+            _ => i + 1,
+        };
+    }
+    push(start, sql.len());
+    statements
+}
+
+/// Runs the statements of a `statement batch` one by one, stopping at the first failure. The
+/// counts of the statements are summed up.
+async fn run_batch<D: AsyncDB>(
+    conn: &mut D,
+    sql: &str,
+    trailing_semicolon: TrailingSemicolon,
+    error_transformer: ErrorTransformer,
+) -> RecordOutput<D::ColumnType> {
+    let mut total = 0;
+    for (index, (offset, statement)) in split_statements(sql).into_iter().enumerate() {
+        match conn
+            .run(&trailing_semicolon.apply(statement.to_owned()))
+            .await
+        {
+            Ok(out) => {
+                if let Ok(DBOutput::StatementComplete(count)) = select_result_set(out, None) {
+                    total += count;
+                }
+            }
+            Err(err) => {
+                return RecordOutput::Statement {
+                    count: total,
+                    error: Some(Arc::new(BatchStatementError {
+                        index: index + 1,
+                        offset,
+                        err: transform_error(error_transformer, err),
+                    })),
+                }
+            }
+        }
+    }
+    RecordOutput::Statement {
+        count: total,
+        error: None,
+    }
+}
+
+/// A query run by [`Runner::run_parallel_queries_async`], or a record run in the background by
+/// [`Runner::run_concurrent_records_async`].
+struct ParallelRun<D: AsyncDB> {
+    record: Record<D::ColumnType>,
+    /// The output of the SQL, or the substitution error.
+    output: Result<Result<DBOutput<D::ColumnType>, D::Error>, AnyError>,
+    elapsed: Option<Duration>,
+    warnings: Option<Vec<String>>,
+}
+
+/// Runs the SQL of the query on any of the idle connections, which are as many as the queries
+/// running at a time.
+async fn run_on_any<D: AsyncDB>(
+    conns: &std::sync::Mutex<Vec<D>>,
+    record: Record<D::ColumnType>,
+    sql: Result<String, AnyError>,
+) -> ParallelRun<D> {
+    let mut conn = conns.lock().unwrap().pop().expect("no idle connection");
+    let run = run_on(&mut conn, record, sql).await;
+    conns.lock().unwrap().push(conn);
+    run
+}
+
+/// Runs the substituted SQL of the record on the connection.
+async fn run_on<D: AsyncDB>(
+    conn: &mut D,
+    record: Record<D::ColumnType>,
+    sql: Result<String, AnyError>,
+) -> ParallelRun<D> {
+    let sql = match sql {
+        Ok(sql) => sql,
+        Err(e) => {
+            return ParallelRun {
+                record,
+                output: Err(e),
+                elapsed: None,
+                warnings: None,
+            }
+        }
+    };
+    conn.take_warnings();
+    let start = Instant::now();
+    let output = conn.run(&sql).await;
+    let elapsed = start.elapsed();
+    let warnings = conn.take_warnings();
+    ParallelRun {
+        record,
+        output: Ok(output),
+        elapsed: Some(elapsed),
+        warnings: Some(warnings),
+    }
+}
+
+/// A record to run in the background, with its substituted SQL.
+type BackgroundRecord<T> = (Record<T>, Result<String, AnyError>);
+
+/// The named connections running records in the background under `control concurrent on`.
+struct Lanes<'a, D: AsyncDB + 'a> {
+    lanes: HashMap<Connection, Lane<D>>,
+    running: FuturesUnordered<BoxFuture<'a, (Connection, D, ParallelRun<D>)>>,
+}
+
+/// A connection running its background records one at a time in the order of the file.
+struct Lane<D: AsyncDB> {
+    /// The connection, or `None` if it's running a record.
+    conn: Option<D>,
+    engine_name: String,
+    /// The records waiting for the running one.
+    queued: VecDeque<BackgroundRecord<D::ColumnType>>,
+    /// The location of the last record of the connection, to report timeouts at.
+    loc: Location,
+}
+
+impl<'a, D: AsyncDB + 'a> Lanes<'a, D> {
+    fn new() -> Self {
+        Lanes {
+            lanes: HashMap::new(),
+            running: FuturesUnordered::new(),
+        }
+    }
+
+    fn add(&mut self, connection: Connection, conn: D, loc: Location) {
+        let lane = Lane {
+            engine_name: conn.engine_name().to_owned(),
+            conn: Some(conn),
+            queued: VecDeque::new(),
+            loc,
+        };
+        self.lanes.insert(connection, lane);
+    }
+
+    /// Runs the record on the connection once it's done with the records before.
+    fn push(
+        &mut self,
+        connection: Connection,
+        record: Record<D::ColumnType>,
+        sql: Result<String, AnyError>,
+    ) {
+        let lane = self.lanes.get_mut(&connection).expect("no lane");
+        lane.loc = record.loc().expect("no location").clone();
+        match lane.conn.take() {
+            Some(conn) => self.start(connection, conn, record, sql),
+            None => lane.queued.push_back((record, sql)),
+        }
+    }
+
+    fn start(
+        &mut self,
+        connection: Connection,
+        mut conn: D,
+        record: Record<D::ColumnType>,
+        sql: Result<String, AnyError>,
+    ) {
+        self.running.push(
+            async move {
+                let run = run_on(&mut conn, record, sql).await;
+                (connection, conn, run)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Runs the next record of the connection that completed a record, and returns the
+    /// completed one.
+    fn complete(
+        &mut self,
+        (connection, conn, run): (Connection, D, ParallelRun<D>),
+    ) -> ParallelRun<D> {
+        let lane = self.lanes.get_mut(&connection).expect("no lane");
+        match lane.queued.pop_front() {
+            Some((record, sql)) => self.start(connection, conn, record, sql),
+            None => lane.conn = Some(conn),
+        }
+        run
+    }
+
+    /// Returns the number of records the connection is running or going to run.
+    fn pending(&self, connection: &Connection) -> usize {
+        match self.lanes.get(connection) {
+            Some(Lane {
+                conn: None, queued, ..
+            }) => 1 + queued.len(),
+            _ => 0,
+        }
+    }
+
+    /// Runs `fut` while running the background records, collecting those completed into `done`.
+    async fn drive<T>(
+        &mut self,
+        fut: impl Future<Output = T>,
+        done: &mut Vec<ParallelRun<D>>,
+    ) -> T {
+        let mut fut = std::pin::pin!(fut);
+        loop {
+            match future::select(fut.as_mut(), self.running.next()).await {
+                Either::Left((output, _)) => return output,
+                Either::Right((Some(run), _)) => done.push(self.complete(run)),
+                Either::Right((None, _)) => return fut.await,
+            }
+        }
+    }
+}
+
+/// Returns the output of the statement or query failing with the error, e.g., of substitution.
+fn error_output<T: ColumnType>(record: &Record<T>, error: AnyError) -> RecordOutput<T> {
+    match record {
+        Record::Query { .. } => RecordOutput::Query {
+            error: Some(error),
+            types: vec![],
+            rows: vec![],
+        },
+        _ => RecordOutput::Statement {
+            count: 0,
+            error: Some(error),
+        },
+    }
+}
+
+/// Returns whether we should skip this record, according to given `conditions`.
+///
+/// If so, and `skipped` is collecting, the record is added to it.
+fn should_skip(
+    labels: &HashSet<String>,
+    engine_name: Option<&str>,
+    conditions: &[Condition],
+    loc: &Location,
+    skipped: &mut Option<Vec<SkippedRecord>>,
+) -> bool {
+    let labels = || {
+        labels
+            .iter()
+            .map(|l| l.as_str())
+            // attach the engine name to the labels
+            .chain(engine_name.filter(|n| !n.is_empty()))
+    };
+    let Some(condition) = conditions.iter().find(|c| c.should_skip(labels())) else {
+        return false;
+    };
+    if let Some(skipped) = skipped {
+        skipped.push(SkippedRecord {
+            loc: loc.clone(),
+            condition: condition.clone(),
+            labels: labels().map(|l| l.to_owned()).sorted().collect(),
+        });
+    }
+    true
+}
+
+/// Replaces the matches of each pattern with its token, in order.
+fn scrub<'a>(scrubbers: &[(Regex, String)], s: &'a str) -> Cow<'a, str> {
+    let mut s = Cow::Borrowed(s);
+    for (pattern, token) in scrubbers {
+        if let Cow::Owned(replaced) = pattern.replace_all(&s, regex::NoExpand(token)) {
+            s = Cow::Owned(replaced);
+        }
+    }
+    s
+}
+
+/// How the trailing semicolon of the SQL is treated before execution. Only the end of the SQL is
+/// touched, so the semicolons separating multiple statements are kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSemicolon {
+    /// The SQL is executed as written.
+    #[default]
+    AsIs,
+    /// A single trailing semicolon is removed, if any.
+    Strip,
+    /// A semicolon is appended, if the SQL doesn't end with one.
+    Require,
+}
+
+impl TrailingSemicolon {
+    fn apply(self, sql: String) -> String {
+        let trimmed = sql.trim_end();
+        match self {
+            Self::AsIs => sql,
+            Self::Strip => match trimmed.strip_suffix(';') {
+                Some(stripped) => stripped.to_owned(),
+                None => sql,
+            },
+            Self::Require if trimmed.ends_with(';') => sql,
+            Self::Require => format!("{trimmed};"),
+        }
+    }
+}
+
+/// Validator will be used by [`Runner`] to validate the output.
+///
+/// # Default
+///
+/// By default, the ([`default_validator`]) will be used compare normalized results.
+pub type Validator =
+    fn(normalizer: Normalizer, actual: &[Vec<String>], expected: &[String]) -> bool;
+
+pub fn default_validator(
+    normalizer: Normalizer,
+    actual: &[Vec<String>],
+    expected: &[String],
+) -> bool {
+    // Default, we compare normalized results. Whitespace characters are ignored.
+    // Rows are normalized lazily so that we can bail out on the first mismatch.
+    actual.len() == expected.len()
+        && actual.iter().zip(expected).all(|(row, expected)| {
+            normalize_row(normalizer, row) == normalize_expected(normalizer, expected)
+        })
+}
+
+/// Returns a validator that compares the numeric values rounded to `sigfigs` significant figures,
+/// and other values as [`default_validator`] does. Numbers may be written in scientific notation.
+///
+/// Queries with `sigfig=<n>`, e.g., `query R sigfig=6`, round the values the same way before the
+/// configured validator instead. To use it for all queries, wrap it in a closure:
+///
+/// ```ignore
+/// runner.with_validator(|normalizer, actual, expected| {
+///     sigfig_validator(6)(normalizer, actual, expected)
+/// });
+/// ```
+pub fn sigfig_validator(sigfigs: usize) -> impl Fn(Normalizer, &[Vec<String>], &[String]) -> bool {
+    move |normalizer, actual, expected| {
+        actual.len() == expected.len()
+            && actual.iter().zip(expected).all(|(row, expected)| {
+                let actual = normalize_row(normalizer, row);
+                let expected = normalize_expected(normalizer, expected);
+                let mut actual = actual.split_ascii_whitespace();
+                let mut expected = expected.split_ascii_whitespace();
+                loop {
+                    match (actual.next(), expected.next()) {
+                        (None, None) => return true,
+                        (Some(a), Some(e)) if a == e => {}
+                        (Some(a), Some(e)) => match (a.parse::<f64>(), e.parse::<f64>()) {
+                            (Ok(a), Ok(e))
+                                if round_sigfig(a, sigfigs) == round_sigfig(e, sigfigs) => {}
+                            _ => return false,
+                        },
+                        _ => return false,
+                    }
+                }
+            })
+    }
+}
+
+/// Formats the number in scientific notation with `sigfigs` significant figures.
+fn round_sigfig(x: f64, sigfigs: usize) -> String {
+    // `-0` is not distinguished from `0`.
+    let x = if x == 0.0 { 0.0 } else { x };
+    format!("{:.*e}", sigfigs.saturating_sub(1), x)
+}
+
+/// [`Runner`] uses this validator to check that the expected column types match an actual output.
+///
+/// # Default
+///
+/// By default ([`default_column_validator`]), columns are not validated.
+pub type ColumnTypeValidator<T> = fn(actual: &Vec<T>, expected: &Vec<T>) -> bool;
+
+/// The default validator always returns success for any inputs of expected and actual sets of
+/// columns.
+pub fn default_column_validator<T: ColumnType>(_: &Vec<T>, _: &Vec<T>) -> bool {
+    true
+}
+
+/// The strict validator checks:
+/// - the number of columns is as expected
+/// - each column has the same type as expected, unless the expected type is `?` (e.g.,
+///   [`DefaultColumnType::Any`]), which matches any type. For example, `query I?I` checks the
+///   types of the first and the last columns only.
+#[allow(clippy::ptr_arg)]
+pub fn strict_column_validator<T: ColumnType>(actual: &Vec<T>, expected: &Vec<T>) -> bool {
+    actual.len() == expected.len()
+        && !actual
+            .iter()
+            .zip(expected.iter())
+            .any(|(actual_column, expected_column)| {
+                expected_column.to_char() != '?' && actual_column != expected_column
+            })
+}
+
+/// Sqllogictest runner.
+pub struct Runner<D: AsyncDB, M: MakeConnection> {
+    conn: Connections<D, M>,
+    // validator is used for validate if the result of query equals to expected.
+    validator: Validator,
+    // normalizer is used to normalize the result text
+    normalizer: Normalizer,
+    /// Patterns replaced in both the expected and actual results before comparison.
+    scrubbers: Vec<(Regex, String)>,
+    error_transformer: ErrorTransformer,
+    trailing_semicolon: TrailingSemicolon,
+    column_type_validator: ColumnTypeValidator<D::ColumnType>,
+    substitution: Option<Substitution>,
+    sort_mode: Option<SortMode>,
+    result_mode: Option<ResultMode>,
+    error_mode: ErrorMode,
+    /// Errors matching this pattern fail the record, even if the error is expected.
+    forbidden_error: Option<Regex>,
+    /// 0 means never hashing
+    hash_threshold: usize,
+    /// The hash threshold set by [`Runner::with_hash_threshold`], which `hash_threshold` is reset
+    /// to when a new script begins.
+    default_hash_threshold: usize,
+    /// Whether to normalize the values before hashing.
+    hash_normalize: bool,
+    /// The algorithm to hash the results with.
+    hash_algo: HashAlgo,
+    /// Whether `rowsort` places `NULL` values last.
+    rowsort_nulls_last: bool,
+    /// Whether consecutive queries run concurrently, set by `control parallel`.
+    parallel: bool,
+    /// The maximum number of queries run concurrently under `control parallel on`.
+    max_parallel_queries: usize,
+    /// Whether query results are compared case-insensitively, as if every query had `nocase`.
+    case_insensitive: bool,
+    scope: Scope,
+    /// The location of the `subtest` whose savepoint is active on the default connection.
+    subtest_savepoint: Option<Location>,
+    /// How long the SQL of the last statement or query took to run, checked against `maxtime`.
+    elapsed: Option<Duration>,
+    /// The warnings emitted by the SQL of the last statement or query, checked against
+    /// `warnings <n>`.
+    warnings: Option<Vec<String>>,
+    /// The number of times to run the next statement or query, set by `control repeat`.
+    repeat: Option<u64>,
+    /// The number of statements and queries run on each connection, checked by `control await`.
+    executed: HashMap<Connection, usize>,
+    /// Labels for condition `skipif` and `onlyif`.
+    labels: HashSet<String>,
+    /// The versions of the labels added with [`Runner::add_versioned_label`].
+    label_versions: HashMap<String, String>,
+    /// The labels that may be used in conditions besides `labels`, checked by
+    /// [`Runner::check_conditions`].
+    known_labels: HashSet<String>,
+    /// The records skipped by conditions, if collecting.
+    skipped: Option<Vec<SkippedRecord>>,
+    /// Whether the engine name is implicitly a label.
+    implicit_engine_label: bool,
+    /// Prints the text of `echo` records.
+    echo: Arc<dyn Fn(&str) + Send + Sync>,
+    /// Whether expected errors must have a non-empty message.
+    require_error_message: bool,
+    /// The maximum number of lines of the diff in query result mismatches, or 0 for unlimited.
+    max_diff_lines: usize,
+    /// The variables bound by the arguments of the `include` records being run.
+    variables: Vec<Vec<(String, String)>>,
+}
+
+impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
     /// Create a new test runner on the database, with the given connection maker.
     ///
     /// See [`MakeConnection`] for more details.
@@ -536,12 +1804,38 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         Runner {
             validator: default_validator,
             normalizer: default_normalizer,
+            scrubbers: vec![],
+            error_transformer: default_error_transformer,
+            trailing_semicolon: TrailingSemicolon::default(),
             column_type_validator: default_column_validator,
             substitution: None,
             sort_mode: None,
             result_mode: None,
+            error_mode: ErrorMode::default(),
+            forbidden_error: None,
             hash_threshold: 0,
+            default_hash_threshold: 0,
+            hash_normalize: false,
+            hash_algo: HashAlgo::default(),
+            rowsort_nulls_last: false,
+            parallel: false,
+            max_parallel_queries: DEFAULT_MAX_PARALLEL_QUERIES,
+            case_insensitive: false,
+            scope: Scope::default(),
+            subtest_savepoint: None,
+            elapsed: None,
+            warnings: None,
+            repeat: None,
+            executed: HashMap::new(),
             labels: HashSet::new(),
+            label_versions: HashMap::new(),
+            known_labels: HashSet::new(),
+            skipped: None,
+            implicit_engine_label: true,
+            echo: Arc::new(|text| println!("{text}")),
+            require_error_message: false,
+            max_diff_lines: 0,
+            variables: vec![],
             conn: Connections::new(make_conn),
         }
     }
@@ -551,56 +1845,312 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         self.labels.insert(label.to_string());
     }
 
-    pub fn with_normalizer(&mut self, normalizer: Normalizer) {
-        self.normalizer = normalizer;
-    }
-    pub fn with_validator(&mut self, validator: Validator) {
-        self.validator = validator;
+    /// Add a label with a version, e.g., `postgres` and `14.2`, checked against the
+    /// `# requires: <label>>=<version>` header of test files.
+    pub fn add_versioned_label(&mut self, label: &str, version: &str) {
+        self.add_label(label);
+        self.label_versions
+            .insert(label.to_string(), version.to_string());
     }
 
-    pub fn with_column_validator(&mut self, validator: ColumnTypeValidator<D::ColumnType>) {
-        self.column_type_validator = validator;
+    /// Add a label that may be used in conditions without being added by [`Runner::add_label`],
+    /// e.g., the name of another engine, so that [`Runner::check_conditions`] accepts it.
+    pub fn add_known_label(&mut self, label: &str) {
+        self.known_labels.insert(label.to_string());
     }
 
-    pub fn with_hash_threshold(&mut self, hash_threshold: usize) {
-        self.hash_threshold = hash_threshold;
+    /// Returns the conditions of the records with labels neither added by [`Runner::add_label`]
+    /// nor by [`Runner::add_known_label`], which are usually typos, e.g., `skipif postgre`, that
+    /// silently never skip or always skip the records.
+    ///
+    /// The engine name is only known after connecting, so the names of the engines that the
+    /// records may run on should be added as known labels.
+    pub fn check_conditions(&self, records: &[Record<D::ColumnType>]) -> Vec<UnknownLabelWarning> {
+        let mut warnings = vec![];
+        for record in records {
+            let (Record::Statement { loc, .. }
+            | Record::Query { loc, .. }
+            | Record::System { loc, .. }) = record
+            else {
+                continue;
+            };
+            for condition in record.conditions() {
+                let label = condition.label();
+                if !self.labels.contains(label) && !self.known_labels.contains(label) {
+                    warnings.push(UnknownLabelWarning {
+                        loc: loc.clone(),
+                        condition: condition.clone(),
+                    });
+                }
+            }
+        }
+        warnings
     }
 
-    pub async fn apply_record(
-        &mut self,
-        record: Record<D::ColumnType>,
-    ) -> RecordOutput<D::ColumnType> {
-        tracing::debug!(?record, "testing");
-        /// Returns whether we should skip this record, according to given `conditions`.
-        fn should_skip(
-            labels: &HashSet<String>,
-            engine_name: &str,
-            conditions: &[Condition],
-        ) -> bool {
-            conditions.iter().any(|c| {
-                c.should_skip(
-                    labels
-                        .iter()
-                        .map(|l| l.as_str())
-                        // attach the engine name to the labels
-                        .chain(Some(engine_name).filter(|n| !n.is_empty())),
-                )
+    /// Returns the requirements in the `# requires:` header of a test file not met by the labels,
+    /// in which case the file should be skipped. [`Runner::run_file_async`] skips such files.
+    ///
+    /// Only the labels added by [`Runner::add_label`] and [`Runner::add_versioned_label`] are
+    /// checked, not the engine name, which is only known after connecting.
+    pub fn unmet_requirements<'a>(&self, metadata: &'a FileMetadata) -> Vec<&'a Requirement> {
+        metadata
+            .requires
+            .iter()
+            .filter(|requirement| {
+                !self.labels.iter().any(|label| {
+                    let version = self.label_versions.get(label).map(String::as_str);
+                    requirement.is_met_by(label, version)
+                })
             })
+            .collect()
+    }
+
+    /// Take a snapshot of the state changed by running scripts, e.g., `control sortmode`, to
+    /// restore it later with [`Runner::restore_state`].
+    ///
+    /// The state is sticky across scripts run by the same runner. Snapshot it before running a
+    /// script and restore it after to isolate the scripts, or keep it to carry the state over.
+    pub fn snapshot_state(&self) -> RunnerState {
+        RunnerState {
+            labels: self.labels.clone(),
+            label_versions: self.label_versions.clone(),
+            sort_mode: self.sort_mode,
+            result_mode: self.result_mode,
+            error_mode: self.error_mode,
+            forbidden_error: self.forbidden_error.clone(),
+            hash_threshold: self.hash_threshold,
+            hash_normalize: self.hash_normalize,
+            hash_algo: self.hash_algo,
+            rowsort_nulls_last: self.rowsort_nulls_last,
+            parallel: self.parallel,
+            scope: self.scope,
+            repeat: self.repeat,
+            substitution: self.substitution.clone(),
+            variables: self.variables.clone(),
+            executed: self.executed.clone(),
         }
+    }
 
-        match record {
-            Record::Statement {
-                conditions,
-                connection,
-                sql,
+    /// Restore the state taken by [`Runner::snapshot_state`].
+    pub fn restore_state(&mut self, state: RunnerState) {
+        let RunnerState {
+            labels,
+            label_versions,
+            sort_mode,
+            result_mode,
+            error_mode,
+            forbidden_error,
+            hash_threshold,
+            hash_normalize,
+            hash_algo,
+            rowsort_nulls_last,
+            parallel,
+            scope,
+            repeat,
+            substitution,
+            variables,
+            executed,
+        } = state;
+        self.labels = labels;
+        self.label_versions = label_versions;
+        self.sort_mode = sort_mode;
+        self.result_mode = result_mode;
+        self.error_mode = error_mode;
+        self.forbidden_error = forbidden_error;
+        self.hash_threshold = hash_threshold;
+        self.hash_normalize = hash_normalize;
+        self.hash_algo = hash_algo;
+        self.rowsort_nulls_last = rowsort_nulls_last;
+        self.parallel = parallel;
+        self.scope = scope;
+        self.repeat = repeat;
+        self.substitution = substitution;
+        self.variables = variables;
+        self.executed = executed;
+    }
 
-                // compare result in run_async
-                expected: _,
-                loc: _,
-                retry: _,
+    /// Whether the engine name of the connection ([`AsyncDB::engine_name`]) is implicitly a label
+    /// for condition `skipif` and `onlyif`. Defaults to `true`.
+    ///
+    /// If disabled, only the labels added by [`Runner::add_label`] are used. To keep matching the
+    /// engine name in this case, add it explicitly with `add_label`.
+    pub fn with_implicit_engine_label(&mut self, enabled: bool) {
+        self.implicit_engine_label = enabled;
+    }
+
+    /// Whether to collect the records skipped by conditions `skipif` and `onlyif`, which can be
+    /// taken by [`Runner::take_skipped_records`]. Defaults to `false`.
+    pub fn with_skip_diagnostics(&mut self, enabled: bool) {
+        self.skipped = enabled.then(Vec::new);
+    }
+
+    /// Takes the records skipped by conditions since the last call, if
+    /// [`Runner::with_skip_diagnostics`] is enabled.
+    pub fn take_skipped_records(&mut self) -> Vec<SkippedRecord> {
+        self.skipped
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Replace the matches of each pattern with the given token, in both the expected and actual
+    /// results of queries before comparing them, e.g., to ignore volatile timestamps or paths.
+    ///
+    /// The actual results are scrubbed before being sorted or hashed, and `--override` writes the
+    /// scrubbed values.
+    pub fn with_scrubbers(&mut self, scrubbers: Vec<(Regex, String)>) {
+        self.scrubbers = scrubbers;
+    }
+
+    /// Transform the messages of the errors of statements and queries, e.g., to replace volatile
+    /// addresses or timings, before matching them against the expected errors. `--override`
+    /// writes the transformed messages. Defaults to [`default_error_transformer`].
+    pub fn with_error_transformer(&mut self, error_transformer: ErrorTransformer) {
+        self.error_transformer = error_transformer;
+    }
+
+    /// Compare the results of all queries case-insensitively, as if they had `nocase`, e.g., for
+    /// engines printing identifiers or keywords in different cases. Defaults to `false`.
+    ///
+    /// Both the actual and expected values are lowercased before being normalized and compared.
+    /// Hashes are still computed from the values as is. Unlike `nocase`, this is not seen by
+    /// [`update_record_with_output`], so `--override` rewrites the values differing in case.
+    pub fn with_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    /// How the trailing semicolon of the SQL in statements and queries is treated before
+    /// execution, so that the same test files can be run against engines that reject or require
+    /// it. Defaults to [`TrailingSemicolon::AsIs`].
+    pub fn with_trailing_semicolon(&mut self, trailing_semicolon: TrailingSemicolon) {
+        self.trailing_semicolon = trailing_semicolon;
+    }
+
+    /// Print the text of `echo` records with `echo`, instead of to the standard output.
+    pub fn with_echo(&mut self, echo: impl Fn(&str) + Send + Sync + 'static) {
+        self.echo = Arc::new(echo);
+    }
+
+    /// Whether an error only matches an expected error if its message is not empty, so that
+    /// engines failing with blank messages are caught even by `statement error` without a
+    /// pattern. Defaults to `false`.
+    pub fn with_require_error_message(&mut self, enabled: bool) {
+        self.require_error_message = enabled;
+    }
+
+    /// Truncate the diff of query result mismatches to the given number of lines, followed by a
+    /// `(diff truncated, N more lines)` marker, so that a failing query returning many rows
+    /// doesn't flood the logs. 0 means unlimited. Defaults to 0.
+    ///
+    /// The full expected and actual results are still available in the
+    /// [`TestErrorKind::QueryResultMismatch`] error.
+    pub fn with_max_diff_lines(&mut self, max_diff_lines: usize) {
+        self.max_diff_lines = max_diff_lines;
+    }
+
+    /// Set the maximum number of queries run concurrently under `control parallel on`, each on
+    /// its own connection. Defaults to 4.
+    pub fn with_max_parallel_queries(&mut self, max_parallel_queries: usize) {
+        self.max_parallel_queries = max_parallel_queries.max(1);
+    }
+
+    pub fn with_normalizer(&mut self, normalizer: Normalizer) {
+        self.normalizer = normalizer;
+    }
+    pub fn with_validator(&mut self, validator: Validator) {
+        self.validator = validator;
+    }
+
+    pub fn with_column_validator(&mut self, validator: ColumnTypeValidator<D::ColumnType>) {
+        self.column_type_validator = validator;
+    }
+
+    /// Set the hash threshold, 0 means never hashing. Defaults to 0.
+    ///
+    /// The `hash-threshold` records in a script only take effect until the end of the script: the
+    /// threshold is reset to this value when a new script begins.
+    pub fn with_hash_threshold(&mut self, hash_threshold: usize) {
+        self.hash_threshold = hash_threshold;
+        self.default_hash_threshold = hash_threshold;
+    }
+
+    /// Reset the hash threshold to the one set by [`Runner::with_hash_threshold`], discarding the
+    /// `hash-threshold` records seen so far.
+    ///
+    /// This is done by [`Runner::run_multi_async`] and the methods calling it. Call it before
+    /// running a new script if you are calling [`Runner::run_async`] on each record instead.
+    pub fn reset_hash_threshold(&mut self) {
+        self.hash_threshold = self.default_hash_threshold;
+    }
+
+    /// Reset the number of statements and queries run on each connection, checked by
+    /// `control await`, so that a new script counts them from its first record.
+    ///
+    /// This is done by [`Runner::run_multi_async`] and the methods calling it. Call it before
+    /// running a new script if you are calling [`Runner::run_async`] on each record instead.
+    pub fn reset_executed(&mut self) {
+        self.executed.clear();
+    }
+
+    /// Returns the number of connections made by the runner so far, including the named ones,
+    /// those for [`Runner::run_parallel_queries_async`], and those dropped and made again, e.g.,
+    /// after failing [`AsyncDB::ping`]. Assert it in tests to catch connections leaking or being
+    /// made again unexpectedly.
+    pub fn connection_count(&self) -> usize {
+        self.conn.made()
+    }
+
+    /// Checks the connections kept from previous runs with [`AsyncDB::ping`], and drops those
+    /// failing it, so that they're made again when they're used next time. This is done by
+    /// [`Runner::run_file_async`] and the methods calling it. Call it before running a new script
+    /// if you are reusing the runner, and thus its connections, in other ways.
+    pub async fn ping_connections(&mut self) {
+        self.conn.ping_all().await;
+    }
+
+    /// Retry making connections on transient errors, e.g., when the database is still starting up.
+    ///
+    /// See [`ConnectRetry`] for more details.
+    pub fn with_connect_retry(&mut self, retry: ConnectRetry) {
+        self.conn.set_retry(Some(retry));
+    }
+
+    /// Make the named connection `name` with `make_conn`, instead of the maker passed to
+    /// [`Runner::new`]. This allows routing records to, e.g., a read replica with
+    /// `connection replica`.
+    ///
+    /// Like other connections, the connection is made lazily when a record first uses it, and is
+    /// closed when the runner is dropped. The factories are not inherited by the runners created
+    /// in [`Runner::run_parallel_async`].
+    pub fn add_connection_factory<F>(&mut self, name: impl Into<String>, make_conn: F)
+    where
+        F: MakeConnection<Conn = D> + Send + 'static,
+        F::MakeFuture: Send + 'static,
+    {
+        self.conn.add_factory(name.into(), make_conn);
+    }
+
+    pub async fn apply_record(
+        &mut self,
+        record: Record<D::ColumnType>,
+    ) -> RecordOutput<D::ColumnType> {
+        tracing::debug!(?record, "testing");
+        match record {
+            Record::Statement {
+                conditions,
+                connection,
+                sql,
+
+                // compare result in run_async
+                expected,
+                loc,
+                maxtime: _,
+                warnings: _,
+                retry: _,
+                inline_connection: _,
             } => {
                 let sql = match self.may_substitute(sql, true) {
-                    Ok(sql) => sql,
+                    Ok(sql) => self.trailing_semicolon.apply(sql),
                     Err(error) => {
                         return RecordOutput::Statement {
                             count: 0,
@@ -614,40 +2164,51 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     Err(e) => {
                         return RecordOutput::Statement {
                             count: 0,
-                            error: Some(Arc::new(e)),
+                            error: Some(Arc::new(ConnectError(Arc::new(e)))),
                         }
                     }
                 };
-                if should_skip(&self.labels, conn.engine_name(), &conditions) {
+                let engine_name = self.implicit_engine_label.then(|| conn.engine_name());
+                if should_skip(
+                    &self.labels,
+                    engine_name,
+                    &conditions,
+                    &loc,
+                    &mut self.skipped,
+                ) {
                     return RecordOutput::Nothing;
                 }
 
-                let ret = conn.run(&sql).await;
-                match ret {
-                    Ok(out) => match out {
-                        DBOutput::Rows { types, rows } => RecordOutput::Query {
-                            types,
-                            rows,
-                            error: None,
-                        },
-                        DBOutput::StatementComplete(count) => {
-                            RecordOutput::Statement { count, error: None }
-                        }
-                    },
-                    Err(e) => RecordOutput::Statement {
-                        count: 0,
-                        error: Some(Arc::new(e)),
-                    },
+                // Discard the warnings not raised by the SQL, e.g., by savepoints of subtests.
+                conn.take_warnings();
+                if let StatementExpect::Batch = expected {
+                    let start = Instant::now();
+                    let output = run_batch(
+                        conn,
+                        &sql,
+                        self.trailing_semicolon,
+                        self.error_transformer,
+                    )
+                    .await;
+                    self.elapsed = Some(start.elapsed());
+                    self.warnings = Some(conn.take_warnings());
+                    return output;
                 }
+
+                let start = Instant::now();
+                let ret = conn.run(&sql).await;
+                self.elapsed = Some(start.elapsed());
+                self.warnings = Some(conn.take_warnings());
+                self.statement_output(ret)
             }
             Record::System {
                 conditions,
                 command,
-                loc: _,
+                loc,
                 stdout: expected_stdout,
                 retry: _,
             } => {
-                if should_skip(&self.labels, "", &conditions) {
+                if should_skip(&self.labels, None, &conditions, &loc, &mut self.skipped) {
                     return RecordOutput::Nothing;
                 }
 
@@ -675,6 +2236,10 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     cmd.arg("-c").arg(&command);
                     cmd
                 };
+                if self.substitution.is_some() {
+                    // The shell substitutes the variables of includes like environment variables.
+                    cmd.envs(self.variables.iter().flatten().map(|(k, v)| (k, v)));
+                }
 
                 if is_background {
                     // Spawn a new process, but don't wait for stdout, otherwise it will block until
@@ -745,11 +2310,14 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
 
                 // compare result in run_async
                 expected,
-                loc: _,
+                loc,
+                maxtime: _,
+                warnings: _,
                 retry: _,
+                inline_connection: _,
             } => {
                 let sql = match self.may_substitute(sql, true) {
-                    Ok(sql) => sql,
+                    Ok(sql) => self.trailing_semicolon.apply(sql),
                     Err(error) => {
                         return RecordOutput::Query {
                             error: Some(error),
@@ -763,82 +2331,29 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     Ok(conn) => conn,
                     Err(e) => {
                         return RecordOutput::Query {
-                            error: Some(Arc::new(e)),
+                            error: Some(Arc::new(ConnectError(Arc::new(e)))),
                             types: vec![],
                             rows: vec![],
                         }
                     }
                 };
-                if should_skip(&self.labels, conn.engine_name(), &conditions) {
+                let engine_name = self.implicit_engine_label.then(|| conn.engine_name());
+                if should_skip(
+                    &self.labels,
+                    engine_name,
+                    &conditions,
+                    &loc,
+                    &mut self.skipped,
+                ) {
                     return RecordOutput::Nothing;
                 }
 
-                let (types, mut rows) = match conn.run(&sql).await {
-                    Ok(out) => match out {
-                        DBOutput::Rows { types, rows } => (types, rows),
-                        DBOutput::StatementComplete(count) => {
-                            return RecordOutput::Statement { count, error: None };
-                        }
-                    },
-                    Err(e) => {
-                        return RecordOutput::Query {
-                            error: Some(Arc::new(e)),
-                            types: vec![],
-                            rows: vec![],
-                        };
-                    }
-                };
-
-                let sort_mode = match expected {
-                    QueryExpect::Results { sort_mode, .. } => sort_mode,
-                    QueryExpect::Error(_) => None,
-                }
-                .or(self.sort_mode);
-
-                let mut value_sort = false;
-                match sort_mode {
-                    None | Some(SortMode::NoSort) => {}
-                    Some(SortMode::RowSort) => {
-                        rows.sort_unstable();
-                    }
-                    Some(SortMode::ValueSort) => {
-                        rows = rows
-                            .iter()
-                            .flat_map(|row| row.iter())
-                            .map(|s| vec![s.to_owned()])
-                            .collect();
-                        rows.sort_unstable();
-                        value_sort = true;
-                    }
-                };
-
-                let num_values = if value_sort {
-                    rows.len()
-                } else {
-                    rows.len() * types.len()
-                };
-
-                if self.hash_threshold > 0 && num_values > self.hash_threshold {
-                    let mut md5 = md5::Md5::new();
-                    for line in &rows {
-                        for value in line {
-                            md5.update(value.as_bytes());
-                            md5.update(b"\n");
-                        }
-                    }
-                    let hash = format!("{:2x}", md5.finalize());
-                    rows = vec![vec![format!(
-                        "{} values hashing to {}",
-                        rows.len() * rows[0].len(),
-                        hash
-                    )]];
-                }
-
-                RecordOutput::Query {
-                    error: None,
-                    types,
-                    rows,
-                }
+                conn.take_warnings();
+                let start = Instant::now();
+                let ret = conn.run(&sql).await;
+                self.elapsed = Some(start.elapsed());
+                self.warnings = Some(conn.take_warnings());
+                self.query_output(expected, ret)
             }
             Record::Sleep { duration, .. } => {
                 D::sleep(duration).await;
@@ -852,11 +2367,42 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     Control::ResultMode(result_mode) => {
                         self.result_mode = Some(result_mode);
                     }
+                    Control::ErrorMode(error_mode) => {
+                        self.error_mode = error_mode;
+                    }
+                    Control::HashThreshold(threshold) => {
+                        self.hash_threshold = threshold as usize;
+                    }
+                    Control::HashNormalize(on_off) => {
+                        self.hash_normalize = on_off;
+                    }
+                    Control::HashAlgo(algo) => {
+                        self.hash_algo = algo;
+                    }
+                    Control::RowSortNullsLast(on_off) => {
+                        self.rowsort_nulls_last = on_off;
+                    }
+                    Control::Parallel(on_off) => {
+                        self.parallel = on_off;
+                    }
+                    // Handled by `run_concurrent_records_async`.
+                    Control::Concurrent(_) => {}
+                    Control::Scope(scope) => {
+                        self.scope = scope;
+                    }
+                    Control::Repeat(n) => {
+                        self.repeat = Some(n);
+                    }
+                    Control::ForbidError(regex) => {
+                        self.forbidden_error = regex;
+                    }
                     Control::Substitution(on_off) => match (&mut self.substitution, on_off) {
                         (s @ None, true) => *s = Some(Substitution::default()),
                         (s @ Some(_), false) => *s = None,
                         _ => {}
                     },
+                    // Checked in `run_async_no_retry`.
+                    Control::Await { .. } => {}
                 }
 
                 RecordOutput::Nothing
@@ -869,20 +2415,206 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                 tracing::error!("halt record encountered. It's likely a bug of the runtime.");
                 RecordOutput::Nothing
             }
+            Record::Injected(Injected::PushVariables(variables)) => {
+                self.variables.push(variables);
+                RecordOutput::Nothing
+            }
+            Record::Injected(Injected::PopVariables) => {
+                self.variables.pop();
+                RecordOutput::Nothing
+            }
             Record::Include { .. }
             | Record::Newline
             | Record::Comment(_)
             | Record::Subtest { .. }
+            // echo is handled in `run_async`, since substitution may fail
+            | Record::Echo { .. }
             | Record::Injected(_)
             | Record::Condition(_)
             | Record::Connection(_) => RecordOutput::Nothing,
         }
     }
 
+    /// Converts the output of the SQL of a statement to be compared with the expected results.
+    fn statement_output(
+        &self,
+        ret: Result<DBOutput<D::ColumnType>, D::Error>,
+    ) -> RecordOutput<D::ColumnType> {
+        match ret {
+            Ok(out) => match select_result_set(out, None) {
+                Ok(DBOutput::Rows { types, rows }) => RecordOutput::Query {
+                    types,
+                    rows,
+                    error: None,
+                },
+                Ok(DBOutput::StatementComplete(count)) => {
+                    RecordOutput::Statement { count, error: None }
+                }
+                Ok(DBOutput::MultipleResultSets(_)) | Err(_) => {
+                    unreachable!("result sets are flattened")
+                }
+            },
+            Err(e) => RecordOutput::Statement {
+                count: 0,
+                error: Some(transform_error(self.error_transformer, e)),
+            },
+        }
+    }
+
+    /// Processes the output of the SQL of a query, e.g., sorting and hashing the rows, to be
+    /// compared with the expected results.
+    fn query_output(
+        &self,
+        expected: QueryExpect<D::ColumnType>,
+        ret: Result<DBOutput<D::ColumnType>, D::Error>,
+    ) -> RecordOutput<D::ColumnType> {
+        let result_set = match &expected {
+            QueryExpect::Results { result_set, .. } => *result_set,
+            QueryExpect::Error(_) => None,
+        };
+        let (types, mut rows) = match ret.map(|out| select_result_set(out, result_set)) {
+            Ok(Ok(out)) => match out {
+                DBOutput::Rows { types, rows } => (types, rows),
+                DBOutput::StatementComplete(count) => {
+                    return RecordOutput::Statement { count, error: None };
+                }
+                DBOutput::MultipleResultSets(_) => unreachable!("result sets are flattened"),
+            },
+            Ok(Err(e)) => {
+                return RecordOutput::Query {
+                    error: Some(Arc::new(e)),
+                    types: vec![],
+                    rows: vec![],
+                };
+            }
+            Err(e) => {
+                return RecordOutput::Query {
+                    error: Some(transform_error(self.error_transformer, e)),
+                    types: vec![],
+                    rows: vec![],
+                };
+            }
+        };
+
+        if !self.scrubbers.is_empty() {
+            for value in rows.iter_mut().flatten() {
+                if let Cow::Owned(scrubbed) = scrub(&self.scrubbers, value) {
+                    *value = scrubbed;
+                }
+            }
+        }
+
+        let expected_types = match &expected {
+            QueryExpect::Results { types, .. } => &types[..],
+            QueryExpect::Error(_) => &[],
+        };
+        trim_trailing_spaces(&mut rows, &types, expected_types);
+
+        let (sort_mode, hashed, columns) = match expected {
+            QueryExpect::Results {
+                sort_mode,
+                hashed,
+                columns,
+                ..
+            } => (sort_mode, hashed, columns),
+            QueryExpect::Error(_) => (None, false, None),
+        };
+        let sort_mode = sort_mode.or(self.sort_mode);
+
+        let num_columns = match &columns {
+            Some(columns) => {
+                match project_columns(rows, columns) {
+                    Ok(projected) => rows = projected,
+                    Err(e) => {
+                        return RecordOutput::Query {
+                            error: Some(Arc::new(e)),
+                            types,
+                            rows: vec![],
+                        }
+                    }
+                }
+                columns.len()
+            }
+            None => types.len(),
+        };
+
+        let mut value_sort = false;
+        match sort_mode {
+            None | Some(SortMode::NoSort) => {}
+            Some(SortMode::RowSort) => {
+                sort_rows(&mut rows, self.rowsort_nulls_last);
+            }
+            Some(SortMode::ValueSort) => {
+                rows = rows.into_iter().flatten().map(|s| vec![s]).collect();
+                rows.sort_unstable();
+                value_sort = true;
+            }
+        };
+
+        let num_values = if value_sort {
+            rows.len()
+        } else {
+            rows.len() * num_columns
+        };
+
+        let threshold_exceeded = self.hash_threshold > 0 && num_values > self.hash_threshold;
+        if (hashed || threshold_exceeded) && self.hash_normalize {
+            // Hash the values in the same form as they are compared, sorting them again
+            // so that the order doesn't depend on the raw form either.
+            for value in rows.iter_mut().flatten() {
+                *value = (self.normalizer)(value);
+            }
+            match sort_mode {
+                Some(SortMode::RowSort) => sort_rows(&mut rows, self.rowsort_nulls_last),
+                Some(SortMode::ValueSort) => rows.sort_unstable(),
+                None | Some(SortMode::NoSort) => {}
+            }
+        }
+
+        if hashed {
+            rows = vec![vec![hash_rows(self.hash_algo, &rows)]];
+        } else if threshold_exceeded {
+            rows = vec![vec![format!(
+                "{} values hashing to {}",
+                rows.len() * rows[0].len(),
+                hash_rows(self.hash_algo, &rows)
+            )]];
+        }
+
+        RecordOutput::Query {
+            error: None,
+            types,
+            rows,
+        }
+    }
+
     /// Run a single record.
     pub async fn run_async(
         &mut self,
         record: Record<D::ColumnType>,
+    ) -> Result<RecordOutput<D::ColumnType>, TestError> {
+        let (repeat, connection) = match &record {
+            Record::Statement { connection, .. } | Record::Query { connection, .. } => {
+                (self.repeat.take(), Some(connection.clone()))
+            }
+            _ => (None, None),
+        };
+        // Every run is validated, so that it fails on the first run with unexpected output.
+        for _ in 1..repeat.unwrap_or(1) {
+            self.run_async_with_retry(record.clone()).await?;
+        }
+        let output = self.run_async_with_retry(record).await?;
+        // Skipped records are not counted.
+        if let (Some(connection), false) = (connection, matches!(output, RecordOutput::Nothing)) {
+            *self.executed.entry(connection).or_default() += 1;
+        }
+        Ok(output)
+    }
+
+    /// Run a single record, retrying it if configured.
+    async fn run_async_with_retry(
+        &mut self,
+        record: Record<D::ColumnType>,
     ) -> Result<RecordOutput<D::ColumnType>, TestError> {
         let retry = match &record {
             Record::Statement { retry, .. } => retry.clone(),
@@ -910,14 +2642,678 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         Err(last_error.unwrap())
     }
 
+    /// Applies the scrubbers to the expected results of a query. Hashes are left as is.
+    fn scrub_expected(&self, mut record: Record<D::ColumnType>) -> Record<D::ColumnType> {
+        if let Record::Query {
+            expected:
+                QueryExpect::Results {
+                    results,
+                    hashed: false,
+                    ..
+                },
+            ..
+        } = &mut record
+        {
+            for line in results.iter_mut() {
+                if let Cow::Owned(scrubbed) = scrub(&self.scrubbers, line) {
+                    *line = scrubbed;
+                }
+            }
+        }
+        record
+    }
+
+    /// Rolls back the changes made by the previous subtest if any, and creates a savepoint for the
+    /// subtest beginning at `loc`.
+    async fn begin_subtest_scope(&mut self, loc: Location) -> Result<(), TestError> {
+        self.end_subtest_scope().await?;
+
+        let conn = self
+            .conn
+            .get(Connection::Default)
+            .await
+            .map_err(|e| TestErrorKind::SavepointFail { err: Arc::new(e) }.at(loc.clone()))?;
+        if !conn.supports_savepoints() {
+            return Err(TestErrorKind::SavepointUnsupported {
+                engine: conn.engine_name().to_owned(),
+            }
+            .at(loc));
+        }
+        conn.savepoint(SUBTEST_SAVEPOINT)
+            .await
+            .map_err(|e| TestErrorKind::SavepointFail { err: Arc::new(e) }.at(loc.clone()))?;
+        self.subtest_savepoint = Some(loc);
+        Ok(())
+    }
+
+    /// Creates a savepoint for the record at `loc` in the current subtest.
+    async fn begin_record_savepoint(&mut self, loc: &Location) -> Result<(), TestError> {
+        let err = |e| TestErrorKind::SavepointFail { err: Arc::new(e) }.at(loc.clone());
+        let conn = self.conn.get(Connection::Default).await.map_err(err)?;
+        conn.savepoint(RECORD_SAVEPOINT).await.map_err(err)
+    }
+
+    /// Releases the savepoint of the record at `loc`, rolling back to it first if the record
+    /// failed, so that the subtest can go on after an expected error.
+    async fn end_record_savepoint(
+        &mut self,
+        loc: &Location,
+        failed: bool,
+    ) -> Result<(), TestError> {
+        let err = |e| TestErrorKind::SavepointFail { err: Arc::new(e) }.at(loc.clone());
+        let conn = self.conn.get(Connection::Default).await.map_err(err)?;
+        if failed {
+            conn.rollback_to(RECORD_SAVEPOINT).await.map_err(err)?;
+        }
+        conn.release(RECORD_SAVEPOINT).await.map_err(err)
+    }
+
+    /// Rolls back the changes made by the current subtest under `control scope subtest`, and
+    /// releases its savepoint, if any.
+    ///
+    /// This is done by [`Runner::run_multi_async`] and the methods calling it, even if a record
+    /// fails. Call it after running the records of a script if you are calling
+    /// [`Runner::run_async`] on each record instead, so that the connection, if reused, is not
+    /// left in the transaction of the subtest.
+    pub async fn end_scope(&mut self) -> Result<(), TestError> {
+        self.end_subtest_scope().await
+    }
+
+    /// Rolls back the changes made by the current subtest and releases its savepoint, if any.
+    async fn end_subtest_scope(&mut self) -> Result<(), TestError> {
+        let Some(loc) = self.subtest_savepoint.take() else {
+            return Ok(());
+        };
+        let err = |e| TestErrorKind::SavepointFail { err: Arc::new(e) }.at(loc.clone());
+
+        let conn = self.conn.get(Connection::Default).await.map_err(err)?;
+        conn.rollback_to(SUBTEST_SAVEPOINT).await.map_err(err)?;
+        conn.release(SUBTEST_SAVEPOINT).await.map_err(err)?;
+        Ok(())
+    }
+
+    /// Returns whether the actual error matches the expected one under the current error mode.
+    fn is_error_match(&self, expected: &ExpectedError, err: &AnyError) -> bool {
+        let sqlstate = Self::error_sqlstate(err);
+        expected.is_match_error(self.error_mode, err.as_ref(), sqlstate.as_deref())
+    }
+
+    /// Returns the SQLSTATE of the error returned by the database, if any.
+    fn error_sqlstate(err: &AnyError) -> Option<String> {
+        let original = match err.downcast_ref::<TransformedError>() {
+            Some(transformed) => &transformed.original,
+            None => err,
+        };
+        original
+            .downcast_ref::<D::Error>()
+            .and_then(D::error_sqlstate)
+    }
+
+    /// Updates the record with its output like [`update_record_with_output`], but matches and
+    /// rewrites the expected errors under the current error mode, set by `control error_mode`,
+    /// with the SQLSTATE of the errors. Used by `--override`.
+    pub fn update_record_with_error_mode(
+        &self,
+        record: &Record<D::ColumnType>,
+        record_output: &RecordOutput<D::ColumnType>,
+        col_separator: &str,
+        validator: Validator,
+        normalizer: Normalizer,
+        column_type_validator: ColumnTypeValidator<D::ColumnType>,
+    ) -> Option<Record<D::ColumnType>> {
+        update_record(
+            record,
+            record_output,
+            col_separator,
+            validator,
+            normalizer,
+            column_type_validator,
+            self.error_mode,
+            &Self::error_sqlstate,
+        )
+    }
+
+    /// Describes the expected error in mismatch errors, noting the error mode if it's not the
+    /// default one.
+    fn expected_error_string(&self, expected: &ExpectedError) -> String {
+        match self.error_mode {
+            ErrorMode::Message => expected.to_string(),
+            mode => format!("{expected} (error_mode {mode})"),
+        }
+    }
+
     /// Run a single record without retry.
     async fn run_async_no_retry(
         &mut self,
         record: Record<D::ColumnType>,
     ) -> Result<RecordOutput<D::ColumnType>, TestError> {
+        let record = self.scrub_expected(record);
+
+        match &record {
+            Record::Subtest { loc, .. } if self.scope == Scope::Subtest => {
+                self.begin_subtest_scope(loc.clone()).await?;
+            }
+            Record::Control(Control::Scope(Scope::File)) => self.end_subtest_scope().await?,
+            Record::Control(Control::Await {
+                connection,
+                record,
+                loc,
+                ..
+            }) => {
+                let executed = self.executed.get(connection).copied().unwrap_or_default();
+                if executed < *record {
+                    return Err(TestErrorKind::AwaitNotReached {
+                        connection: connection.name().to_string(),
+                        expected: *record,
+                        actual: executed,
+                    }
+                    .at(loc.clone()));
+                }
+            }
+            Record::Echo { loc, text } => {
+                let text = self.may_substitute(text.clone(), true).map_err(|err| {
+                    TestErrorKind::EchoFail {
+                        text: text.clone(),
+                        err,
+                    }
+                    .at(loc.clone())
+                })?;
+                (self.echo)(&text);
+            }
+            _ => {}
+        }
+
+        let guarded = self
+            .subtest_savepoint
+            .as_ref()
+            .and(expected_error_loc(&record));
+        if let Some(loc) = &guarded {
+            self.begin_record_savepoint(loc).await?;
+        }
+
+        self.elapsed = None;
+        self.warnings = None;
         let result = self.apply_record(record.clone()).await;
+        let warnings = self.warnings.take();
 
-        match (record, &result) {
+        if let Some(loc) = &guarded {
+            let failed = matches!(
+                result,
+                RecordOutput::Statement { error: Some(_), .. }
+                    | RecordOutput::Query { error: Some(_), .. }
+            );
+            self.end_record_savepoint(loc, failed).await?;
+        }
+
+        self.check_output(record, &result, self.elapsed, warnings)?;
+        Ok(result)
+    }
+
+    /// Takes the queries to run concurrently from the front of `records`, i.e., the consecutive
+    /// queries under `control parallel on`, with the newlines, comments and conditions between
+    /// them. Returns no queries if the next record is not such a query.
+    ///
+    /// A query with a named connection, `retry` or `control repeat` ends the run of queries, and
+    /// runs as usual. To run records one by one, e.g., to print progress, run the queries taken
+    /// with [`Runner::run_parallel_queries_async`] and the other records with
+    /// [`Runner::run_async`].
+    pub fn take_parallel_queries<I>(
+        &self,
+        records: &mut std::iter::Peekable<I>,
+    ) -> Vec<Record<D::ColumnType>>
+    where
+        I: Iterator<Item = Record<D::ColumnType>>,
+    {
+        let can_run_in_parallel = |record: &Record<D::ColumnType>| {
+            matches!(
+                record,
+                Record::Query {
+                    connection: Connection::Default,
+                    retry: None,
+                    ..
+                }
+            )
+        };
+        let mut queries = vec![];
+        if !self.parallel || self.repeat.is_some() {
+            return queries;
+        }
+        while let Some(record) = records.next_if(|record| match record {
+            Record::Newline | Record::Comment(_) | Record::Condition(_) => !queries.is_empty(),
+            record => can_run_in_parallel(record),
+        }) {
+            if let Record::Query { .. } = record {
+                queries.push(record);
+            }
+        }
+        queries
+    }
+
+    /// Run the queries taken by [`Runner::take_parallel_queries`] concurrently, each on a new
+    /// connection, with at most [`Runner::with_max_parallel_queries`] at a time. The results are
+    /// checked as they complete, and the first failure is returned.
+    ///
+    /// The queries must be free of side effects, and must not depend on each other, since their
+    /// order is not defined. As they don't run on the default connection, they don't see its
+    /// uncommitted changes, e.g., under `control scope subtest`, nor count for `control await`.
+    pub async fn run_parallel_queries_async(
+        &mut self,
+        queries: Vec<Record<D::ColumnType>>,
+    ) -> Result<(), TestError> {
+        if queries.is_empty() {
+            return Ok(());
+        }
+        let jobs = self.max_parallel_queries.min(queries.len());
+        let mut conns = Vec::with_capacity(jobs);
+        for _ in 0..jobs {
+            match self.conn.make().await {
+                Ok(conn) => conns.push(conn),
+                Err(e) => {
+                    let Record::Query { sql, loc, .. } = &queries[0] else {
+                        unreachable!("only queries run in parallel")
+                    };
+                    return Err(TestErrorKind::ConnectFail {
+                        sql: sql.clone(),
+                        err: Arc::new(e),
+                        kind: RecordKind::Query,
+                    }
+                    .at(loc.clone()));
+                }
+            }
+        }
+
+        // Skip and substitute the queries before running them.
+        let engine_name = conns.first().map(|conn| conn.engine_name().to_owned());
+        let engine_name = engine_name.filter(|_| self.implicit_engine_label);
+        let mut prepared = vec![];
+        for record in queries {
+            let record = self.scrub_expected(record);
+            let Record::Query {
+                conditions,
+                loc,
+                sql,
+                ..
+            } = &record
+            else {
+                unreachable!("only queries run in parallel")
+            };
+            if should_skip(
+                &self.labels,
+                engine_name.as_deref(),
+                conditions,
+                loc,
+                &mut self.skipped,
+            ) {
+                continue;
+            }
+            let sql = self
+                .may_substitute(sql.clone(), true)
+                .map(|sql| self.trailing_semicolon.apply(sql));
+            prepared.push((record, sql));
+        }
+
+        let conns = std::sync::Mutex::new(conns);
+        let runs = prepared
+            .into_iter()
+            .map(|(record, sql)| run_on_any(&conns, record, sql))
+            .collect_vec();
+        let mut outputs = stream::iter(runs).buffer_unordered(jobs);
+
+        while let Some(ParallelRun {
+            record,
+            output,
+            elapsed,
+            warnings,
+        }) = outputs.next().await
+        {
+            let output = match output {
+                Ok(ret) => {
+                    let Record::Query { expected, .. } = &record else {
+                        unreachable!("only queries run in parallel")
+                    };
+                    self.query_output(expected.clone(), ret)
+                }
+                Err(error) => error_output(&record, error),
+            };
+            self.check_output(record, &output, elapsed, warnings)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the records from `control concurrent on` at the front of `records` to the next
+    /// `control concurrent off` or the end of the file, whichever comes first, to be run with
+    /// [`Runner::run_concurrent_records_async`]. Returns no records if the next record is not
+    /// `control concurrent on`.
+    ///
+    /// [`Runner::run_async`] ignores `control concurrent`, and runs the records one by one.
+    pub fn take_concurrent_records<I>(
+        &self,
+        records: &mut std::iter::Peekable<I>,
+    ) -> Vec<Record<D::ColumnType>>
+    where
+        I: Iterator<Item = Record<D::ColumnType>>,
+    {
+        let mut taken = vec![];
+        if records
+            .next_if_eq(&Record::Control(Control::Concurrent(true)))
+            .is_none()
+        {
+            return taken;
+        }
+        while let Some(record) = records.next_if(|record| !matches!(record, Record::Halt { .. })) {
+            let end = record == Record::Control(Control::Concurrent(false));
+            taken.push(record);
+            if end {
+                break;
+            }
+        }
+        taken
+    }
+
+    /// Runs the records taken by [`Runner::take_concurrent_records`], running the statements and
+    /// queries on named connections in the background. Each connection runs its records one at a
+    /// time in the order of the file, but the records after them go on without waiting, e.g.,
+    /// while they are blocked by a lock. Use `control await` to wait for them. Their results are
+    /// checked as they complete, and the first failure is returned.
+    ///
+    /// Records with `retry` or `control repeat`, and `statement batch`, on a named connection
+    /// run in the foreground after the background records of the connection. All the background
+    /// records are waited for at the end, for at most 10 seconds per connection.
+    ///
+    /// The records run concurrently only if the futures returned by [`AsyncDB::run`] wait for the
+    /// database without blocking the thread. Otherwise, e.g., for a [`DB`], a background record
+    /// blocked by a lock blocks the whole runner.
+    pub async fn run_concurrent_records_async(
+        &mut self,
+        records: Vec<Record<D::ColumnType>>,
+    ) -> Result<(), TestError> {
+        let mut lanes = Lanes::new();
+        let mut done = vec![];
+        for record in records {
+            for run in done.drain(..) {
+                self.check_background(run)?;
+            }
+            match record {
+                Record::Control(Control::Concurrent(_)) => {}
+                Record::Control(Control::Await {
+                    connection,
+                    record,
+                    timeout,
+                    loc,
+                }) => {
+                    let timeout = timeout.unwrap_or(DEFAULT_AWAIT_TIMEOUT);
+                    self.await_background(&mut lanes, &mut done, &connection, record, timeout, loc)
+                        .await?;
+                }
+                Record::Statement {
+                    connection: Connection::Named(_),
+                    retry: None,
+                    ref expected,
+                    ..
+                } if !matches!(expected, StatementExpect::Batch) && self.repeat.is_none() => {
+                    self.run_in_background(&mut lanes, record).await?;
+                }
+                Record::Query {
+                    connection: Connection::Named(_),
+                    retry: None,
+                    ..
+                } if self.repeat.is_none() => {
+                    self.run_in_background(&mut lanes, record).await?;
+                }
+                record => {
+                    if let Record::Statement { connection, .. } | Record::Query { connection, .. } =
+                        &record
+                    {
+                        self.join_background(&mut lanes, &mut done, connection)
+                            .await?;
+                    }
+                    lanes.drive(self.run_async(record), &mut done).await?;
+                }
+            }
+        }
+
+        let connections = lanes
+            .lanes
+            .keys()
+            .cloned()
+            .sorted_by_key(|c| c.name().to_owned());
+        for connection in connections.collect_vec() {
+            self.join_background(&mut lanes, &mut done, &connection)
+                .await?;
+        }
+        for run in done {
+            self.check_background(run)?;
+        }
+        Ok(())
+    }
+
+    /// Substitutes the SQL of the statement or query on a named connection, and runs it in the
+    /// background, unless it's skipped.
+    async fn run_in_background(
+        &mut self,
+        lanes: &mut Lanes<'_, D>,
+        record: Record<D::ColumnType>,
+    ) -> Result<(), TestError> {
+        let record = self.scrub_expected(record);
+        let (Record::Statement {
+            connection,
+            conditions,
+            loc,
+            sql,
+            ..
+        }
+        | Record::Query {
+            connection,
+            conditions,
+            loc,
+            sql,
+            ..
+        }) = &record
+        else {
+            unreachable!("only statements and queries run in the background")
+        };
+        if !lanes.lanes.contains_key(connection) {
+            match self.conn.take(connection.clone()).await {
+                Ok(conn) => lanes.add(connection.clone(), conn, loc.clone()),
+                Err(e) => {
+                    let output = error_output(&record, Arc::new(ConnectError(Arc::new(e))));
+                    return self.check_output(record, &output, None, None);
+                }
+            }
+        }
+
+        let engine_name = &lanes.lanes[connection].engine_name;
+        let engine_name = self.implicit_engine_label.then_some(engine_name.as_str());
+        if should_skip(
+            &self.labels,
+            engine_name,
+            conditions,
+            loc,
+            &mut self.skipped,
+        ) {
+            return Ok(());
+        }
+        let sql = self
+            .may_substitute(sql.clone(), true)
+            .map(|sql| self.trailing_semicolon.apply(sql));
+        lanes.push(connection.clone(), record, sql);
+        Ok(())
+    }
+
+    /// Checks the output of a record completed in the background, and counts it for
+    /// `control await`.
+    fn check_background(&mut self, run: ParallelRun<D>) -> Result<(), TestError> {
+        let output = match run.output {
+            Ok(ret) => match &run.record {
+                Record::Query { expected, .. } => self.query_output(expected.clone(), ret),
+                _ => self.statement_output(ret),
+            },
+            Err(error) => error_output(&run.record, error),
+        };
+        let (Record::Statement { connection, .. } | Record::Query { connection, .. }) = &run.record
+        else {
+            unreachable!("only statements and queries run in the background")
+        };
+        let connection = connection.clone();
+        self.check_output(run.record, &output, run.elapsed, run.warnings)?;
+        *self.executed.entry(connection).or_default() += 1;
+        Ok(())
+    }
+
+    /// Waits until the connection has run at least `record` statements or queries, checking the
+    /// records completed in the background meanwhile.
+    async fn await_background(
+        &mut self,
+        lanes: &mut Lanes<'_, D>,
+        done: &mut Vec<ParallelRun<D>>,
+        connection: &Connection,
+        record: usize,
+        timeout: Duration,
+        loc: Location,
+    ) -> Result<(), TestError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            for run in done.drain(..) {
+                self.check_background(run)?;
+            }
+            let executed = self.executed.get(connection).copied().unwrap_or_default();
+            if executed >= record {
+                return Ok(());
+            }
+            if lanes.pending(connection) == 0 {
+                return Err(TestErrorKind::AwaitNotReached {
+                    connection: connection.name().to_string(),
+                    expected: record,
+                    actual: executed,
+                }
+                .at(loc));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TestErrorKind::AwaitTimeout {
+                    connection: connection.name().to_string(),
+                    expected: record,
+                    actual: executed,
+                    timeout,
+                }
+                .at(loc));
+            }
+            let sleep = D::sleep(AWAIT_POLL_INTERVAL.min(deadline - now));
+            if let Either::Left((Some(run), _)) = future::select(lanes.running.next(), sleep).await
+            {
+                done.push(lanes.complete(run));
+            }
+        }
+    }
+
+    /// Waits for the background records of the connection, if any, and gives the connection
+    /// back to the runner.
+    async fn join_background(
+        &mut self,
+        lanes: &mut Lanes<'_, D>,
+        done: &mut Vec<ParallelRun<D>>,
+        connection: &Connection,
+    ) -> Result<(), TestError> {
+        let Some(loc) = lanes.lanes.get(connection).map(|lane| lane.loc.clone()) else {
+            return Ok(());
+        };
+        for run in done.drain(..) {
+            self.check_background(run)?;
+        }
+        let executed = self.executed.get(connection).copied().unwrap_or_default();
+        let record = executed + lanes.pending(connection);
+        self.await_background(lanes, done, connection, record, DEFAULT_AWAIT_TIMEOUT, loc)
+            .await?;
+        let lane = lanes.lanes.remove(connection).unwrap();
+        self.conn.put(
+            connection.clone(),
+            lane.conn.expect("connection is running"),
+        );
+        Ok(())
+    }
+
+    /// Checks the output of a record against its expectations, including `warnings` and
+    /// `maxtime` with the `warnings` emitted by its SQL and the time `elapsed` to run it.
+    fn check_output(
+        &self,
+        record: Record<D::ColumnType>,
+        result: &RecordOutput<D::ColumnType>,
+        elapsed: Option<Duration>,
+        warnings: Option<Vec<String>>,
+    ) -> Result<(), TestError> {
+        let executed = match &record {
+            Record::Statement {
+                sql,
+                loc,
+                warnings,
+                maxtime,
+                ..
+            } => Some((
+                sql.clone(),
+                loc.clone(),
+                RecordKind::Statement,
+                *warnings,
+                *maxtime,
+            )),
+            Record::Query {
+                sql,
+                loc,
+                warnings,
+                maxtime,
+                ..
+            } => Some((
+                sql.clone(),
+                loc.clone(),
+                RecordKind::Query,
+                *warnings,
+                *maxtime,
+            )),
+            _ => None,
+        };
+
+        if let (
+            Some((sql, loc, kind, ..)),
+            RecordOutput::Statement {
+                error: Some(err), ..
+            }
+            | RecordOutput::Query {
+                error: Some(err), ..
+            },
+        ) = (&executed, result)
+        {
+            if let Some(ConnectError(err)) = err.downcast_ref() {
+                return Err(TestErrorKind::ConnectFail {
+                    sql: sql.clone(),
+                    err: Arc::clone(err),
+                    kind: kind.clone(),
+                }
+                .at(loc.clone()));
+            }
+        }
+
+        if let (
+            Some(forbidden),
+            Some((sql, loc, kind, ..)),
+            RecordOutput::Statement {
+                error: Some(err), ..
+            }
+            | RecordOutput::Query {
+                error: Some(err), ..
+            },
+        ) = (&self.forbidden_error, &executed, result)
+        {
+            if forbidden.is_match(&err.to_string()) {
+                return Err(TestErrorKind::ForbiddenError {
+                    sql: sql.clone(),
+                    err: Arc::clone(err),
+                    pattern: forbidden.to_string(),
+                    kind: kind.clone(),
+                }
+                .at(loc.clone()));
+            }
+        }
+
+        match (record, result) {
             (_, RecordOutput::Nothing) => {}
             // Tolerate the mismatched return type...
             (
@@ -965,7 +3361,8 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         expected: results.join("\n"),
                         actual: "".to_string(),
                     }
-                    .at(loc))
+                    .at(loc)
+                    .with_max_diff_lines(self.max_diff_lines))
                 }
                 QueryExpect::Results { .. } => {}
             },
@@ -973,9 +3370,12 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                 Record::Statement {
                     loc,
                     connection: _,
+                    inline_connection: _,
                     conditions: _,
                     sql,
                     expected,
+                    maxtime: _,
+                    warnings: _,
                     retry: _,
                 },
                 RecordOutput::Statement { count, error },
@@ -997,19 +3397,29 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         .at(loc));
                     }
                 }
-                (None, StatementExpect::Ok) => {}
+                (None, StatementExpect::Ok | StatementExpect::Batch) => {}
                 (Some(e), StatementExpect::Error(expected_error)) => {
-                    if !expected_error.is_match(&e.to_string()) {
+                    if self.require_error_message && e.to_string().trim().is_empty() {
+                        return Err(TestErrorKind::EmptyErrorMessage {
+                            sql,
+                            kind: RecordKind::Statement,
+                        }
+                        .at(loc));
+                    }
+                    if !self.is_error_match(&expected_error, e) {
                         return Err(TestErrorKind::ErrorMismatch {
                             sql,
                             err: Arc::clone(e),
-                            expected_err: expected_error.to_string(),
+                            expected_err: self.expected_error_string(&expected_error),
                             kind: RecordKind::Statement,
                         }
                         .at(loc));
                     }
                 }
-                (Some(e), StatementExpect::Count(_) | StatementExpect::Ok) => {
+                (
+                    Some(e),
+                    StatementExpect::Count(_) | StatementExpect::Ok | StatementExpect::Batch,
+                ) => {
                     return Err(TestErrorKind::Fail {
                         sql,
                         err: Arc::clone(e),
@@ -1023,8 +3433,11 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     loc,
                     conditions: _,
                     connection: _,
+                    inline_connection: _,
                     sql,
                     expected,
+                    maxtime: _,
+                    warnings: _,
                     retry: _,
                 },
                 RecordOutput::Query { types, rows, error },
@@ -1038,11 +3451,18 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         .at(loc));
                     }
                     (Some(e), QueryExpect::Error(expected_error)) => {
-                        if !expected_error.is_match(&e.to_string()) {
+                        if self.require_error_message && e.to_string().trim().is_empty() {
+                            return Err(TestErrorKind::EmptyErrorMessage {
+                                sql,
+                                kind: RecordKind::Query,
+                            }
+                            .at(loc));
+                        }
+                        if !self.is_error_match(&expected_error, e) {
                             return Err(TestErrorKind::ErrorMismatch {
                                 sql,
                                 err: Arc::clone(e),
-                                expected_err: expected_error.to_string(),
+                                expected_err: self.expected_error_string(&expected_error),
                                 kind: RecordKind::Query,
                             }
                             .at(loc));
@@ -1061,6 +3481,8 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         QueryExpect::Results {
                             types: expected_types,
                             results: expected_results,
+                            sigfig,
+                            nocase,
                             ..
                         },
                     ) => {
@@ -1074,16 +3496,35 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         }
 
                         let actual_results = match self.result_mode {
-                            Some(ResultMode::ValueWise) => rows
-                                .iter()
-                                .flat_map(|strs| strs.iter())
-                                .map(|str| vec![str.to_string()])
-                                .collect_vec(),
+                            Some(ResultMode::ValueWise) => Cow::Owned(
+                                rows.iter()
+                                    .flat_map(|strs| strs.iter())
+                                    .map(|str| vec![str.to_string()])
+                                    .collect_vec(),
+                            ),
                             // default to rowwise
-                            _ => rows.clone(),
+                            _ => Cow::Borrowed(rows),
                         };
-
-                        if !(self.validator)(self.normalizer, &actual_results, &expected_results) {
+                        let (actual_results, expected_results) =
+                            if nocase.unwrap_or(self.case_insensitive) {
+                                let (actual, expected) =
+                                    lowercase_results(&actual_results, &expected_results);
+                                (Cow::Owned(actual), expected)
+                            } else {
+                                (actual_results, expected_results)
+                            };
+
+                        let (actual_results, expected_results) = match sigfig {
+                            Some(sigfig) => {
+                                let (actual, expected) =
+                                    round_results(&actual_results, &expected_results, sigfig);
+                                (Cow::Owned(actual), Cow::Owned(expected))
+                            }
+                            None => (actual_results, Cow::Borrowed(&*expected_results)),
+                        };
+                        let matched =
+                            (self.validator)(self.normalizer, &actual_results, &expected_results);
+                        if !matched {
                             let output_rows =
                                 rows.iter().map(|strs| strs.iter().join(" ")).collect_vec();
                             return Err(TestErrorKind::QueryResultMismatch {
@@ -1091,7 +3532,8 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                                 expected: expected_results.join("\n"),
                                 actual: output_rows.join("\n"),
                             }
-                            .at(loc));
+                            .at(loc)
+                            .with_max_diff_lines(self.max_diff_lines));
                         }
                     }
                 };
@@ -1135,7 +3577,31 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
             _ => unreachable!(),
         }
 
-        Ok(result)
+        if let (Some((sql, loc, kind, Some(expected), _)), Some(actual)) = (&executed, warnings) {
+            if !expected.matches(actual.len()) {
+                return Err(TestErrorKind::WarningsMismatch {
+                    sql: sql.clone(),
+                    expected: *expected,
+                    actual,
+                    kind: kind.clone(),
+                }
+                .at(loc.clone()));
+            }
+        }
+
+        if let (Some((sql, loc, kind, _, Some(limit))), Some(elapsed)) = (executed, elapsed) {
+            if elapsed > limit {
+                return Err(TestErrorKind::TooSlow {
+                    sql,
+                    elapsed,
+                    limit,
+                    kind,
+                }
+                .at(loc));
+            }
+        }
+
+        Ok(())
     }
 
     /// Run a single record.
@@ -1157,11 +3623,35 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         &mut self,
         records: impl IntoIterator<Item = Record<D::ColumnType>>,
     ) -> Result<(), TestError> {
-        for record in records.into_iter() {
-            if let Record::Halt { .. } = record {
-                break;
+        self.reset_hash_threshold();
+        self.reset_executed();
+        let result = self.run_records_async(records).await;
+        // End the subtest even if a record failed, so that a reused connection is not left in
+        // its transaction.
+        let end = self.end_subtest_scope().await;
+        result.and(end)
+    }
+
+    async fn run_records_async(
+        &mut self,
+        records: impl IntoIterator<Item = Record<D::ColumnType>>,
+    ) -> Result<(), TestError> {
+        let mut records = records.into_iter().peekable();
+        loop {
+            let queries = self.take_parallel_queries(&mut records);
+            if !queries.is_empty() {
+                self.run_parallel_queries_async(queries).await?;
+                continue;
             }
-            self.run_async(record).await?;
+            let concurrent = self.take_concurrent_records(&mut records);
+            if !concurrent.is_empty() {
+                self.run_concurrent_records_async(concurrent).await?;
+                continue;
+            }
+            match records.next() {
+                None | Some(Record::Halt { .. }) => break,
+                Some(record) => self.run_async(record).await?,
+            };
         }
         Ok(())
     }
@@ -1178,6 +3668,164 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         block_on(self.run_multi_async(records))
     }
 
+    /// Writes the records as they would be run, for debugging the effects of substitution,
+    /// conditions and sticky `control` records, without running any SQL:
+    /// - the SQL, commands and `echo` texts are substituted,
+    /// - the records skipped by conditions are commented out, noting the condition,
+    /// - queries have the effective sort mode, and the effective hash threshold as a comment,
+    /// - included files are delimited by comments.
+    ///
+    /// No connection is made, so the engine name is not a label for the conditions here. Add it
+    /// with [`Runner::add_label`] if needed. The `control` records are applied to the runner, as
+    /// if the records were run.
+    pub fn dump_plan(
+        &mut self,
+        records: impl IntoIterator<Item = Record<D::ColumnType>>,
+        w: &mut impl std::io::Write,
+    ) -> std::io::Result<()> {
+        self.reset_hash_threshold();
+        for mut record in records {
+            match &mut record {
+                Record::Halt { .. } => {
+                    writeln!(w, "{record}")?;
+                    break;
+                }
+                // The conditions are evaluated with the records they apply to.
+                Record::Condition(_) => continue,
+                Record::Injected(Injected::BeginInclude(file)) => {
+                    writeln!(w, "# begin include {file}")?;
+                    continue;
+                }
+                Record::Injected(Injected::EndInclude(file)) => {
+                    writeln!(w, "# end include {file}")?;
+                    continue;
+                }
+                Record::Injected(Injected::PushVariables(variables)) => {
+                    let variables = variables.iter().map(|(k, v)| format!("{k}={v}")).join(", ");
+                    writeln!(w, "# bind {variables}")?;
+                    block_on(self.apply_record(record));
+                    continue;
+                }
+                Record::Injected(Injected::PopVariables) => {
+                    block_on(self.apply_record(record));
+                    continue;
+                }
+                Record::Control(_) | Record::HashThreshold { .. } => {
+                    writeln!(w, "{record}")?;
+                    block_on(self.apply_record(record));
+                    continue;
+                }
+                Record::Statement { .. } => self.repeat = None,
+                Record::Query { expected, .. } => {
+                    self.repeat = None;
+                    if let QueryExpect::Results { sort_mode, .. } = expected {
+                        *sort_mode = sort_mode.or(self.sort_mode);
+                    }
+                }
+                _ => {}
+            }
+
+            let labels = || self.labels.iter().map(|l| l.as_str());
+            if let Some(condition) = record.conditions().iter().find(|c| c.should_skip(labels())) {
+                let condition = Record::<D::ColumnType>::Condition(condition.clone());
+                writeln!(w, "# skipped by `{condition}`:")?;
+                for line in record.to_string().lines() {
+                    writeln!(w, "# {line}")?;
+                }
+                writeln!(w)?;
+                continue;
+            }
+
+            let mut failed = None;
+            let mut substitute = |s: &mut String, subst_env_vars: bool| match self
+                .may_substitute(s.clone(), subst_env_vars)
+            {
+                Ok(substituted) => *s = substituted,
+                Err(e) => failed = Some(e),
+            };
+            match &mut record {
+                Record::Statement { sql, .. } => substitute(sql, true),
+                Record::Query { sql, .. } => {
+                    substitute(sql, true);
+                    if self.hash_threshold > 0 {
+                        writeln!(w, "# hash-threshold {}", self.hash_threshold)?;
+                    }
+                }
+                Record::System { command, .. } => substitute(command, false),
+                Record::Echo { text, .. } => substitute(text, true),
+                _ => {}
+            }
+            if let Some(e) = failed {
+                writeln!(w, "# substitution failed: {e}")?;
+            }
+            if let Record::Statement { sql, .. } | Record::Query { sql, .. } = &mut record {
+                *sql = self.trailing_semicolon.apply(std::mem::take(sql));
+            }
+            writeln!(w, "{record}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the SQL of the statements and queries as a plain SQL script, e.g., to reproduce a
+    /// test in `psql` or `mysql`:
+    /// - the SQL is substituted, and terminated by `;` if not yet,
+    /// - the records skipped by conditions are left out,
+    /// - the statement or query after `control repeat <n>` is written `n` times,
+    /// - included files are inlined, delimited by `--` comments.
+    ///
+    /// Like [`Runner::dump_plan`], no connection is made, so the engine name is not a label for
+    /// the conditions here, and the `control` records are applied to the runner.
+    pub fn emit_sql(
+        &mut self,
+        records: impl IntoIterator<Item = Record<D::ColumnType>>,
+        w: &mut impl std::io::Write,
+    ) -> std::io::Result<()> {
+        for record in records {
+            match &record {
+                Record::Halt { .. } => break,
+                Record::Injected(Injected::BeginInclude(file)) => {
+                    writeln!(w, "-- begin include {file}")?;
+                    continue;
+                }
+                Record::Injected(Injected::EndInclude(file)) => {
+                    writeln!(w, "-- end include {file}")?;
+                    continue;
+                }
+                Record::Injected(_) | Record::Control(_) => {
+                    block_on(self.apply_record(record));
+                    continue;
+                }
+                Record::Statement { .. } | Record::Query { .. } => {}
+                _ => continue,
+            }
+
+            let repeat = self.repeat.take().unwrap_or(1);
+            let labels = || self.labels.iter().map(|l| l.as_str());
+            if record.conditions().iter().any(|c| c.should_skip(labels())) {
+                continue;
+            }
+            let (Record::Statement { sql, .. } | Record::Query { sql, .. }) = record else {
+                unreachable!()
+            };
+            let sql = match self.may_substitute(sql.clone(), true) {
+                Ok(sql) => sql,
+                Err(e) => {
+                    writeln!(w, "-- substitution failed: {e}")?;
+                    sql
+                }
+            };
+            let sql = sql.trim_end();
+            for _ in 0..repeat {
+                if sql.ends_with(';') {
+                    writeln!(w, "{sql}")?;
+                } else {
+                    writeln!(w, "{sql};")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Run a sqllogictest script.
     pub async fn run_script_async(&mut self, script: &str) -> Result<(), TestError> {
         let records = parse(script).expect("failed to parse sqllogictest");
@@ -1194,12 +3842,91 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         self.run_multi_async(records).await
     }
 
-    /// Run a sqllogictest file.
+    /// Run a sqllogictest file. The file is skipped if the requirements in its `# requires:`
+    /// header are not met, see [`Runner::unmet_requirements`].
+    ///
+    /// The connections kept from previous runs are checked with [`AsyncDB::ping`] first, see
+    /// [`Runner::ping_connections`].
     pub async fn run_file_async(&mut self, filename: impl AsRef<Path>) -> Result<(), TestError> {
-        let records = parse_file(filename)?;
+        let ParsedFile { metadata, records } = parse_file_with_metadata(filename)?;
+        if !self.unmet_requirements(&metadata).is_empty() {
+            return Ok(());
+        }
+        self.ping_connections().await;
         self.run_multi_async(records).await
     }
 
+    /// Run a sqllogictest file like [`Runner::run_file_async`], and return the records skipped by
+    /// conditions `skipif` and `onlyif`, to help find out whether the conditions are set as
+    /// intended.
+    pub async fn run_file_async_with_skipped(
+        &mut self,
+        filename: impl AsRef<Path>,
+    ) -> Result<Vec<SkippedRecord>, TestError> {
+        let collecting = self.skipped.replace(Vec::new());
+        let result = self.run_file_async(filename).await;
+        let skipped = std::mem::replace(&mut self.skipped, collecting).unwrap_or_default();
+        result.map(|()| skipped)
+    }
+
+    /// Run the sqllogictest files in `dir` matching the glob `pattern`, e.g., `**/*.slt`, one by
+    /// one, and report the result of each file instead of stopping at the first failure.
+    ///
+    /// Each file starts with the state of the runner when this is called, and with new connections
+    /// made by the [`MakeConnection`] of the runner. Unlike [`Runner::run_parallel_async`], no
+    /// database is created for each file, so the files share the database connected to, and
+    /// should clean up the tables they create. Make a temporary database in the `MakeConnection`
+    /// if that's not desired.
+    ///
+    /// Files with unmet requirements in their `# requires:` header are skipped, see
+    /// [`Runner::unmet_requirements`]. The records skipped by conditions are collected in the
+    /// reports, like [`Runner::run_file_async_with_skipped`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is invalid, or if a matched path can't be read.
+    pub async fn run_dir_async(&mut self, dir: impl AsRef<Path>, pattern: &str) -> DirReport {
+        let pattern = dir.as_ref().join(pattern);
+        let files = glob::glob(&pattern.to_string_lossy()).expect("failed to read glob pattern");
+        let state = self.snapshot_state();
+        let mut report = DirReport::default();
+
+        for file in files {
+            let path = file.expect("failed to read path");
+            self.restore_state(state.clone());
+            self.conn.close_all();
+
+            let start = Instant::now();
+            let collecting = self.skipped.replace(Vec::new());
+            let status = match parse_file_with_metadata(&path) {
+                Ok(ParsedFile { metadata, records }) => {
+                    let unmet = self.unmet_requirements(&metadata);
+                    if !unmet.is_empty() {
+                        FileStatus::Skipped(unmet.into_iter().cloned().collect())
+                    } else {
+                        match self.run_multi_async(records).await {
+                            Ok(()) => FileStatus::Passed,
+                            Err(e) => FileStatus::Failed(e),
+                        }
+                    }
+                }
+                Err(e) => FileStatus::Failed(e.into()),
+            };
+            let skipped_records =
+                std::mem::replace(&mut self.skipped, collecting).unwrap_or_default();
+
+            report.files.push(FileReport {
+                path,
+                status,
+                skipped_records,
+                duration: start.elapsed(),
+            });
+        }
+
+        self.restore_state(state);
+        report
+    }
+
     /// Run a sqllogictest script.
     pub fn run_script(&mut self, script: &str) -> Result<(), TestError> {
         block_on(self.run_script_async(script))
@@ -1219,8 +3946,16 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
         block_on(self.run_file_async(filename))
     }
 
+    /// sync version of `run_dir_async`
+    pub fn run_dir(&mut self, dir: impl AsRef<Path>, pattern: &str) -> DirReport {
+        block_on(self.run_dir_async(dir, pattern))
+    }
+
     /// accept the tasks, spawn jobs task to run slt test. the tasks are (AsyncDB, slt filename)
     /// pairs.
+    ///
+    /// The records skipped by conditions in all the files are collected by this runner, in the
+    /// order the files complete, if [`Runner::with_skip_diagnostics`] is enabled.
     // TODO: This is not a good interface, as the `make_conn` passed to `new` is unused but we
     // accept a new `conn_builder` here. May change `MakeConnection` to support specifying the
     // database name in the future.
@@ -1250,31 +3985,65 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                 .expect("create db failed");
             let target = hosts[idx % hosts.len()].clone();
 
+            let mut conn =
+                Connections::new(move || conn_builder(target.clone(), db_name.clone()).map(Ok));
+            conn.set_retry(self.conn.retry().cloned());
+
             let mut tester = Runner {
-                conn: Connections::new(move || {
-                    conn_builder(target.clone(), db_name.clone()).map(Ok)
-                }),
+                conn,
                 validator: self.validator,
                 normalizer: self.normalizer,
+                scrubbers: self.scrubbers.clone(),
+                error_transformer: self.error_transformer,
+                trailing_semicolon: self.trailing_semicolon,
                 column_type_validator: self.column_type_validator,
                 substitution: self.substitution.clone(),
                 sort_mode: self.sort_mode,
                 result_mode: self.result_mode,
+                error_mode: self.error_mode,
+                forbidden_error: self.forbidden_error.clone(),
                 hash_threshold: self.hash_threshold,
+                default_hash_threshold: self.default_hash_threshold,
+                hash_normalize: self.hash_normalize,
+                hash_algo: self.hash_algo,
+                rowsort_nulls_last: self.rowsort_nulls_last,
+                parallel: self.parallel,
+                max_parallel_queries: self.max_parallel_queries,
+                case_insensitive: self.case_insensitive,
+                scope: self.scope,
+                subtest_savepoint: None,
+                elapsed: None,
+                warnings: None,
+                repeat: None,
+                executed: HashMap::new(),
                 labels: self.labels.clone(),
+                label_versions: self.label_versions.clone(),
+                known_labels: self.known_labels.clone(),
+                skipped: self.skipped.as_ref().map(|_| Vec::new()),
+                implicit_engine_label: self.implicit_engine_label,
+                echo: self.echo.clone(),
+                require_error_message: self.require_error_message,
+                max_diff_lines: self.max_diff_lines,
+                variables: vec![],
             };
 
             tasks.push(async move {
                 let filename = file.to_string_lossy().to_string();
-                tester.run_file_async(filename).await
+                let result = tester.run_file_async(filename).await;
+                (result, tester.skipped)
             })
         }
 
-        let tasks = stream::iter(tasks).buffer_unordered(jobs);
-        let errors: Vec<_> = tasks
-            .filter_map(|result| async { result.err() })
-            .collect()
-            .await;
+        let mut tasks = stream::iter(tasks).buffer_unordered(jobs);
+        let mut errors = vec![];
+        while let Some((result, skipped)) = tasks.next().await {
+            if let Err(e) = result {
+                errors.push(e);
+            }
+            if let (Some(all), Some(skipped)) = (&mut self.skipped, skipped) {
+                all.extend(skipped);
+            }
+        }
         if errors.is_empty() {
             Ok(())
         } else {
@@ -1309,7 +4078,7 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
     fn may_substitute(&self, input: String, subst_env_vars: bool) -> Result<String, AnyError> {
         if let Some(substitution) = &self.substitution {
             substitution
-                .substitute(&input, subst_env_vars)
+                .substitute(&input, subst_env_vars, &self.variables)
                 .map_err(|e| Arc::new(e) as AnyError)
         } else {
             Ok(input)
@@ -1432,6 +4201,9 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                     override_with_outfile(filename, outfilename, outfile)?;
                     stack.pop();
                 }
+                Record::Injected(Injected::PushVariables(_) | Injected::PopVariables) => {
+                    self.apply_record(record).await;
+                }
                 _ => {
                     if *halt {
                         writeln!(outfile, "{record}")?;
@@ -1445,16 +4217,18 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
                         );
                         continue;
                     }
+                    let record = self.scrub_expected(record);
                     let record_output = self.apply_record(record.clone()).await;
-                    let record = update_record_with_output(
-                        &record,
-                        &record_output,
-                        col_separator,
-                        validator,
-                        normalizer,
-                        column_type_validator,
-                    )
-                    .unwrap_or(record);
+                    let record = self
+                        .update_record_with_error_mode(
+                            &record,
+                            &record_output,
+                            col_separator,
+                            validator,
+                            normalizer,
+                            column_type_validator,
+                        )
+                        .unwrap_or(record);
                     writeln!(outfile, "{record}")?;
                 }
             }
@@ -1476,6 +4250,9 @@ impl<D: AsyncDB, M: MakeConnection<Conn = D>> Runner<D, M> {
 /// by a Database, returning `Some(new_record)`.
 ///
 /// If an update is not supported or not necessary, returns `None`
+///
+/// Expected errors are matched under the default [`ErrorMode`], without SQLSTATE. Use
+/// [`Runner::update_record_with_error_mode`] to follow `control error_mode` instead.
 pub fn update_record_with_output<T: ColumnType>(
     record: &Record<T>,
     record_output: &RecordOutput<T>,
@@ -1483,6 +4260,31 @@ pub fn update_record_with_output<T: ColumnType>(
     validator: Validator,
     normalizer: Normalizer,
     column_type_validator: ColumnTypeValidator<T>,
+) -> Option<Record<T>> {
+    update_record(
+        record,
+        record_output,
+        col_separator,
+        validator,
+        normalizer,
+        column_type_validator,
+        ErrorMode::default(),
+        &|_| None,
+    )
+}
+
+/// Updates the record like [`update_record_with_output`], matching and rewriting the expected
+/// errors under the error mode, with the SQLSTATE of the errors returned by `sqlstate`.
+#[allow(clippy::too_many_arguments)]
+fn update_record<T: ColumnType>(
+    record: &Record<T>,
+    record_output: &RecordOutput<T>,
+    col_separator: &str,
+    validator: Validator,
+    normalizer: Normalizer,
+    column_type_validator: ColumnTypeValidator<T>,
+    error_mode: ErrorMode,
+    sqlstate: &dyn Fn(&AnyError) -> Option<String>,
 ) -> Option<Record<T>> {
     match (record.clone(), record_output) {
         (_, RecordOutput::Nothing) => None,
@@ -1493,7 +4295,10 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 expected: mut expected @ (StatementExpect::Ok | StatementExpect::Count(_)),
+                maxtime,
+                warnings,
                 retry,
             },
             RecordOutput::Query {
@@ -1516,7 +4321,10 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 expected,
+                maxtime,
+                warnings,
                 retry,
             })
         }
@@ -1527,7 +4335,10 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 expected: _,
+                maxtime,
+                warnings,
                 retry,
             },
             RecordOutput::Statement { error: None, count },
@@ -1536,7 +4347,10 @@ pub fn update_record_with_output<T: ColumnType>(
             loc,
             conditions,
             connection,
+            inline_connection,
             expected: StatementExpect::Count(*count),
+            maxtime,
+            warnings,
             retry,
         }),
         // statement, statement
@@ -1545,8 +4359,11 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 sql,
                 expected,
+                maxtime,
+                warnings,
                 retry,
             },
             RecordOutput::Statement { count, error },
@@ -1557,15 +4374,26 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 expected: match expected {
                     StatementExpect::Count(_) => StatementExpect::Count(*count),
+                    StatementExpect::Batch => StatementExpect::Batch,
                     StatementExpect::Error(_) | StatementExpect::Ok => StatementExpect::Ok,
                 },
+                maxtime,
+                warnings,
                 retry,
             }),
+            // A failing batch can't be expressed by `statement batch`, and turning it into
+            // `statement error` would run the batch as a whole instead.
+            (Some(_), StatementExpect::Batch) => None,
             // Error match
             (Some(e), StatementExpect::Error(expected_error))
-                if expected_error.is_match(&e.to_string()) =>
+                if expected_error.is_match_error(
+                    error_mode,
+                    e.as_ref(),
+                    sqlstate(e).as_deref(),
+                ) =>
             {
                 None
             }
@@ -1573,17 +4401,24 @@ pub fn update_record_with_output<T: ColumnType>(
             (Some(e), r) => {
                 let reference = match &r {
                     StatementExpect::Error(e) => Some(e),
-                    StatementExpect::Count(_) | StatementExpect::Ok => None,
+                    StatementExpect::Count(_) | StatementExpect::Ok | StatementExpect::Batch => {
+                        None
+                    }
                 };
                 Some(Record::Statement {
                     sql,
-                    expected: StatementExpect::Error(ExpectedError::from_actual_error(
+                    expected: StatementExpect::Error(ExpectedError::from_actual_error_with_mode(
                         reference,
                         &e.to_string(),
+                        error_mode,
+                        sqlstate(e).as_deref(),
                     )),
                     loc,
                     conditions,
                     connection,
+                    inline_connection,
+                    maxtime,
+                    warnings,
                     retry,
                 })
             }
@@ -1594,15 +4429,22 @@ pub fn update_record_with_output<T: ColumnType>(
                 loc,
                 conditions,
                 connection,
+                inline_connection,
                 sql,
                 expected,
+                maxtime,
+                warnings,
                 retry,
             },
             RecordOutput::Query { types, rows, error },
         ) => match (error, expected) {
             // Error match
             (Some(e), QueryExpect::Error(expected_error))
-                if expected_error.is_match(&e.to_string()) =>
+                if expected_error.is_match_error(
+                    error_mode,
+                    e.as_ref(),
+                    sqlstate(e).as_deref(),
+                ) =>
             {
                 None
             }
@@ -1614,13 +4456,18 @@ pub fn update_record_with_output<T: ColumnType>(
                 };
                 Some(Record::Query {
                     sql,
-                    expected: QueryExpect::Error(ExpectedError::from_actual_error(
+                    expected: QueryExpect::Error(ExpectedError::from_actual_error_with_mode(
                         reference,
                         &e.to_string(),
+                        error_mode,
+                        sqlstate(e).as_deref(),
                     )),
                     loc,
                     conditions,
                     connection,
+                    inline_connection,
+                    maxtime,
+                    warnings,
                     retry,
                 })
             }
@@ -1629,8 +4476,28 @@ pub fn update_record_with_output<T: ColumnType>(
                     // If validation is successful, we respect the original file's expected results.
                     QueryExpect::Results {
                         results: expected_results,
+                        sigfig,
+                        nocase,
                         ..
-                    } if validator(normalizer, rows, expected_results) => expected_results.clone(),
+                    } if {
+                        let (rows, expected) = if *nocase == Some(true) {
+                            let (rows, expected) = lowercase_results(rows, expected_results);
+                            (Cow::Owned(rows), Cow::Owned(expected))
+                        } else {
+                            (Cow::Borrowed(rows), Cow::Borrowed(expected_results))
+                        };
+                        let (rows, expected) = match sigfig {
+                            Some(sigfig) => {
+                                let (rows, expected) = round_results(&rows, &expected, *sigfig);
+                                (Cow::Owned(rows), Cow::Owned(expected))
+                            }
+                            None => (rows, expected),
+                        };
+                        validator(normalizer, &rows, &expected)
+                    } =>
+                    {
+                        expected_results.clone()
+                    }
                     _ => rows.iter().map(|cols| cols.join(col_separator)).collect(),
                 };
                 let types = match &expected {
@@ -1646,11 +4513,17 @@ pub fn update_record_with_output<T: ColumnType>(
                     loc,
                     conditions,
                     connection,
+                    inline_connection,
                     expected: match expected {
                         QueryExpect::Results {
                             sort_mode,
                             label,
                             result_mode,
+                            hashed,
+                            nocase,
+                            sigfig,
+                            columns,
+                            result_set,
                             ..
                         } => QueryExpect::Results {
                             results,
@@ -1658,6 +4531,11 @@ pub fn update_record_with_output<T: ColumnType>(
                             sort_mode,
                             result_mode,
                             label,
+                            hashed,
+                            nocase,
+                            sigfig,
+                            columns,
+                            result_set,
                         },
                         QueryExpect::Error(_) => QueryExpect::Results {
                             results,
@@ -1665,8 +4543,15 @@ pub fn update_record_with_output<T: ColumnType>(
                             sort_mode: None,
                             result_mode: None,
                             label: None,
+                            hashed: false,
+                            nocase: None,
+                            sigfig: None,
+                            columns: None,
+                            result_set: None,
                         },
                     },
+                    maxtime,
+                    warnings,
                     retry,
                 })
             }
@@ -1726,466 +4611,2639 @@ mod tests {
                 vec![DefaultColumnType::Integer, DefaultColumnType::Any],
             ),
 
-            expected: Some(record),
-        }
-        .run()
+            expected: Some(record),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_replacement() {
+        TestCase {
+            // input should be ignored
+            input: "query III\n\
+                    select * from foo;\n\
+                    ----\n\
+                    1 2",
+
+            // Model a run that produced a 3,4 as output
+            record_output: query_output(
+                &[&["3", "4"]],
+                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
+            ),
+
+            expected: Some(
+                "query I?\n\
+                 select * from foo;\n\
+                 ----\n\
+                 3 4",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_replacement_no_input() {
+        TestCase {
+            // input has no query results
+            input: "query\n\
+                    select * from foo;\n\
+                    ----",
+
+            // Model a run that produced a 3,4 as output
+            record_output: query_output(
+                &[&["3", "4"]],
+                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
+            ),
+
+            expected: Some(
+                "query I?\n\
+                 select * from foo;\n\
+                 ----\n\
+                 3 4",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_replacement_no_output() {
+        TestCase {
+            // input has no query results
+            input: "query III\n\
+                    select * from foo;\n\
+                    ----",
+
+            // Model nothing was output
+            record_output: RecordOutput::Nothing,
+
+            // No update
+            expected: None,
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_replacement_error() {
+        TestCase {
+            // input has no query results
+            input: "query III\n\
+                    select * from foo;\n\
+                    ----",
+
+            // Model a run that produced a "MyAwesomeDB Error"
+            record_output: query_output_error("MyAwesomeDB Error"),
+
+            expected: Some(
+                "query error TestError: MyAwesomeDB Error\n\
+                 select * from foo;\n",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_replacement_error_multiline() {
+        TestCase {
+            // input has no query results
+            input: "query III\n\
+                    select * from foo;\n\
+                    ----",
+
+            // Model a run that produced a "MyAwesomeDB Error"
+            record_output: query_output_error("MyAwesomeDB Error\n\nCaused by:\n  Inner Error"),
+
+            expected: Some(
+                "query error
+select * from foo;
+----
+TestError: MyAwesomeDB Error
+
+Caused by:
+  Inner Error",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_query_output() {
+        TestCase {
+            // input has no query results
+            input: "statement ok\n\
+                    create table foo;",
+
+            // Model a run that produced a 3,4 as output
+            record_output: query_output(
+                &[&["3", "4"]],
+                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
+            ),
+
+            expected: Some(
+                "statement ok\n\
+                 create table foo;",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_statement_output() {
+        TestCase {
+            // input has no query results
+            input: "query III\n\
+                    select * from foo;\n\
+                    ----",
+
+            // Model a run that produced a statement output
+            record_output: statement_output(3),
+
+            expected: Some(
+                "statement count 3\n\
+                 select * from foo;",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_output() {
+        TestCase {
+            // statement that has no output
+            input: "statement ok\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced a statement output
+            record_output: statement_output(3),
+
+            // Note the the output does not include 3 (statement
+            // count) Rationale is if the record is statement count
+            // <n>, n will be updated to real count. If the record is
+            // statement ok (which means we don't care the number of
+            // affected rows), it won't be updated.
+            expected: Some(
+                "statement ok\n\
+                 insert into foo values(2);",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_to_ok() {
+        TestCase {
+            // statement expected error
+            input: "statement error\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced a statement output
+            record_output: statement_output(3),
+
+            expected: Some(
+                "statement ok\n\
+                 insert into foo values(2);",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_no_error() {
+        TestCase {
+            // statement expected error
+            input: "statement error\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced an error message
+            record_output: statement_output_error("foo"),
+
+            // Input didn't have an expected error, so output is not to expect the message, then no
+            // update
+            expected: None,
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_new_error() {
+        TestCase {
+            // statement expected error
+            input: "statement error bar\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced an error message
+            record_output: statement_output_error("foo"),
+
+            // expect the output includes foo
+            expected: Some(
+                "statement error TestError: foo\n\
+                 insert into foo values(2);",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_new_error_multiline() {
+        TestCase {
+            // statement expected error
+            input: "statement error bar\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced an error message
+            record_output: statement_output_error("foo\n\nCaused by:\n  Inner Error"),
+
+            // expect the output includes foo
+            expected: Some(
+                "statement error
+insert into foo values(2);
+----
+TestError: foo
+
+Caused by:
+  Inner Error",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_ok_to_error() {
+        TestCase {
+            // statement was ok
+            input: "statement ok\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced an error message
+            record_output: statement_output_error("foo"),
+
+            // expect the output includes foo
+            expected: Some(
+                "statement error TestError: foo\n\
+                 insert into foo values(2);",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_ok_to_error_multiline() {
+        TestCase {
+            // statement was ok
+            input: "statement ok\n\
+                    insert into foo values(2);",
+
+            // Model a run that produced an error message
+            record_output: statement_output_error("foo\n\nCaused by:\n  Inner Error"),
+
+            // expect the output includes foo
+            expected: Some(
+                "statement error
+insert into foo values(2);
+----
+TestError: foo
+
+Caused by:
+  Inner Error",
+            ),
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_statement_error_special_chars() {
+        TestCase {
+            // statement expected error
+            input: "statement error tbd\n\
+                    inser into foo values(2);",
+
+            // Model a run that produced an error message that contains regex special characters
+            record_output: statement_output_error("The operation (inser) is not supported. Did you mean [insert]?"),
+
+            // expect the output includes foo
+            expected: Some(
+                "statement error TestError: The operation \\(inser\\) is not supported\\. Did you mean \\[insert\\]\\?\n\
+                 inser into foo values(2);",
+            ),
+        }
+            .run()
+    }
+
+    #[test]
+    fn test_statement_keep_error_regex_when_matches() {
+        TestCase {
+            // statement expected error
+            input: "statement error TestError: The operation \\([a-z]+\\) is not supported.*\n\
+                    inser into foo values(2);",
+
+            // Model a run that produced an error message that contains regex special characters
+            record_output: statement_output_error(
+                "The operation (inser) is not supported. Did you mean [insert]?",
+            ),
+
+            // no update expected
+            expected: None,
+        }
+        .run()
+    }
+
+    #[test]
+    fn test_query_error_special_chars() {
+        TestCase {
+            // statement expected error
+            input: "query error tbd\n\
+                    selec *;",
+
+            // Model a run that produced an error message that contains regex special characters
+            record_output: query_output_error("The operation (selec) is not supported. Did you mean [select]?"),
+
+            // expect the output includes foo
+            expected: Some(
+                "query error TestError: The operation \\(selec\\) is not supported\\. Did you mean \\[select\\]\\?\n\
+                 selec *;",
+            ),
+        }
+            .run()
+    }
+
+    #[test]
+    fn test_query_error_special_chars_when_matches() {
+        TestCase {
+            // statement expected error
+            input: "query error TestError: The operation \\([a-z]+\\) is not supported.*\n\
+                    selec *;",
+
+            // Model a run that produced an error message that contains regex special characters
+            record_output: query_output_error(
+                "The operation (selec) is not supported. Did you mean [select]?",
+            ),
+
+            // no update expected
+            expected: None,
+        }
+        .run()
+    }
+
+    #[derive(Debug)]
+    struct TestCase<'a> {
+        input: &'a str,
+        record_output: RecordOutput<DefaultColumnType>,
+        expected: Option<&'a str>,
+    }
+
+    impl TestCase<'_> {
+        #[track_caller]
+        fn run(self) {
+            let Self {
+                input,
+                record_output,
+                expected,
+            } = self;
+            println!("TestCase");
+            println!("**input:\n{input}\n");
+            println!("**record_output:\n{record_output:#?}\n");
+            println!("**expected:\n{}\n", expected.unwrap_or(""));
+            let input = parse_to_record(input);
+            let expected = expected.map(parse_to_record);
+            let output = update_record_with_output(
+                &input,
+                &record_output,
+                " ",
+                default_validator,
+                default_normalizer,
+                strict_column_validator,
+            );
+
+            assert_eq!(
+                &output,
+                &expected,
+                "\n\noutput:\n\n{}\n\nexpected:\n\n{}",
+                output
+                    .as_ref()
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "None".into()),
+                expected
+                    .as_ref()
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "None".into()),
+            );
+        }
+    }
+
+    fn parse_to_record(s: &str) -> Record<DefaultColumnType> {
+        let mut records = parse(s).unwrap();
+        assert_eq!(records.len(), 1);
+        records.pop().unwrap()
+    }
+
+    /// Returns a RecordOutput that models the successful execution of a query
+    fn query_output(
+        rows: &[&[&str]],
+        types: Vec<DefaultColumnType>,
+    ) -> RecordOutput<DefaultColumnType> {
+        let rows = rows
+            .iter()
+            .map(|cols| cols.iter().map(|c| c.to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        RecordOutput::Query {
+            types,
+            rows,
+            error: None,
+        }
+    }
+
+    /// Returns a RecordOutput that models the error of a query
+    fn query_output_error(error_message: &str) -> RecordOutput<DefaultColumnType> {
+        RecordOutput::Query {
+            types: vec![],
+            rows: vec![],
+            error: Some(Arc::new(TestError(error_message.to_string()))),
+        }
+    }
+
+    fn statement_output(count: u64) -> RecordOutput<DefaultColumnType> {
+        RecordOutput::Statement { count, error: None }
+    }
+
+    /// RecordOutput that models a statement with error
+    fn statement_output_error(error_message: &str) -> RecordOutput<DefaultColumnType> {
+        RecordOutput::Statement {
+            count: 0,
+            error: Some(Arc::new(TestError(error_message.to_string()))),
+        }
+    }
+
+    #[test]
+    fn test_implicit_engine_label() {
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(TestError(sql.to_string()))
+            }
+
+            fn engine_name(&self) -> &str {
+                "fake"
+            }
+        }
+
+        let script = "\
+skipif fake
+statement ok
+should be skipped
+
+onlyif fake
+statement error
+should be run
+";
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_implicit_engine_label(false);
+        runner.run_script(script).unwrap_err();
+
+        // The engine name can still be added explicitly.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_implicit_engine_label(false);
+        runner.add_label("fake");
+        runner.run_script(script).unwrap();
+    }
+
+    #[test]
+    fn test_hash_threshold_reset() {
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text],
+                    rows: vec![vec!["a".to_string()], vec!["b".to_string()]],
+                })
+            }
+        }
+
+        let unhashed = "\
+query T
+select
+----
+a
+b
+";
+        let hashed = "\
+query T
+select
+----
+2 values hashing to dd8c6a395b5dd36c56d23275028f526c
+";
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script(&format!("hash-threshold 1\n\n{hashed}"))
+            .unwrap();
+        // The threshold set by the previous script doesn't leak into the next one.
+        runner.run_script(unhashed).unwrap();
+        runner
+            .run_script(&format!("control hash-threshold 1\n\n{hashed}"))
+            .unwrap();
+        runner.run_script(unhashed).unwrap();
+
+        // The threshold set on the runner is kept across scripts.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_hash_threshold(1);
+        runner.run_script(hashed).unwrap();
+        runner
+            .run_script(&format!("hash-threshold 0\n\n{unhashed}"))
+            .unwrap();
+        runner.run_script(hashed).unwrap();
+    }
+
+    #[test]
+    fn test_subtest_scope() {
+        /// Aborts the transaction on errors like Postgres, until rolled back to a savepoint.
+        #[derive(Default)]
+        struct FakeDB {
+            rows: Vec<String>,
+            savepoints: Vec<(String, usize)>,
+            aborted: bool,
+            supports_savepoints: bool,
+        }
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                if self.aborted {
+                    return Err(TestError("current transaction is aborted".to_string()));
+                }
+                if sql == "fail" {
+                    self.aborted = !self.savepoints.is_empty();
+                    return Err(TestError("failed".to_string()));
+                }
+                if let Some(value) = sql.strip_prefix("insert ") {
+                    self.rows.push(value.to_string());
+                    return Ok(DBOutput::StatementComplete(1));
+                }
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text],
+                    rows: self.rows.iter().map(|r| vec![r.clone()]).collect(),
+                })
+            }
+
+            fn supports_savepoints(&self) -> bool {
+                self.supports_savepoints
+            }
+
+            fn savepoint(&mut self, name: &str) -> Result<(), Self::Error> {
+                assert!(!self.aborted);
+                self.savepoints.push((name.to_string(), self.rows.len()));
+                Ok(())
+            }
+
+            fn rollback_to(&mut self, name: &str) -> Result<(), Self::Error> {
+                let (last, len) = self.savepoints.last().unwrap();
+                assert_eq!(last, name);
+                self.rows.truncate(*len);
+                self.aborted = false;
+                Ok(())
+            }
+
+            fn release(&mut self, name: &str) -> Result<(), Self::Error> {
+                assert!(!self.aborted);
+                assert_eq!(self.savepoints.pop().unwrap().0, name);
+                Ok(())
+            }
+        }
+
+        let script = "\
+statement ok
+insert a
+
+control scope subtest
+
+subtest first
+
+statement ok
+insert b
+
+statement error
+fail
+
+query T
+select
+----
+a
+b
+
+subtest second
+
+query T
+select
+----
+a
+
+statement ok
+insert c
+
+control scope file
+
+query T
+select
+----
+a
+
+statement ok
+insert d
+
+subtest third
+
+query T
+select
+----
+a
+d
+";
+
+        let mut runner = Runner::new(|| async {
+            Ok(FakeDB {
+                supports_savepoints: true,
+                ..Default::default()
+            })
+        });
+        runner.run_script(script).unwrap();
+
+        // The changes made by the last subtest are rolled back at the end of the script.
+        let mut runner = Runner::new(|| async {
+            Ok(FakeDB {
+                supports_savepoints: true,
+                ..Default::default()
+            })
+        });
+        runner
+            .run_script("control scope subtest\n\nsubtest first\n\nstatement ok\ninsert a\n")
+            .unwrap();
+        runner.run_script("query T\nselect\n----\n\n").unwrap();
+
+        // The subtest is rolled back even if a record fails.
+        runner
+            .run_script("control scope subtest\n\nsubtest first\n\nstatement ok\ninsert a\n\nstatement ok\nfail\n")
+            .unwrap_err();
+        runner.run_script("query T\nselect\n----\n\n").unwrap();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB::default()) });
+        let err = runner.run_script(script).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            TestErrorKind::SavepointUnsupported { .. }
+        ));
+    }
+
+    #[test]
+    fn test_scrubbers() {
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text, DefaultColumnType::Text],
+                    rows: vec![vec![
+                        "created".to_string(),
+                        "2024-01-02T03:04:05.678Z".to_string(),
+                    ]],
+                })
+            }
+        }
+
+        let script = "\
+query TT
+select
+----
+created 2023-12-31T23:59:59Z
+";
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap_err();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_scrubbers(vec![(
+            Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?Z").unwrap(),
+            "<TS>".to_string(),
+        )]);
+        runner.run_script(script).unwrap();
+        runner
+            .run_script(&script.replace("2023-12-31T23:59:59Z", "<TS>"))
+            .unwrap();
+
+        // `--override` writes the scrubbed values.
+        let record = parse::<DefaultColumnType>(script)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let record = runner.scrub_expected(record);
+        let output = block_on(runner.apply_record(record.clone()));
+        let updated = update_record_with_output(
+            &record,
+            &output,
+            " ",
+            default_validator,
+            default_normalizer,
+            default_column_validator,
+        )
+        .unwrap();
+        assert_eq!(
+            updated.to_string(),
+            "query TT\nselect\n----\ncreated <TS>\n"
+        );
+    }
+
+    #[test]
+    fn test_error_transformer() {
+        /// Fails with a volatile pointer in the message.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(TestError("invalid page at 0x7ffd5e8a1c20".to_string()))
+            }
+        }
+
+        fn scrub_pointers(err: &str) -> String {
+            Regex::new(r"0x[0-9a-f]+")
+                .unwrap()
+                .replace_all(err, "<ptr>")
+                .into_owned()
+        }
+
+        let script = "statement error invalid page at <ptr>$\nselect\n";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap_err();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_error_transformer(scrub_pointers);
+        runner.run_script(script).unwrap();
+
+        // `--override` writes the transformed message.
+        let record = parse::<DefaultColumnType>("statement ok\nselect\n")
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let output = block_on(runner.apply_record(record.clone()));
+        let updated = update_record_with_output(
+            &record,
+            &output,
+            " ",
+            default_validator,
+            default_normalizer,
+            default_column_validator,
+        )
+        .unwrap();
+        assert_eq!(
+            updated.to_string(),
+            "statement error TestError: invalid page at <ptr>\nselect\n"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_state() {
+        /// Returns rows in descending order.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: vec![vec!["2".to_string()], vec!["1".to_string()]],
+                })
+            }
+        }
+
+        let sorted = "control sortmode rowsort\n\nquery I\nselect\n----\n1\n2\n";
+        let unsorted = "query I\nselect\n----\n2\n1\n";
+
+        // The sort mode leaks into the next script.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(sorted).unwrap();
+        runner.run_script(unsorted).unwrap_err();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        let state = runner.snapshot_state();
+        runner.run_script(sorted).unwrap();
+        runner.restore_state(state);
+        runner.run_script(unsorted).unwrap();
+    }
+
+    #[test]
+    fn test_unmet_requirements() {
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
+
+        let metadata = parse_with_metadata::<DefaultColumnType>(
+            "# requires: postgres>=14, replica\nstatement ok\nselect 1\n",
+        )
+        .unwrap()
+        .metadata;
+        let unmet =
+            |runner: &Runner<_, _>| runner.unmet_requirements(&metadata).into_iter().join(", ");
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        assert_eq!(unmet(&runner), "postgres>=14, replica");
+        runner.add_label("postgres");
+        runner.add_label("replica");
+        assert_eq!(unmet(&runner), "postgres>=14");
+        runner.add_versioned_label("postgres", "13.4");
+        assert_eq!(unmet(&runner), "postgres>=14");
+        runner.add_versioned_label("postgres", "16.1");
+        assert_eq!(unmet(&runner), "");
+    }
+
+    #[test]
+    fn test_max_diff_lines() {
+        /// Returns 1000 rows.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: (1..=1000).map(|i| vec![i.to_string()]).collect(),
+                })
+            }
+        }
+
+        let script = "query I\nselect\n----\n0\n";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_max_diff_lines(10);
+        let err = runner.run_script(script).unwrap_err();
+        let message = err.to_string();
+        // The header, 10 lines of the diff, the marker and the location.
+        assert_eq!(message.lines().count(), 3 + 10 + 1 + 1, "{message}");
+        assert!(message.contains("\n+   9\n(diff truncated, 991 more lines)\n"));
+        assert!(err
+            .display(true)
+            .to_string()
+            .contains("\n(diff truncated, 991 more lines)\n"));
+        let kind = err.kind();
+        let TestErrorKind::QueryResultMismatch { actual, .. } = &kind else {
+            panic!("expected a query result mismatch");
+        };
+        assert_eq!(actual.lines().count(), 1000);
+        // The kind is displayed in full unless limited.
+        assert_eq!(kind.to_string().lines().count(), 3 + 1001);
+        let message = kind.display(false).max_diff_lines(10).to_string();
+        assert_eq!(message.lines().count(), 3 + 10 + 1, "{message}");
+
+        // Unlimited by default.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        let message = runner.run_script(script).unwrap_err().to_string();
+        assert_eq!(message.lines().count(), 3 + 1001 + 1);
+    }
+
+    #[test]
+    fn test_await() {
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
+
+        let script = "\
+statement ok connection=tx1
+begin
+
+statement ok connection=tx1
+update t set v = 1
+
+statement ok connection=tx2
+begin
+
+control await connection=tx1 record=2
+
+skipif fake
+statement ok connection=tx2
+update t set v = 2 nowait
+
+control await connection=tx2 record=2
+";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.add_label("fake");
+        let err = runner.run_script(script).unwrap_err();
+        assert_eq!(err.location().line(), 16);
+        assert!(
+            matches!(
+                err.kind(),
+                TestErrorKind::AwaitNotReached { ref connection, expected: 2, actual: 1 }
+                    if connection == "tx2"
+            ),
+            "{err}"
+        );
+
+        // Skipped records are not counted.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script(&script.replace("skipif fake\n", ""))
+            .unwrap();
+
+        // The records run by the previous scripts are not counted.
+        let err = runner
+            .run_script("control await connection=tx1 record=1\n")
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                TestErrorKind::AwaitNotReached {
+                    expected: 1,
+                    actual: 0,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_concurrent() {
+        #[derive(Default)]
+        struct Lock {
+            holder: Option<usize>,
+            waiters: Vec<std::task::Waker>,
+            log: Vec<String>,
+        }
+
+        /// A database with a single lock, taken by `lock` and released by `commit`. `lock` waits
+        /// while another connection holds the lock.
+        struct FakeDB {
+            id: usize,
+            lock: Arc<std::sync::Mutex<Lock>>,
+        }
+
+        #[async_trait]
+        impl AsyncDB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            async fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                if sql == "lock" {
+                    future::poll_fn(|cx| {
+                        let mut lock = self.lock.lock().unwrap();
+                        match lock.holder {
+                            Some(holder) if holder != self.id => {
+                                lock.waiters.push(cx.waker().clone());
+                                std::task::Poll::Pending
+                            }
+                            _ => {
+                                lock.holder = Some(self.id);
+                                std::task::Poll::Ready(())
+                            }
+                        }
+                    })
+                    .await;
+                }
+                let mut lock = self.lock.lock().unwrap();
+                if sql == "commit" && lock.holder == Some(self.id) {
+                    lock.holder = None;
+                    lock.waiters.drain(..).for_each(|waker| waker.wake());
+                }
+                lock.log.push(format!("{}: {sql}", self.id));
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
+
+        let lock = Arc::new(std::sync::Mutex::new(Lock::default()));
+        let make_runner = || {
+            let lock = lock.clone();
+            let ids = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            Runner::new(move || {
+                let id = ids.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let lock = lock.clone();
+                async move { Ok(FakeDB { id, lock }) }
+            })
+        };
+
+        // `lock` of tx2 waits for tx1 to commit, which would block the records one by one.
+        let script = "\
+control concurrent on
+
+statement ok connection=tx1
+lock
+
+control await connection=tx1 record=1
+
+statement ok connection=tx2
+lock
+
+statement ok connection=tx1
+commit
+
+control await connection=tx2 record=1 timeout=1s
+
+statement ok connection=tx2
+commit
+
+control concurrent off
+";
+        make_runner().run_script(script).unwrap();
+        assert_eq!(
+            std::mem::take(&mut lock.lock().unwrap().log),
+            ["0: lock", "0: commit", "1: lock", "1: commit"]
+        );
+
+        // Without the commit of tx1, tx2 never gets the lock.
+        let err = make_runner()
+            .run_script(&script.replace("statement ok connection=tx1\ncommit\n", ""))
+            .unwrap_err();
+        assert_eq!(err.location().line(), 12);
+        assert!(
+            matches!(
+                err.kind(),
+                TestErrorKind::AwaitTimeout { ref connection, expected: 1, actual: 0, .. }
+                    if connection == "tx2"
+            ),
+            "{err}"
+        );
+        *lock.lock().unwrap() = Lock::default();
+
+        // The background records are checked even if not awaited.
+        let err = make_runner()
+            .run_script("control concurrent on\n\nstatement error connection=tx1\nlock\n")
+            .unwrap_err();
+        assert_eq!(err.location().line(), 3);
+        assert!(matches!(err.kind(), TestErrorKind::Ok { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_trims_trailing_space() {
+        /// The default types, plus `C` for padded `CHAR(n)` values.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        enum CharColumnType {
+            Char,
+            Other(DefaultColumnType),
+        }
+
+        impl ColumnType for CharColumnType {
+            fn from_char(value: char) -> Option<Self> {
+                match value {
+                    'C' => Some(Self::Char),
+                    _ => DefaultColumnType::from_char(value).map(Self::Other),
+                }
+            }
+
+            fn to_char(&self) -> char {
+                match self {
+                    Self::Char => 'C',
+                    Self::Other(ty) => ty.to_char(),
+                }
+            }
+
+            fn trims_trailing_space(&self) -> bool {
+                *self == Self::Char
+            }
+        }
+
+        /// Returns two padded text values.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = CharColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![CharColumnType::Other(DefaultColumnType::Text); 2],
+                    rows: vec![vec!["ab  ".to_string(), "cd  ".to_string()]],
+                })
+            }
+        }
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        // Compare the values exactly, without the trimming of the default normalizer.
+        runner.with_normalizer(|s| s.clone());
+        runner
+            .run_script("query TT\nselect\n----\nab   cd  \n")
+            .unwrap();
+        runner
+            .run_script("query CT\nselect\n----\nab cd  \n")
+            .unwrap();
+        runner
+            .run_script("query TT\nselect\n----\nab cd\n")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_maxtime() {
+        /// Takes 50ms to run any SQL.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script("statement ok maxtime 10s\ninsert into t values (1)\n")
+            .unwrap();
+        let script = "statement ok maxtime 10ms\ninsert into t values (1)\n";
+        let err = runner.run_script(script).unwrap_err();
+        assert!(
+            matches!(err.kind(), TestErrorKind::TooSlow { limit, .. } if limit == Duration::from_millis(10)),
+            "{err}"
+        );
+
+        // The bound is ignored with `--override`, and kept in the updated record.
+        let record = parse::<DefaultColumnType>(script)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let output = block_on(runner.apply_record(record.clone()));
+        let updated = update_record_with_output(
+            &record,
+            &output,
+            " ",
+            default_validator,
+            default_normalizer,
+            default_column_validator,
+        )
+        .unwrap();
+        assert_eq!(updated.to_string(), script);
+    }
+
+    #[test]
+    fn test_warnings() {
+        /// Emits a warning for each `old_` in the SQL.
+        struct FakeDB {
+            warnings: Vec<String>,
+        }
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                self.warnings = (sql.matches("old_"))
+                    .map(|_| "old_ is deprecated".to_string())
+                    .collect();
+                Ok(DBOutput::StatementComplete(0))
+            }
+
+            fn take_warnings(&mut self) -> Vec<String> {
+                std::mem::take(&mut self.warnings)
+            }
+        }
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB { warnings: vec![] }) });
+        runner
+            .run_script(
+                "statement ok warnings 2
+select old_a, old_b
+",
+            )
+            .unwrap();
+        runner
+            .run_script(
+                "statement ok warnings >0
+select old_a
+",
+            )
+            .unwrap();
+        runner
+            .run_script(
+                "statement ok warnings 0
+select a
+",
+            )
+            .unwrap();
+        // Ignored by default.
+        runner
+            .run_script(
+                "statement ok
+select old_a
+",
+            )
+            .unwrap();
+
+        let err = runner
+            .run_script(
+                "statement ok warnings 1
+select old_a, old_b
+",
+            )
+            .unwrap_err();
+        let TestErrorKind::WarningsMismatch {
+            expected, actual, ..
+        } = err.kind()
+        else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(expected, ExpectedWarnings::Exactly(1));
+        assert_eq!(actual.len(), 2);
+        assert_eq!(
+            err.to_string().lines().next().unwrap(),
+            r#"statement is expected to emit 1 warnings, but actually emitted 2: ["old_ is deprecated", "old_ is deprecated"]"#
+        );
+
+        let err = runner
+            .run_script(
+                "statement ok warnings >0
+select a
+",
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err.kind(), TestErrorKind::WarningsMismatch { .. }),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let row = vec!["  Alice ".to_string(), "a\t b".to_string()];
+        assert_eq!(normalize_cell(default_normalizer, &row[0]), "Alice");
+        assert_eq!(normalize_row(default_normalizer, &row), "Alice a b");
+        assert_eq!(
+            normalize_expected(default_normalizer, &"Alice  a b ".to_string()),
+            "Alice a b"
+        );
+
+        // A custom validator built from them behaves like the default one.
+        fn validator(normalizer: Normalizer, actual: &[Vec<String>], expected: &[String]) -> bool {
+            actual.len() == expected.len()
+                && actual.iter().zip(expected).all(|(row, expected)| {
+                    normalize_row(normalizer, row) == normalize_expected(normalizer, expected)
+                })
+        }
+        let actual = [row];
+        for expected in ["Alice a b", "Alice  a\tb", "Alice ab"] {
+            let expected = [expected.to_string()];
+            assert_eq!(
+                validator(default_normalizer, &actual, &expected),
+                default_validator(default_normalizer, &actual, &expected),
+            );
+        }
+    }
+
+    #[test]
+    fn test_sigfig_validator() {
+        let check = |sigfigs, actual: &[&str], expected: &str| {
+            let actual = vec![actual.iter().map(|s| s.to_string()).collect_vec()];
+            sigfig_validator(sigfigs)(default_normalizer, &actual, &[expected.to_string()])
+        };
+
+        // Across magnitudes.
+        assert!(check(3, &["3.14159"], "3.14"));
+        assert!(check(3, &["314159.2"], "314000"));
+        assert!(check(3, &["0.000314159"], "0.000314"));
+        assert!(check(6, &["1.23456789e20"], "123457000000000000000"));
+        assert!(check(6, &["1.23456789E-20"], "1.23457e-20"));
+        assert!(!check(6, &["1.23456789e20"], "1.23456e20"));
+        assert!(!check(3, &["3.15"], "3.14"));
+
+        // Negatives and zeros.
+        assert!(check(3, &["-2.71828"], "-2.72"));
+        assert!(!check(3, &["-2.71828"], "2.72"));
+        assert!(check(3, &["-0.0"], "0"));
+
+        // Rounding up to the next power of ten.
+        assert!(check(2, &["9.99"], "10"));
+
+        // Non-numeric values are compared as is, and the number of values must match.
+        assert!(check(3, &["abc", "1.0001"], "abc 1"));
+        assert!(!check(3, &["abc", "1.0001"], "abd 1"));
+        assert!(!check(3, &["1.0001"], "1 1"));
+        assert!(!check(3, &["NULL"], "0"));
     }
 
     #[test]
-    fn test_query_replacement() {
-        TestCase {
-            // input should be ignored
-            input: "query III\n\
-                    select * from foo;\n\
-                    ----\n\
-                    1 2",
+    fn test_sigfig() {
+        /// Returns the value in the SQL as the result.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::FloatingPoint],
+                    rows: vec![vec![sql.trim_start_matches("select ").to_string()]],
+                })
+            }
+        }
 
-            // Model a run that produced a 3,4 as output
-            record_output: query_output(
-                &[&["3", "4"]],
-                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
-            ),
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script("query R sigfig=6\nselect 0.30000000000000004\n----\n0.3\n")
+            .unwrap();
+        runner
+            .run_script("query R\nselect 0.30000000000000004\n----\n0.3\n")
+            .unwrap_err();
+        runner
+            .run_script("query R sigfig=6\nselect 0.30001\n----\n0.3\n")
+            .unwrap_err();
+
+        // The values are rounded before the configured validator.
+        runner.with_validator(|_, _, _| false);
+        runner
+            .run_script("query R sigfig=6\nselect 0.30000000000000004\n----\n0.3\n")
+            .unwrap_err();
+        // Compares the last values only.
+        runner.with_validator(|normalizer, actual, expected| {
+            let last = |s: String| s.rsplit(' ').next().unwrap().to_string();
+            actual.len() == expected.len()
+                && actual.iter().zip(expected).all(|(row, expected)| {
+                    last(normalize_row(normalizer, row))
+                        == last(normalize_expected(normalizer, expected))
+                })
+        });
+        runner
+            .run_script("query R sigfig=6\nselect x 0.30000000000000004\n----\ny 0.3\n")
+            .unwrap();
+    }
 
-            expected: Some(
-                "query I?\n\
-                 select * from foo;\n\
-                 ----\n\
-                 3 4",
-            ),
+    #[test]
+    fn test_strict_column_validator() {
+        use DefaultColumnType::*;
+
+        /// Returns a row of the types in the SQL.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let types = sql
+                    .trim_start_matches("select ")
+                    .chars()
+                    .map(|c| DefaultColumnType::from_char(c).unwrap())
+                    .collect_vec();
+                Ok(DBOutput::Rows {
+                    rows: vec![vec!["1".to_string(); types.len()]],
+                    types,
+                })
+            }
         }
-        .run()
+
+        assert!(strict_column_validator(
+            &vec![Integer, Text, Integer],
+            &vec![Integer, Any, Integer]
+        ));
+        assert!(!strict_column_validator(
+            &vec![Integer, Text, Text],
+            &vec![Integer, Any, Integer]
+        ));
+        assert!(!strict_column_validator(
+            &vec![Integer, Any],
+            &vec![Integer, Text]
+        ));
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.with_column_validator(strict_column_validator);
+        runner
+            .run_script("query I?I\nselect ITI\n----\n1 1 1\n")
+            .unwrap();
+        runner
+            .run_script("query I?I\nselect ITT\n----\n1 1 1\n")
+            .unwrap_err();
     }
 
     #[test]
-    fn test_query_replacement_no_input() {
-        TestCase {
-            // input has no query results
-            input: "query\n\
-                    select * from foo;\n\
-                    ----",
+    fn test_nocase() {
+        /// Returns mixed-case values.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text; 2],
+                    rows: vec![vec!["Id".to_string(), "INTEGER".to_string()]],
+                })
+            }
+        }
 
-            // Model a run that produced a 3,4 as output
-            record_output: query_output(
-                &[&["3", "4"]],
-                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
-            ),
+        let script = "query TT\nselect\n----\nID integer\n";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap_err();
+        runner
+            .run_script("query TT nocase\nselect\n----\nID integer\n")
+            .unwrap();
+        runner
+            .run_script("query TT nocase\nselect\n----\nID int\n")
+            .unwrap_err();
+
+        runner.with_case_insensitive(true);
+        runner.run_script(script).unwrap();
+        // The record overrides the runner in both directions.
+        runner
+            .run_script("query TT nocase=off\nselect\n----\nID integer\n")
+            .unwrap_err();
+        runner
+            .run_script("query TT nocase=off\nselect\n----\nId INTEGER\n")
+            .unwrap();
+        runner.with_case_insensitive(false);
+        runner
+            .run_script("query TT nocase\nselect\n----\nID integer\n")
+            .unwrap();
+        runner.with_case_insensitive(true);
+
+        // `--override` keeps the expected values matching case-insensitively.
+        let record = parse::<DefaultColumnType>("query TT nocase\nselect\n----\nID integer\n")
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let output = block_on(runner.apply_record(record.clone()));
+        let updated = update_record_with_output(
+            &record,
+            &output,
+            " ",
+            default_validator,
+            default_normalizer,
+            default_column_validator,
+        )
+        .unwrap();
+        assert_eq!(updated, record);
+    }
 
-            expected: Some(
-                "query I?\n\
-                 select * from foo;\n\
-                 ----\n\
-                 3 4",
-            ),
+    #[test]
+    fn test_rowsort_nulls_last() {
+        /// Returns the rows in the SQL, separated by `;`.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let rows = sql
+                    .trim_start_matches("select ")
+                    .split("; ")
+                    .map(|row| row.split(", ").map(|v| v.to_string()).collect_vec())
+                    .collect_vec();
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Any; rows[0].len()],
+                    rows,
+                })
+            }
         }
-        .run()
+
+        let sql = "select NULL, b; Apple, NULL; Apple, a; Zebra, c";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script(&format!(
+                "query TT rowsort\n{sql}\n----\nApple NULL\nApple a\nNULL b\nZebra c\n"
+            ))
+            .unwrap();
+        runner
+            .run_script(&format!(
+                "control rowsort_nulls_last on\n\n\
+                 query TT rowsort\n{sql}\n----\nApple a\nApple NULL\nZebra c\nNULL b\n"
+            ))
+            .unwrap();
     }
 
     #[test]
-    fn test_query_replacement_no_output() {
-        TestCase {
-            // input has no query results
-            input: "query III\n\
-                    select * from foo;\n\
-                    ----",
+    fn test_dump_plan() {
+        /// Fails the test if any SQL is run.
+        struct FakeDB;
 
-            // Model nothing was output
-            record_output: RecordOutput::Nothing,
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // No update
-            expected: None,
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                panic!("unexpected SQL: {sql}");
+            }
         }
-        .run()
+
+        let script = "\
+control substitution on
+
+control sortmode rowsort
+
+hash-threshold 8
+
+skipif postgres
+query I
+select 1
+----
+1
+
+onlyif postgres
+statement ok
+insert into t values (${v:1})
+
+query I nosort
+select ${v:2}
+----
+2
+
+halt
+
+statement ok
+select 3
+";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.add_label("postgres");
+        let mut dump = vec![];
+        runner
+            .dump_plan(parse::<DefaultColumnType>(script).unwrap(), &mut dump)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            "\
+control substitution on
+
+control sortmode rowsort
+
+hash-threshold 8
+
+# skipped by `skipif postgres`:
+# query I rowsort
+# select 1
+# ----
+# 1
+
+statement ok
+insert into t values (1)
+
+# hash-threshold 8
+query I nosort
+select 2
+----
+2
+
+halt
+"
+        );
     }
 
     #[test]
-    fn test_query_replacement_error() {
-        TestCase {
-            // input has no query results
-            input: "query III\n\
-                    select * from foo;\n\
-                    ----",
+    fn test_columns() {
+        /// Returns the comma-separated values in the SQL as a row.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let row = sql
+                    .trim_start_matches("select ")
+                    .split(", ")
+                    .map(|v| v.to_string())
+                    .collect_vec();
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer; row.len()],
+                    rows: vec![row],
+                })
+            }
+        }
 
-            // Model a run that produced a "MyAwesomeDB Error"
-            record_output: query_output_error("MyAwesomeDB Error"),
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script("query III cols=0,2\nselect 1, 2, 3\n----\n1 3\n")
+            .unwrap();
+        runner
+            .run_script("query III cols=2,0\nselect 1, 2, 3\n----\n3 1\n")
+            .unwrap();
+        runner
+            .run_script("query III cols=0,2\nselect 1, 2, 3\n----\n1 2\n")
+            .unwrap_err();
+        // A column may be kept more than once.
+        runner
+            .run_script("query III cols=1,1,0\nselect 1, 2, 3\n----\n2 2 1\n")
+            .unwrap();
+        // The actual rows are narrower than the type string.
+        let err = runner
+            .run_script("query III cols=0,2\nselect 1, 2\n----\n1 2\n")
+            .unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err}");
+    }
 
-            expected: Some(
-                "query error TestError: MyAwesomeDB Error\n\
-                 select * from foo;\n",
-            ),
+    #[test]
+    fn test_multiple_result_sets() {
+        /// Returns a result set for each `;`-separated statement, with the statement as its value.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::MultipleResultSets(
+                    sql.split("; ")
+                        .map(|stmt| match stmt.strip_prefix("insert ") {
+                            Some(count) => DBOutput::StatementComplete(count.parse().unwrap()),
+                            None => DBOutput::Rows {
+                                types: vec![DefaultColumnType::Text],
+                                rows: vec![vec![stmt.to_string()]],
+                            },
+                        })
+                        .collect(),
+                ))
+            }
         }
-        .run()
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script("query T\nselect 1; select 2\n----\nselect 2\n")
+            .unwrap();
+        runner
+            .run_script("query T resultset=0\nselect 1; select 2\n----\nselect 1\n")
+            .unwrap();
+        runner
+            .run_script("query T resultset=1\nselect 1; select 2\n----\nselect 1\n")
+            .unwrap_err();
+        runner
+            .run_script("statement count 3\nselect 1; insert 3\n")
+            .unwrap();
+        let err = runner
+            .run_script("query T resultset=2\nselect 1; select 2\n----\nselect 1\n")
+            .unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err}");
     }
 
     #[test]
-    fn test_query_replacement_error_multiline() {
-        TestCase {
-            // input has no query results
-            input: "query III\n\
-                    select * from foo;\n\
-                    ----",
+    fn test_trailing_semicolon() {
+        /// Returns the executed SQL as the result.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text],
+                    rows: vec![vec![sql.to_string()]],
+                })
+            }
+        }
 
-            // Model a run that produced a "MyAwesomeDB Error"
-            record_output: query_output_error("MyAwesomeDB Error\n\nCaused by:\n  Inner Error"),
+        let check = |mode, sql: &str, executed: &str| {
+            let mut runner = Runner::new(|| async { Ok(FakeDB) });
+            runner.with_trailing_semicolon(mode);
+            runner
+                .run_script(&format!("query T\n{sql}\n----\n{executed}\n"))
+                .unwrap();
+        };
 
-            expected: Some(
-                "query error
-select * from foo;
-----
-TestError: MyAwesomeDB Error
+        check(TrailingSemicolon::AsIs, "select 1;", "select 1;");
+        check(TrailingSemicolon::AsIs, "select 1", "select 1");
 
-Caused by:
-  Inner Error",
-            ),
-        }
-        .run()
+        check(TrailingSemicolon::Strip, "select 1;", "select 1");
+        check(TrailingSemicolon::Strip, "select 1", "select 1");
+        check(TrailingSemicolon::Strip, "select 1;;", "select 1;");
+        check(
+            TrailingSemicolon::Strip,
+            "select 1; select 2;",
+            "select 1; select 2",
+        );
+
+        check(TrailingSemicolon::Require, "select 1", "select 1;");
+        check(TrailingSemicolon::Require, "select 1;", "select 1;");
+        check(
+            TrailingSemicolon::Require,
+            "select 1; select 2",
+            "select 1; select 2;",
+        );
     }
 
     #[test]
-    fn test_statement_query_output() {
-        TestCase {
-            // input has no query results
-            input: "statement ok\n\
-                    create table foo;",
-
-            // Model a run that produced a 3,4 as output
-            record_output: query_output(
-                &[&["3", "4"]],
-                vec![DefaultColumnType::Integer, DefaultColumnType::Any],
-            ),
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("create t;\n  insert 1 ;\n;select 2"),
+            vec![(0, "create t;"), (12, "insert 1 ;"), (24, "select 2")]
+        );
+        // Semicolons in quotes, dollar quotes and comments.
+        let sql =
+            "select ';', \"a;b\", 'it''s;'; -- no;\nselect $$;$$, $f$ $$; $f$ /* ; */;\nselect $1;";
+        assert_eq!(
+            split_statements(sql)
+                .into_iter()
+                .map(|(_, s)| s)
+                .collect_vec(),
+            vec![
+                "select ';', \"a;b\", 'it''s;';",
+                "-- no;\nselect $$;$$, $f$ $$; $f$ /* ; */;",
+                "select $1;",
+            ]
+        );
+    }
 
-            expected: Some(
-                "statement ok\n\
-                 create table foo;",
-            ),
+    #[test]
+    fn test_statement_batch() {
+        /// Records the executed statements, and fails those containing `fail`.
+        struct FakeDB(Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                self.0.lock().unwrap().push(sql.to_string());
+                if sql.contains("fail") {
+                    return Err(TestError("failed".to_string()));
+                }
+                Ok(DBOutput::StatementComplete(1))
+            }
         }
-        .run()
+
+        let executed = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut runner = Runner::new({
+            let executed = executed.clone();
+            move || {
+                let executed = executed.clone();
+                async move { Ok(FakeDB(executed)) }
+            }
+        });
+        runner
+            .run_script("statement batch\ninsert 1;\ninsert ';';\n")
+            .unwrap();
+        assert_eq!(
+            std::mem::take(&mut *executed.lock().unwrap()),
+            vec!["insert 1;", "insert ';';"]
+        );
+
+        let err = runner
+            .run_script("statement batch\ninsert 1;\nfail;\ninsert 3;\n")
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("error in statement 2 of the batch at offset 10: TestError: failed"),
+            "{err}"
+        );
+        // The statements after the failing one are not run.
+        assert_eq!(*executed.lock().unwrap(), vec!["insert 1;", "fail;"]);
     }
 
     #[test]
-    fn test_query_statement_output() {
-        TestCase {
-            // input has no query results
-            input: "query III\n\
-                    select * from foo;\n\
-                    ----",
+    fn test_debug_error() {
+        /// An error with a generic message, whose details are in the source.
+        #[derive(Debug)]
+        struct OuterError(TestError);
+
+        impl std::error::Error for OuterError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
 
-            // Model a run that produced a statement output
-            record_output: statement_output(3),
+        impl std::fmt::Display for OuterError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "db error")
+            }
+        }
 
-            expected: Some(
-                "statement count 3\n\
-                 select * from foo;",
-            ),
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = OuterError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(OuterError(TestError("connection reset".to_string())))
+            }
         }
-        .run()
+
+        let run = |script: &str| {
+            let mut runner = Runner::new(|| async { Ok(FakeDB) });
+            runner.run_script(script)
+        };
+
+        run("statement error db error\nselect\n").unwrap();
+        run("statement error connection reset\nselect\n").unwrap_err();
+        // Matched against the chain of sources.
+        run("statement error debug:^db error: TestError: connection reset$\nselect\n").unwrap();
+        // Matched against the `Debug` form.
+        run("statement error debug:OuterError\\(TestError\\(\"connection\nselect\n").unwrap();
+        run("query error debug:connection reset\nselect\n").unwrap();
+        run("query error debug:timeout\nselect\n").unwrap_err();
     }
 
     #[test]
-    fn test_statement_output() {
-        TestCase {
-            // statement that has no output
-            input: "statement ok\n\
-                    insert into foo values(2);",
+    fn test_repeat() {
+        /// Returns the number of times the SQL has been run.
+        struct FakeDB(Arc<std::sync::Mutex<std::collections::HashMap<String, usize>>>);
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let mut counts = self.0.lock().unwrap();
+                let count = counts.entry(sql.to_string()).or_default();
+                *count += 1;
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: vec![vec![count.to_string()]],
+                })
+            }
+        }
 
-            // Model a run that produced a statement output
-            record_output: statement_output(3),
+        let counts = Arc::new(std::sync::Mutex::new(std::collections::HashMap::new()));
+        let counts_clone = counts.clone();
+        let mut runner = Runner::new(move || {
+            let counts = counts_clone.clone();
+            async move { Ok(FakeDB(counts)) }
+        });
+
+        // Only the next record is repeated.
+        runner
+            .run_script(
+                "control repeat 3\nstatement ok\ninsert\n\nstatement ok\nupdate\n\nstatement ok\ninsert\n",
+            )
+            .unwrap();
+        assert_eq!(counts.lock().unwrap()["insert"], 4);
+        assert_eq!(counts.lock().unwrap()["update"], 1);
+
+        // The output of every run is validated.
+        let err = runner
+            .run_script("control repeat 2\nquery I\nselect\n----\n1\n")
+            .unwrap_err();
+        assert!(
+            matches!(err.kind(), TestErrorKind::QueryResultMismatch { .. }),
+            "{err}"
+        );
+        assert_eq!(counts.lock().unwrap()["select"], 2);
+    }
 
-            // Note the the output does not include 3 (statement
-            // count) Rationale is if the record is statement count
-            // <n>, n will be updated to real count. If the record is
-            // statement ok (which means we don't care the number of
-            // affected rows), it won't be updated.
-            expected: Some(
-                "statement ok\n\
-                 insert into foo values(2);",
-            ),
+    #[test]
+    fn test_parallel() {
+        /// Returns the number after `select`.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: vec![vec![sql.trim_start_matches("select ").to_string()]],
+                })
+            }
         }
-        .run()
+
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connections_clone = connections.clone();
+        let mut runner = Runner::new(move || {
+            connections_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { Ok(FakeDB) }
+        });
+        runner.with_max_parallel_queries(2);
+        runner.add_label("mine");
+
+        let script = "\
+control parallel on
+
+query I
+select 1
+----
+1
+
+skipif mine
+query I
+select 2
+----
+3
+
+query I
+select 3
+----
+3
+
+statement ok
+select 4
+
+query I
+select 5
+----
+5
+";
+        runner.run_script(script).unwrap();
+        // The statement runs on the default connection, and ends the run of queries.
+        assert_eq!(
+            connections.load(std::sync::atomic::Ordering::SeqCst),
+            1 + 2 + 1
+        );
+
+        // The failure is reported at the failed query.
+        let err = runner
+            .run_script("query I\nselect 1\n----\n1\n\nquery I\nselect 2\n----\n3\n")
+            .unwrap_err();
+        assert!(
+            matches!(err.kind(), TestErrorKind::QueryResultMismatch { .. }),
+            "{err}"
+        );
+        assert_eq!(err.location().line(), 6);
+
+        // Queries run one by one after `control parallel off`.
+        connections.store(0, std::sync::atomic::Ordering::SeqCst);
+        runner
+            .run_script("control parallel off\n\nquery I\nselect 1\n----\n1\n\nquery I\nselect 2\n----\n2\n")
+            .unwrap();
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 0);
     }
 
     #[test]
-    fn test_statement_error_to_ok() {
-        TestCase {
-            // statement expected error
-            input: "statement error\n\
-                    insert into foo values(2);",
+    fn test_override_error_mode() {
+        /// Fails with a SQLSTATE.
+        struct FakeDB;
 
-            // Model a run that produced a statement output
-            record_output: statement_output(3),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            expected: Some(
-                "statement ok\n\
-                 insert into foo values(2);",
-            ),
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(TestError("relation t does not exist".to_string()))
+            }
+
+            fn error_sqlstate(_error: &Self::Error) -> Option<String> {
+                Some("42P01".to_string())
+            }
         }
-        .run()
+
+        let update = |script: &str| {
+            let mut runner = Runner::new(|| async { Ok(FakeDB) });
+            let mut updated = vec![];
+            for record in parse::<DefaultColumnType>(script).unwrap() {
+                let output = block_on(runner.apply_record(record.clone()));
+                updated.push(
+                    runner
+                        .update_record_with_error_mode(
+                            &record,
+                            &output,
+                            " ",
+                            default_validator,
+                            default_normalizer,
+                            default_column_validator,
+                        )
+                        .unwrap_or(record),
+                );
+            }
+            updated.iter().join("\n")
+        };
+
+        // A matching SQLSTATE is kept.
+        let script = "control error_mode sqlstate\n\nstatement error 42P01\nselect * from t\n";
+        assert_eq!(update(script), script);
+        let script = "control error_mode both\n\nstatement error 42P01 relation .* does not exist\nselect * from t\n";
+        assert_eq!(update(script), script);
+
+        // Otherwise, the expected error is rewritten in the form of the error mode.
+        assert_eq!(
+            update("control error_mode sqlstate\n\nstatement ok\nselect * from t\n"),
+            "control error_mode sqlstate\n\nstatement error 42P01\nselect * from t\n"
+        );
+        assert_eq!(
+            update("control error_mode both\n\nquery I\nselect * from t\n----\n1\n"),
+            "control error_mode both\n\nquery error 42P01 TestError: relation t does not exist\nselect * from t\n"
+        );
     }
 
     #[test]
-    fn test_statement_error_no_error() {
-        TestCase {
-            // statement expected error
-            input: "statement error\n\
-                    insert into foo values(2);",
+    fn test_hash_algo() {
+        struct FakeDB;
 
-            // Model a run that produced an error message
-            record_output: statement_output_error("foo"),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // Input didn't have an expected error, so output is not to expect the message, then no
-            // update
-            expected: None,
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text],
+                    rows: vec![vec!["a".to_string()], vec!["b".to_string()]],
+                })
+            }
+        }
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script(
+                "\
+control hash_algo sha256
+
+query T hashed
+select
+----
+sha256:911169ddaaf146aff539f58c26c489af3b892dff0fe283c1c264c65ae5aa59a2
+",
+            )
+            .unwrap();
+
+        // The hash written by `--override` is validated with the same algorithm.
+        for (algo, threshold) in ["md5", "sha256", "xxh3"]
+            .into_iter()
+            .cartesian_product([0, 1])
+        {
+            let hashed = if threshold == 0 { " hashed" } else { "" };
+            let script = format!(
+                "control hash_algo {algo}\n\ncontrol hash-threshold {threshold}\n\nquery T{hashed}\nselect\n----\n"
+            );
+            let mut runner = Runner::new(|| async { Ok(FakeDB) });
+            let mut updated = vec![];
+            for record in parse::<DefaultColumnType>(&script).unwrap() {
+                let output = block_on(runner.apply_record(record.clone()));
+                updated.push(
+                    update_record_with_output(
+                        &record,
+                        &output,
+                        " ",
+                        default_validator,
+                        default_normalizer,
+                        default_column_validator,
+                    )
+                    .unwrap_or(record),
+                );
+            }
+            let script = updated.iter().join("\n");
+            let hash = script.lines().last().unwrap();
+            assert_eq!(hash.contains("sha256:"), algo == "sha256", "{script}");
+            assert_eq!(hash.contains("xxh3:"), algo == "xxh3", "{script}");
+            Runner::new(|| async { Ok(FakeDB) })
+                .run_script(&script)
+                .unwrap();
+
+            // A hash of another algorithm never matches.
+            let other = if algo == "md5" { "sha256" } else { "md5" };
+            let script = script.replacen(algo, other, 1);
+            Runner::new(|| async { Ok(FakeDB) })
+                .run_script(&script)
+                .unwrap_err();
         }
-        .run()
     }
 
     #[test]
-    fn test_statement_error_new_error() {
-        TestCase {
-            // statement expected error
-            input: "statement error bar\n\
-                    insert into foo values(2);",
+    fn test_check_conditions() {
+        struct FakeDB;
 
-            // Model a run that produced an error message
-            record_output: statement_output_error("foo"),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // expect the output includes foo
-            expected: Some(
-                "statement error TestError: foo\n\
-                 insert into foo values(2);",
-            ),
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
         }
-        .run()
+
+        let records = parse::<DefaultColumnType>(
+            "\
+skipif postgre
+statement ok
+select 1
+
+onlyif mysql
+skipif replica
+statement ok
+select 2
+",
+        )
+        .unwrap();
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.add_label("replica");
+        runner.add_known_label("postgres");
+        runner.add_known_label("mysql");
+        let warnings = runner.check_conditions(&records);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].loc.line(), 2);
+        assert_eq!(
+            warnings[0].condition,
+            Condition::SkipIf {
+                label: "postgre".to_string()
+            }
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "unknown label \"postgre\" in condition at <unknown>:2"
+        );
+
+        runner.add_known_label("postgre");
+        assert!(runner.check_conditions(&records).is_empty());
     }
 
     #[test]
-    fn test_statement_error_new_error_multiline() {
-        TestCase {
-            // statement expected error
-            input: "statement error bar\n\
-                    insert into foo values(2);",
-
-            // Model a run that produced an error message
-            record_output: statement_output_error("foo\n\nCaused by:\n  Inner Error"),
+    fn test_table_normalizer() {
+        /// Returns a rendered table.
+        struct FakeDB;
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let table = "\
++----+--------+
+| id | name   |
++====+========+
+|  1 | Alice  |
+|  2 | Bob    |
++----+--------+";
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Text],
+                    rows: table.lines().map(|line| vec![line.to_string()]).collect(),
+                })
+            }
+        }
 
-            // expect the output includes foo
-            expected: Some(
-                "statement error
-insert into foo values(2);
+        // Only the column widths differ.
+        let script = "\
+query T
+explain
 ----
-TestError: foo
-
-Caused by:
-  Inner Error",
-            ),
-        }
-        .run()
++----+-------+
+| id | name  |
++====+=======+
+|  1 | Alice |
+|  2 | Bob   |
++----+-------+
+";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap_err();
+        runner.with_normalizer(table_normalizer);
+        runner.run_script(script).unwrap();
+
+        // The values and borders are still compared.
+        runner
+            .run_script(&script.replace("Bob  ", "Carol"))
+            .unwrap_err();
+        runner
+            .run_script(&script.replace("+====+", "+----+"))
+            .unwrap_err();
+
+        assert_eq!(table_normalizer(&"─────┼──── ".to_string()), "─┼─");
+        assert_eq!(table_normalizer(&" -1 ".to_string()), "-1");
+        // Values made of border characters are not borders.
+        assert_eq!(table_normalizer(&"--".to_string()), "--");
+        assert_eq!(
+            table_normalizer(&"|  -- |  == |".to_string()),
+            "| -- | == |"
+        );
+        assert_eq!(table_normalizer(&"|:---:|".to_string()), "|:-:|");
+        assert_eq!(table_normalizer(&"  ".to_string()), "");
     }
 
     #[test]
-    fn test_statement_error_ok_to_error() {
-        TestCase {
-            // statement was ok
-            input: "statement ok\n\
-                    insert into foo values(2);",
+    fn test_shared_db() {
+        /// Returns the number of SQL run, and warns with it. A `DB`, which is shared as an
+        /// `AsyncDB`.
+        #[derive(Default)]
+        struct FakeDB(usize, Vec<String>);
+
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                self.0 += 1;
+                self.1.push(self.0.to_string());
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: vec![vec![self.0.to_string()]],
+                })
+            }
 
-            // Model a run that produced an error message
-            record_output: statement_output_error("foo"),
+            fn engine_name(&self) -> &str {
+                "fake"
+            }
 
-            // expect the output includes foo
-            expected: Some(
-                "statement error TestError: foo\n\
-                 insert into foo values(2);",
-            ),
+            fn take_warnings(&mut self) -> Vec<String> {
+                std::mem::take(&mut self.1)
+            }
         }
-        .run()
+
+        let db = SharedDB::new(FakeDB::default());
+        let make = || {
+            let db = db.clone();
+            move || {
+                let db = db.clone();
+                async move { Ok(db) }
+            }
+        };
+        let mut runner1 = Runner::new(make());
+        let mut runner2 = Runner::new(make());
+        runner1.run_script("query I\nselect\n----\n1\n").unwrap();
+        runner2.run_script("query I\nselect\n----\n2\n").unwrap();
+        runner1.run_script("query I\nselect\n----\n3\n").unwrap();
+        assert_eq!(futures::executor::block_on(db.lock()).0, 3);
+
+        // The engine name is delegated, and the warnings are kept by the sharer running the SQL.
+        runner2
+            .run_script(
+                "onlyif fake\nquery I\nselect\n----\n4\n\nskipif fake\nquery I\nselect\n----\n0\n",
+            )
+            .unwrap();
+        let mut other = db.clone();
+        block_on(other.run("select")).unwrap();
+        assert_eq!(other.take_warnings(), ["5"]);
+        runner1.run_script("query I\nselect\n----\n6\n").unwrap();
+        assert!(other.take_warnings().is_empty());
     }
 
     #[test]
-    fn test_statement_error_ok_to_error_multiline() {
-        TestCase {
-            // statement was ok
-            input: "statement ok\n\
-                    insert into foo values(2);",
+    fn test_run_parallel_skipped() {
+        struct FakeDB;
 
-            // Model a run that produced an error message
-            record_output: statement_output_error("foo\n\nCaused by:\n  Inner Error"),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // expect the output includes foo
-            expected: Some(
-                "statement error
-insert into foo values(2);
-----
-TestError: foo
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
 
-Caused by:
-  Inner Error",
-            ),
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.slt", "b.slt"] {
+            let script = "skipif fake\nstatement ok\nselect 1\n\nstatement ok\nselect 2\n";
+            std::fs::write(dir.path().join(name), script).unwrap();
         }
-        .run()
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.add_label("fake");
+        runner.with_skip_diagnostics(true);
+        runner
+            .run_parallel(
+                &format!("{}/*.slt", dir.path().display()),
+                vec!["localhost".to_string()],
+                |_, _| async { FakeDB },
+                2,
+            )
+            .unwrap();
+        let skipped = runner
+            .take_skipped_records()
+            .into_iter()
+            .map(|record| record.loc.to_string())
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            skipped,
+            [
+                format!("{}:2", dir.path().join("a.slt").display()),
+                format!("{}:2", dir.path().join("b.slt").display()),
+            ]
+        );
     }
 
     #[test]
-    fn test_statement_error_special_chars() {
-        TestCase {
-            // statement expected error
-            input: "statement error tbd\n\
-                    inser into foo values(2);",
-
-            // Model a run that produced an error message that contains regex special characters
-            record_output: statement_output_error("The operation (inser) is not supported. Did you mean [insert]?"),
+    fn test_shared_async_db() {
+        /// Counts the SQL run, yielding in between reading and writing the count, so that the
+        /// runs of the sharers interleave unless they wait for each other.
+        struct FakeDB(usize);
+
+        #[async_trait]
+        impl AsyncDB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            async fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                let count = self.0;
+                let mut yielded = false;
+                future::poll_fn(|cx| {
+                    if yielded {
+                        return std::task::Poll::Ready(());
+                    }
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    std::task::Poll::Pending
+                })
+                .await;
+                self.0 = count + 1;
+                Ok(DBOutput::StatementComplete(0))
+            }
 
-            // expect the output includes foo
-            expected: Some(
-                "statement error TestError: The operation \\(inser\\) is not supported\\. Did you mean \\[insert\\]\\?\n\
-                 inser into foo values(2);",
-            ),
+            fn engine_name(&self) -> &str {
+                "fake"
+            }
         }
-            .run()
+
+        let db = SharedDB::new(FakeDB(0));
+        let make = || {
+            let db = db.clone();
+            move || {
+                let db = db.clone();
+                async move { Ok(db) }
+            }
+        };
+        let mut runner1 = Runner::new(make());
+        let mut runner2 = Runner::new(make());
+        let script = "onlyif fake\nstatement ok\nupdate\n\n".repeat(10);
+        let (result1, result2) = block_on(future::join(
+            runner1.run_script_async(&script),
+            runner2.run_script_async(&script),
+        ));
+        result1.unwrap();
+        result2.unwrap();
+        assert_eq!(block_on(db.lock()).0, 20);
     }
 
     #[test]
-    fn test_statement_keep_error_regex_when_matches() {
-        TestCase {
-            // statement expected error
-            input: "statement error TestError: The operation \\([a-z]+\\) is not supported.*\n\
-                    inser into foo values(2);",
+    fn test_connect_fail() {
+        struct FakeDB;
 
-            // Model a run that produced an error message that contains regex special characters
-            record_output: statement_output_error(
-                "The operation (inser) is not supported. Did you mean [insert]?",
-            ),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // no update expected
-            expected: None,
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
+        }
+
+        // Failing to connect fails the record, even if it expects an error.
+        let mut runner =
+            Runner::new(|| async { Err::<FakeDB, _>(TestError("connection refused".to_string())) });
+        for script in [
+            "statement ok\nselect 1\n",
+            "query error refused\nselect 1\n",
+        ] {
+            let err = runner.run_script(script).unwrap_err();
+            assert!(
+                matches!(err.kind(), TestErrorKind::ConnectFail { ref err, .. } if err.to_string() == "TestError: connection refused"),
+                "{err}"
+            );
         }
-        .run()
     }
 
     #[test]
-    fn test_query_error_special_chars() {
-        TestCase {
-            // statement expected error
-            input: "query error tbd\n\
-                    selec *;",
+    fn test_ping_connections() {
+        /// Fails the ping if it's the first connection made.
+        struct FakeDB {
+            id: usize,
+        }
 
-            // Model a run that produced an error message that contains regex special characters
-            record_output: query_output_error("The operation (selec) is not supported. Did you mean [select]?"),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // expect the output includes foo
-            expected: Some(
-                "query error TestError: The operation \\(selec\\) is not supported\\. Did you mean \\[select\\]\\?\n\
-                 selec *;",
-            ),
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::Rows {
+                    types: vec![DefaultColumnType::Integer],
+                    rows: vec![vec![self.id.to_string()]],
+                })
+            }
+
+            fn ping(&mut self) -> Result<(), Self::Error> {
+                if self.id == 1 {
+                    return Err(TestError("connection closed".to_string()));
+                }
+                Ok(())
+            }
         }
-            .run()
+
+        let made = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut runner = Runner::new({
+            let made = made.clone();
+            move || {
+                let id = made.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                async move { Ok(FakeDB { id }) }
+            }
+        });
+        runner.run_script("query I\nselect\n----\n1\n").unwrap();
+
+        // The dead connection is dropped and made again.
+        futures::executor::block_on(runner.ping_connections());
+        runner.run_script("query I\nselect\n----\n2\n").unwrap();
+
+        // The live connection is kept.
+        futures::executor::block_on(runner.ping_connections());
+        runner.run_script("query I\nselect\n----\n2\n").unwrap();
+        assert_eq!(made.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(runner.connection_count(), 2);
     }
 
     #[test]
-    fn test_query_error_special_chars_when_matches() {
-        TestCase {
-            // statement expected error
-            input: "query error TestError: The operation \\([a-z]+\\) is not supported.*\n\
-                    selec *;",
+    fn test_connection_count() {
+        struct FakeDB;
 
-            // Model a run that produced an error message that contains regex special characters
-            record_output: query_output_error(
-                "The operation (selec) is not supported. Did you mean [select]?",
-            ),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
 
-            // no update expected
-            expected: None,
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
         }
-        .run()
-    }
 
-    #[derive(Debug)]
-    struct TestCase<'a> {
-        input: &'a str,
-        record_output: RecordOutput<DefaultColumnType>,
-        expected: Option<&'a str>,
+        let script = "\
+statement ok
+select 1
+
+connection a
+statement ok
+select 1
+
+connection b
+statement ok
+select 1
+
+connection a
+statement ok
+select 1
+";
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        assert_eq!(runner.connection_count(), 0);
+        runner.run_script(script).unwrap();
+        assert_eq!(runner.connection_count(), 3);
+
+        // The connections are kept across scripts.
+        runner.run_script(script).unwrap();
+        assert_eq!(runner.connection_count(), 3);
     }
 
-    impl TestCase<'_> {
-        #[track_caller]
-        fn run(self) {
-            let Self {
-                input,
-                record_output,
-                expected,
-            } = self;
-            println!("TestCase");
-            println!("**input:\n{input}\n");
-            println!("**record_output:\n{record_output:#?}\n");
-            println!("**expected:\n{}\n", expected.unwrap_or(""));
-            let input = parse_to_record(input);
-            let expected = expected.map(parse_to_record);
-            let output = update_record_with_output(
-                &input,
-                &record_output,
-                " ",
-                default_validator,
-                default_normalizer,
-                strict_column_validator,
-            );
+    #[test]
+    fn test_forbid_error() {
+        /// Fails with the SQL as the error message.
+        struct FakeDB;
 
-            assert_eq!(
-                &output,
-                &expected,
-                "\n\noutput:\n\n{}\n\nexpected:\n\n{}",
-                output
-                    .as_ref()
-                    .map(|r| r.to_string())
-                    .unwrap_or_else(|| "None".into()),
-                expected
-                    .as_ref()
-                    .map(|r| r.to_string())
-                    .unwrap_or_else(|| "None".into()),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(TestError(sql.to_string()))
+            }
+        }
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner
+            .run_script("statement error\ninternal error: panicked\n")
+            .unwrap();
+
+        runner
+            .run_script("control forbid_error (?i)internal error\n")
+            .unwrap();
+        runner
+            .run_script("statement error\ndivision by zero\n")
+            .unwrap();
+        for script in [
+            "statement error\ninternal error: panicked\n",
+            "statement error panicked\nInternal Error: panicked\n",
+            "query error\ninternal error: panicked\n",
+        ] {
+            let err = runner.run_script(script).unwrap_err();
+            assert!(
+                matches!(err.kind(), TestErrorKind::ForbiddenError { .. }),
+                "{err}"
             );
         }
-    }
 
-    fn parse_to_record(s: &str) -> Record<DefaultColumnType> {
-        let mut records = parse(s).unwrap();
-        assert_eq!(records.len(), 1);
-        records.pop().unwrap()
+        runner.run_script("control forbid_error off\n").unwrap();
+        runner
+            .run_script("statement error\ninternal error: panicked\n")
+            .unwrap();
     }
 
-    /// Returns a RecordOutput that models the successful execution of a query
-    fn query_output(
-        rows: &[&[&str]],
-        types: Vec<DefaultColumnType>,
-    ) -> RecordOutput<DefaultColumnType> {
-        let rows = rows
-            .iter()
-            .map(|cols| cols.iter().map(|c| c.to_string()).collect::<Vec<_>>())
-            .collect::<Vec<_>>();
+    #[test]
+    fn test_require_error_message() {
+        /// Fails with the SQL as the error message.
+        struct FakeDB;
+
+        #[derive(Debug)]
+        struct RawError(String);
+        impl std::error::Error for RawError {}
+        impl std::fmt::Display for RawError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
 
-        RecordOutput::Query {
-            types,
-            rows,
-            error: None,
+        impl crate::DB for FakeDB {
+            type Error = RawError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Err(RawError(sql.trim_start_matches("fail").trim().to_string()))
+            }
         }
-    }
 
-    /// Returns a RecordOutput that models the error of a query
-    fn query_output_error(error_message: &str) -> RecordOutput<DefaultColumnType> {
-        RecordOutput::Query {
-            types: vec![],
-            rows: vec![],
-            error: Some(Arc::new(TestError(error_message.to_string()))),
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script("statement error\nfail\n").unwrap();
+
+        runner.with_require_error_message(true);
+        runner
+            .run_script("statement error\nfail division by zero\n")
+            .unwrap();
+        for script in ["statement error\nfail\n", "query error\nfail\n"] {
+            let err = runner.run_script(script).unwrap_err();
+            assert!(
+                matches!(err.kind(), TestErrorKind::EmptyErrorMessage { .. }),
+                "{err}"
+            );
         }
+        // Unexpected errors are reported as usual.
+        let err = runner.run_script("statement ok\nfail\n").unwrap_err();
+        assert!(matches!(err.kind(), TestErrorKind::Fail { .. }), "{err}");
     }
 
-    fn statement_output(count: u64) -> RecordOutput<DefaultColumnType> {
-        RecordOutput::Statement { count, error: None }
-    }
+    #[test]
+    fn test_skip_diagnostics() {
+        struct FakeDB;
 
-    /// RecordOutput that models a statement with error
-    fn statement_output_error(error_message: &str) -> RecordOutput<DefaultColumnType> {
-        RecordOutput::Statement {
-            count: 0,
-            error: Some(Arc::new(TestError(error_message.to_string()))),
+        impl crate::DB for FakeDB {
+            type Error = TestError;
+            type ColumnType = DefaultColumnType;
+
+            fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, Self::Error> {
+                Ok(DBOutput::StatementComplete(0))
+            }
+
+            fn engine_name(&self) -> &str {
+                "fake"
+            }
         }
+
+        let script = "\
+skipif fake
+statement ok
+skipped by skipif
+
+onlyif other
+statement ok
+skipped by onlyif
+
+onlyif fake
+statement ok
+not skipped
+";
+
+        // Not collected by default.
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.run_script(script).unwrap();
+        assert!(runner.take_skipped_records().is_empty());
+
+        let mut runner = Runner::new(|| async { Ok(FakeDB) });
+        runner.add_label("mine");
+        runner.with_skip_diagnostics(true);
+        runner.run_script(script).unwrap();
+        let skipped = runner.take_skipped_records();
+        assert_eq!(
+            skipped
+                .iter()
+                .map(|s| (s.loc.line(), s.condition.clone(), s.labels.clone()))
+                .collect_vec(),
+            vec![
+                (
+                    2,
+                    Condition::SkipIf {
+                        label: "fake".to_string()
+                    },
+                    vec!["fake".to_string(), "mine".to_string()]
+                ),
+                (
+                    6,
+                    Condition::OnlyIf {
+                        label: "other".to_string()
+                    },
+                    vec!["fake".to_string(), "mine".to_string()]
+                ),
+            ]
+        );
+        assert!(runner.take_skipped_records().is_empty());
     }
 
     #[derive(Debug)]
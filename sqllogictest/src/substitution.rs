@@ -15,14 +15,29 @@ pub(crate) struct Substitution {
 #[error("substitution failed: {0}")]
 pub(crate) struct SubstError(subst::Error);
 
+/// Variables bound by the arguments of `include` records, from the outermost to the innermost
+/// include.
+pub(crate) type Variables = [Vec<(String, String)>];
+
 impl Substitution {
-    pub fn substitute(&self, input: &str, subst_env_vars: bool) -> Result<String, SubstError> {
+    /// If `subst_env_vars`, the `variables` shadow the special variables and environment
+    /// variables, and the variables of inner includes shadow those of outer ones.
+    pub fn substitute(
+        &self,
+        input: &str,
+        subst_env_vars: bool,
+        variables: &Variables,
+    ) -> Result<String, SubstError> {
         if !subst_env_vars {
             Ok(input
                 .replace("$__TEST_DIR__", &self.test_dir())
                 .replace("$__NOW__", &self.now()))
         } else {
-            subst::substitute(input, self).map_err(SubstError)
+            let map = WithVariables {
+                substitution: self,
+                variables,
+            };
+            subst::substitute(input, &map).map_err(SubstError)
         }
     }
 
@@ -53,3 +68,22 @@ impl<'a> subst::VariableMap<'a> for Substitution {
         }
     }
 }
+
+struct WithVariables<'s> {
+    substitution: &'s Substitution,
+    variables: &'s Variables,
+}
+
+impl<'a> subst::VariableMap<'a> for WithVariables<'_> {
+    type Value = String;
+
+    fn get(&'a self, key: &str) -> Option<Self::Value> {
+        self.variables
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+            .or_else(|| self.substitution.get(key))
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqllogictest::{ConnectRetry, DBOutput, DefaultColumnType};
+
+pub struct FakeDB;
+
+#[derive(Debug)]
+pub enum FakeDBError {
+    Connect(std::io::Error),
+    Auth,
+}
+
+impl std::fmt::Display for FakeDBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FakeDBError::Connect(_) => write!(f, "failed to connect"),
+            FakeDBError::Auth => write!(f, "password authentication failed"),
+        }
+    }
+}
+
+impl std::error::Error for FakeDBError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FakeDBError::Connect(e) => Some(e),
+            FakeDBError::Auth => None,
+        }
+    }
+}
+
+impl sqllogictest::DB for FakeDB {
+    type Error = FakeDBError;
+    type ColumnType = DefaultColumnType;
+
+    fn run(&mut self, _sql: &str) -> Result<DBOutput<Self::ColumnType>, FakeDBError> {
+        Ok(DBOutput::StatementComplete(0))
+    }
+}
+
+/// Returns a connection maker which fails with `error` for the first `failures` attempts, and the
+/// number of attempts made so far.
+fn flaky_maker(
+    failures: usize,
+    error: fn() -> FakeDBError,
+) -> (
+    impl FnMut() -> std::future::Ready<Result<FakeDB, FakeDBError>>,
+    Arc<AtomicUsize>,
+) {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = attempts.clone();
+    let make = move || {
+        let attempt = counter.fetch_add(1, Ordering::SeqCst);
+        std::future::ready(if attempt < failures {
+            Err(error())
+        } else {
+            Ok(FakeDB)
+        })
+    };
+    (make, attempts)
+}
+
+fn connection_refused() -> FakeDBError {
+    FakeDBError::Connect(std::io::ErrorKind::ConnectionRefused.into())
+}
+
+fn retry(retries: usize) -> ConnectRetry {
+    ConnectRetry {
+        initial_backoff: Duration::from_millis(1),
+        ..ConnectRetry::new(retries, Duration::from_secs(10))
+    }
+}
+
+#[test]
+fn test_retry_until_connected() {
+    let (make, attempts) = flaky_maker(3, connection_refused);
+    let mut tester = sqllogictest::Runner::new(make);
+    tester.with_connect_retry(retry(5));
+
+    tester
+        .run_script("statement ok\ncreate table t;\n")
+        .unwrap();
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_retry_exhausted() {
+    let (make, attempts) = flaky_maker(3, connection_refused);
+    let mut tester = sqllogictest::Runner::new(make);
+    tester.with_connect_retry(retry(2));
+
+    let err = tester
+        .run_script("statement ok\ncreate table t;\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("failed to connect"), "{err}");
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_no_retry_on_auth_error() {
+    let (make, attempts) = flaky_maker(1, || FakeDBError::Auth);
+    let mut tester = sqllogictest::Runner::new(make);
+    tester.with_connect_retry(retry(5));
+
+    let err = tester
+        .run_script("statement ok\ncreate table t;\n")
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("password authentication failed"),
+        "{err}"
+    );
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
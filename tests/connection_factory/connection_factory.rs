@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use sqllogictest::{DBOutput, DefaultColumnType};
+
+/// A fake database whose primary and replicas share the same rows, but only the primary accepts
+/// writes.
+pub struct FakeDB {
+    rows: Arc<Mutex<Vec<String>>>,
+    read_only: bool,
+}
+
+#[derive(Debug)]
+pub struct FakeDBError(String);
+
+impl std::fmt::Display for FakeDBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FakeDBError {}
+
+impl sqllogictest::DB for FakeDB {
+    type Error = FakeDBError;
+    type ColumnType = DefaultColumnType;
+
+    fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, FakeDBError> {
+        if let Some(value) = sql.strip_prefix("insert ") {
+            if self.read_only {
+                return Err(FakeDBError(
+                    "cannot execute INSERT in a read-only transaction".to_string(),
+                ));
+            }
+            self.rows.lock().unwrap().push(value.to_string());
+            return Ok(DBOutput::StatementComplete(1));
+        }
+        if sql == "select" {
+            return Ok(DBOutput::Rows {
+                types: vec![DefaultColumnType::Text],
+                rows: (self.rows.lock().unwrap().iter())
+                    .map(|row| vec![row.clone()])
+                    .collect(),
+            });
+        }
+        Err(FakeDBError(format!("unknown SQL: {sql}")))
+    }
+}
+
+#[test]
+fn test_replica() {
+    let rows = Arc::new(Mutex::new(vec![]));
+
+    let primary = rows.clone();
+    let mut tester = sqllogictest::Runner::new(move || {
+        let rows = primary.clone();
+        async move {
+            Ok(FakeDB {
+                rows,
+                read_only: false,
+            })
+        }
+    });
+    let replica = rows.clone();
+    tester.add_connection_factory("replica", move || {
+        let rows = replica.clone();
+        async move {
+            Ok(FakeDB {
+                rows,
+                read_only: true,
+            })
+        }
+    });
+
+    tester
+        .run_file("./connection_factory/connection_factory.slt")
+        .unwrap();
+}
@@ -38,6 +38,17 @@ impl sqllogictest::DB for FakeDB {
                 ],
             });
         }
+        if sql == "select * from example_spaces" {
+            // Same as `example_basic` after normalization.
+            return Ok(DBOutput::Rows {
+                types: vec![DefaultColumnType::Text],
+                rows: vec![
+                    vec!["Eve  ".to_string()],
+                    vec![" Bob".to_string()],
+                    vec!["Alice".to_string()],
+                ],
+            });
+        }
         if sql == "select * from example_sort" {
             // Even if the order is not the same as `slt` file, sqllogictest will sort them before
             // comparing.
@@ -82,4 +93,12 @@ impl sqllogictest::DB for FakeDB {
         }
         Err(FakeDBError("Hey you got FakeDBError!".to_string()))
     }
+
+    fn error_sqlstate(error: &FakeDBError) -> Option<String> {
+        // feature_not_supported
+        error
+            .0
+            .contains("not supported")
+            .then(|| "0A000".to_string())
+    }
 }
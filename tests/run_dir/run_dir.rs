@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use sqllogictest::{DBOutput, DefaultColumnType, FileStatus};
+
+/// A fake database keeping the inserted rows in the connection.
+pub struct FakeDB {
+    rows: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct FakeDBError(String);
+
+impl std::fmt::Display for FakeDBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FakeDBError {}
+
+impl sqllogictest::DB for FakeDB {
+    type Error = FakeDBError;
+    type ColumnType = DefaultColumnType;
+
+    fn run(&mut self, sql: &str) -> Result<DBOutput<Self::ColumnType>, FakeDBError> {
+        if let Some(value) = sql.strip_prefix("insert ") {
+            self.rows.push(value.to_string());
+            return Ok(DBOutput::StatementComplete(1));
+        }
+        if sql == "select" {
+            return Ok(DBOutput::Rows {
+                types: vec![DefaultColumnType::Integer],
+                rows: self.rows.iter().map(|row| vec![row.clone()]).collect(),
+            });
+        }
+        Err(FakeDBError(format!("unknown SQL: {sql}")))
+    }
+
+    fn engine_name(&self) -> &str {
+        "fake"
+    }
+}
+
+#[test]
+fn test_run_dir() {
+    let mut tester = sqllogictest::Runner::new(|| async { Ok(FakeDB { rows: vec![] }) });
+    let report = tester.run_dir("./run_dir", "*.slt");
+
+    let files: Vec<_> = (report.files.iter())
+        .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(
+        files,
+        ["fail.slt", "pass.slt", "requires.slt", "skipif.slt"]
+    );
+    assert!(!report.is_ok());
+
+    let failed = report.failed();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].path, Path::new("run_dir/fail.slt"));
+
+    // `skipif.slt` sees no rows inserted by `pass.slt`, as each file has new connections.
+    assert_eq!(report.passed().len(), 2);
+    assert_eq!(report.files[3].skipped_records.len(), 1);
+
+    let skipped = report.skipped();
+    assert_eq!(skipped.len(), 1);
+    let FileStatus::Skipped(unmet) = &skipped[0].status else {
+        unreachable!()
+    };
+    assert_eq!(unmet[0].to_string(), "replica");
+}
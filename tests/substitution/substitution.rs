@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 use rusty_fork::rusty_fork_test;
 use sqllogictest::{DBOutput, DefaultColumnType};
 
@@ -62,4 +64,42 @@ rusty_fork_test! {
 
         tester.run_file("./substitution/basic.slt").unwrap();
     }
+
+    #[test]
+    fn test_echo() {
+        std::env::set_var("MY_USERNAME", "sqllogictest");
+
+        let echoed = Arc::new(Mutex::new(vec![]));
+        let mut tester = sqllogictest::Runner::new(|| async { Ok(FakeDB) });
+        let echoed_clone = echoed.clone();
+        tester.with_echo(move |text| echoed_clone.lock().unwrap().push(text.to_string()));
+
+        tester
+            .run_script(
+                "echo user:  $MY_USERNAME\n\ncontrol substitution on\n\necho user:  $MY_USERNAME\n",
+            )
+            .unwrap();
+        assert_eq!(
+            *echoed.lock().unwrap(),
+            ["user:  $MY_USERNAME", "user:  sqllogictest"]
+        );
+
+        tester.run_script("echo ${MY_INEXISTENT}\n").unwrap_err();
+    }
+
+    #[test]
+    fn test_include_args() {
+        std::env::set_var("MY_USERNAME", "sqllogictest");
+
+        let echoed = Arc::new(Mutex::new(vec![]));
+        let mut tester = sqllogictest::Runner::new(|| async { Ok(FakeDB) });
+        let echoed_clone = echoed.clone();
+        tester.with_echo(move |text| echoed_clone.lock().unwrap().push(text.to_string()));
+
+        tester.run_file("./substitution/include_args.slt").unwrap();
+        assert_eq!(
+            *echoed.lock().unwrap(),
+            ["hello, alice", "hi, bob", "unbound, sqllogictest"]
+        );
+    }
 }